@@ -0,0 +1,92 @@
+//! Compares checking every collider with querying the spatial grid, on a battle field fully
+//! covered by quarter bricks with many bullets flying.
+//!
+//! Run with `cargo bench --bench collision`.
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use battle_city::{
+    bullet::BULLET_SIZE,
+    collision::Collider,
+    consts::HALF_BLOCK_WIDTH,
+    spatial::SpatialGrid,
+    utils::{grid2translation, GRID_SIZE},
+};
+use bevy::{
+    math::{Vec2, Vec3},
+    prelude::Entity,
+    sprite::collide_aabb::collide,
+};
+
+const BULLETS: u32 = 200;
+const TICKS: u32 = 100;
+
+fn main() {
+    let brick_size = Vec2::splat(HALF_BLOCK_WIDTH);
+    let mut bricks = Vec::new();
+    let mut grid = SpatialGrid::new();
+    for y in 0..GRID_SIZE as i32 {
+        for x in 0..GRID_SIZE as i32 {
+            let entity = Entity::from_raw(bricks.len() as u32);
+            let translation = grid2translation(x, y, 0.);
+            grid.insert(entity, Collider::Brick, translation, brick_size);
+            bricks.push((entity, translation));
+        }
+    }
+    let bullets: Vec<Vec3> = (0..BULLETS)
+        .map(|i| {
+            let x = (i * 7 % GRID_SIZE as u32) as i32;
+            let y = (i * 13 % GRID_SIZE as u32) as i32;
+            grid2translation(x, y, 0.) + Vec3::new(3., -5., 0.)
+        })
+        .collect();
+
+    let all_pairs = measure(|| {
+        let mut hits = 0;
+        for bullet in bullets.iter() {
+            for (_, brick) in bricks.iter() {
+                if collide(*bullet, BULLET_SIZE, *brick, brick_size).is_some() {
+                    hits += 1;
+                }
+            }
+        }
+        hits
+    });
+    let spatial = measure(|| {
+        let mut hits = 0;
+        for bullet in bullets.iter() {
+            for shape in grid.query(*bullet, BULLET_SIZE) {
+                if collide(*bullet, BULLET_SIZE, shape.translation, shape.size).is_some() {
+                    hits += 1;
+                }
+            }
+        }
+        hits
+    });
+
+    println!(
+        "{} bricks, {} bullets, per tick: all pairs {:?}, spatial grid {:?}, {:.1}x faster",
+        bricks.len(),
+        BULLETS,
+        all_pairs / TICKS,
+        spatial / TICKS,
+        all_pairs.as_secs_f64() / spatial.as_secs_f64()
+    );
+    assert!(
+        spatial < all_pairs,
+        "spatial grid is slower than checking all pairs"
+    );
+}
+
+/// Runs a tick of collision checks many times, every run must find the same hits
+fn measure(mut tick: impl FnMut() -> usize) -> Duration {
+    let hits = tick();
+    let start = Instant::now();
+    for _ in 0..TICKS {
+        assert_eq!(black_box(tick()), hits);
+    }
+    start.elapsed()
+}
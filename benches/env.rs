@@ -0,0 +1,58 @@
+//! Steps per second of the reinforcement learning environment, headless with both players
+//! moving and firing, over games played from reset to the end. Fails when it runs fewer than
+//! `MIN_STEPS_PER_SECOND`, the thousands of steps per second agents are trained at.
+//!
+//! Run with `cargo bench --bench env`.
+
+use std::{hint::black_box, time::Instant};
+
+use battle_city::{
+    env::{Action, Env, EnvConfig},
+    utils::Direction,
+};
+
+const STEPS: u64 = 20_000;
+const MIN_STEPS_PER_SECOND: f64 = 1000.;
+
+fn main() {
+    let config = EnvConfig::default();
+    let ticks_per_step = config.ticks_per_step;
+    let mut env = Env::new(config);
+    let directions = [
+        Direction::Up,
+        Direction::Left,
+        Direction::Down,
+        Direction::Right,
+    ];
+    let mut games = 1;
+    env.reset(0, 1);
+    let start = Instant::now();
+    for step in 0..STEPS {
+        let action = Action {
+            direction: Some(directions[(step / 10 % 4) as usize]),
+            fire: step % 4 == 0,
+        };
+        if black_box(env.step([action, action])).done {
+            env.reset(games, 1);
+            games += 1;
+        }
+    }
+    let elapsed = start.elapsed();
+
+    let per_second = STEPS as f64 / elapsed.as_secs_f64();
+    println!(
+        "{} steps of {} ticks in {} games: {:?}, {:.0} steps per second, {:.0} ticks per second",
+        STEPS,
+        ticks_per_step,
+        games,
+        elapsed,
+        per_second,
+        per_second * ticks_per_step as f64
+    );
+    assert!(
+        per_second >= MIN_STEPS_PER_SECOND,
+        "{:.0} steps per second, below {}",
+        per_second,
+        MIN_STEPS_PER_SECOND
+    );
+}
@@ -1,6 +1,6 @@
 use crate::{
     brick,
-    collision::Collider,
+    collision::{Collider, Hitbox},
     consts::{BLOCK_WIDTH, MIN_BLOCK_WIDTH, SCALE},
     iron, state,
     texture::SpriteIndex,
@@ -9,7 +9,7 @@ use bevy::{math::const_vec2, prelude::*};
 
 const SIZE: Vec2 = const_vec2!([BLOCK_WIDTH, BLOCK_WIDTH]);
 
-#[derive(Component)]
+#[derive(Component, Clone)]
 pub struct Base;
 
 pub fn spawn(
@@ -32,5 +32,6 @@ pub fn spawn(
         })
         .insert(Base)
         .insert(Collider::Base)
+        .insert(Hitbox(Vec2::new(BLOCK_WIDTH, BLOCK_WIDTH)))
         .insert(state::State::Base);
 }
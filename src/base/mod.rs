@@ -1,4 +1,19 @@
+use bevy::prelude::*;
+
+use crate::simulation::AddSimulationSystem;
+
 mod base;
 pub mod wall;
 
 pub use base::*;
+
+/// The eagle and the wall around it, which can be changed by the shovel power up
+pub struct BasePlugin;
+
+impl Plugin for BasePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<wall::ChangeBaseWall>()
+            .add_simulation_system(wall::handle_change_base_wall)
+            .add_simulation_system(wall::change_basewall_count_down);
+    }
+}
@@ -1,13 +1,14 @@
 use bevy::{math::const_vec3, prelude::*};
 
 use crate::{
-    brick,
-    collision::Collider,
-    consts::{MIN_BLOCK_WIDTH, SCALE},
+    brick::Cells,
+    consts::MIN_BLOCK_WIDTH,
     game_data::GameData,
-    iron, power_up, state,
-    texture::{SpriteIndex, Textures},
+    power_up,
+    simulation::TICK,
+    terrain::{Tile, TileMap},
     utils::Owner,
+    versus::Match,
 };
 
 pub const BASE_WALL_POSITIONS: [Vec3; 8] = [
@@ -21,78 +22,58 @@ pub const BASE_WALL_POSITIONS: [Vec3; 8] = [
     const_vec3!([MIN_BLOCK_WIDTH, -25. * MIN_BLOCK_WIDTH, 0.]),
 ];
 
-#[derive(Component)]
-pub struct BaseWall;
-
 pub enum BaseWallType {
     Iron,
     Brick,
 }
 
-pub fn spawn(commands: &mut Commands, texture: Handle<TextureAtlas>, wtype: BaseWallType) {
-    match wtype {
-        BaseWallType::Iron => {
-            for pos in &BASE_WALL_POSITIONS {
-                commands
-                    .spawn_bundle(SpriteSheetBundle {
-                        sprite: TextureAtlasSprite::new(SpriteIndex::IRON[0]),
-                        texture_atlas: texture.clone(),
-                        transform: Transform {
-                            translation: *pos,
-                            scale: Vec3::splat(SCALE),
-                            ..Default::default()
-                        },
-                        ..Default::default()
-                    })
-                    .insert(iron::Iron)
-                    .insert(Collider::Iron)
-                    .insert(state::State::Iron)
-                    .insert(BaseWall);
-            }
-        }
-        BaseWallType::Brick => {
-            for pos in &BASE_WALL_POSITIONS {
-                commands
-                    .spawn_bundle(SpriteSheetBundle {
-                        sprite: TextureAtlasSprite::new(SpriteIndex::BRICK[1]),
-                        texture_atlas: texture.clone(),
-                        transform: Transform {
-                            translation: *pos,
-                            scale: Vec3::splat(SCALE),
-                            ..Default::default()
-                        },
-                        ..Default::default()
-                    })
-                    .insert(brick::Brick)
-                    .insert(Collider::Brick)
-                    .insert(state::State::Brick(brick::State {
-                        b_type: brick::BrickType::QuarterBrick,
-                    }))
-                    .insert(BaseWall);
-            }
+/// Puts the wall around the base into the tile map, or around the base of P2's team at the top
+/// in versus
+pub fn spawn(tiles: &mut TileMap, wtype: BaseWallType, top: bool) {
+    let tile = match wtype {
+        BaseWallType::Iron => Tile::Iron,
+        BaseWallType::Brick => Tile::Brick(Cells::FULL),
+    };
+    set(tiles, tile, top);
+}
+
+fn set(tiles: &mut TileMap, tile: Tile, top: bool) {
+    for pos in &BASE_WALL_POSITIONS {
+        // the wall at the top is upside down
+        let pos = if top {
+            Vec3::new(pos.x, -pos.y, pos.z)
+        } else {
+            *pos
+        };
+        if let Some((x, y)) = TileMap::locate(pos) {
+            tiles.set(x, y, tile);
         }
     }
 }
 
+/// If a player's base is the one of P2's team at the top
+fn top(owner: Owner, versus: &Option<Res<Match>>) -> bool {
+    versus.is_some() && owner.team == 1
+}
+
+/// A player picked up a shovel, only players pick up power ups
 pub struct ChangeBaseWall {
     pub by: Owner,
 }
 
 pub fn handle_change_base_wall(
-    mut commands: Commands,
     mut event_reader: EventReader<ChangeBaseWall>,
-    query: Query<Entity, With<BaseWall>>,
-    textures: Res<Textures>,
+    mut tiles: ResMut<TileMap>,
     mut game_data: ResMut<GameData>,
+    versus: Option<Res<Match>>,
 ) {
-    let texture = &textures.texture;
     for event in event_reader.iter() {
-        for entity in query.iter() {
-            commands.entity(entity).despawn();
-        }
-        if event.by != Owner::AI {
-            spawn(&mut commands, texture.clone(), BaseWallType::Iron);
+        let changed_by = game_data.base_wall_changed_by;
+        if game_data.base_wall_changed && top(changed_by, &versus) != top(event.by, &versus) {
+            // the other player's base was fortified, it gets its bricks back first
+            spawn(&mut tiles, BaseWallType::Brick, top(changed_by, &versus));
         }
+        spawn(&mut tiles, BaseWallType::Iron, top(event.by, &versus));
 
         game_data.base_wall_changed = true;
         game_data.base_wall_changed_by = event.by;
@@ -101,24 +82,18 @@ pub fn handle_change_base_wall(
 }
 
 pub fn change_basewall_count_down(
-    mut commands: Commands,
-    query: Query<Entity, With<BaseWall>>,
-    time: Res<Time>,
-    textures: Res<Textures>,
+    mut tiles: ResMut<TileMap>,
     mut game_data: ResMut<GameData>,
+    versus: Option<Res<Match>>,
 ) {
     if !game_data.base_wall_changed {
         return;
     }
+    let top = top(game_data.base_wall_changed_by, &versus);
 
-    let texture = &textures.texture;
-    let timer = game_data.restore_timer.tick(time.delta());
+    let timer = game_data.restore_timer.tick(TICK);
     if timer.finished() {
-        for entity in query.iter() {
-            commands.entity(entity).despawn_recursive();
-        }
-
-        spawn(&mut commands, texture.clone(), BaseWallType::Brick);
+        spawn(&mut tiles, BaseWallType::Brick, top);
         game_data.base_wall_changed = false;
         game_data.base_wall_normal = true;
         return;
@@ -126,23 +101,11 @@ pub fn change_basewall_count_down(
 
     let left = timer.duration() - timer.elapsed();
     if left <= power_up::BLINK_DURATION {
-        if game_data.blink_timer.tick(time.delta()).just_finished() {
+        if game_data.blink_timer.tick(TICK).just_finished() {
             if game_data.base_wall_normal {
-                for entity in query.iter() {
-                    commands.entity(entity).despawn_recursive();
-                }
-
-                if game_data.base_wall_changed_by == Owner::AI {
-                    unimplemented!();
-                } else {
-                    spawn(&mut commands, texture.clone(), BaseWallType::Iron);
-                }
+                spawn(&mut tiles, BaseWallType::Iron, top);
             } else {
-                for entity in query.iter() {
-                    commands.entity(entity).despawn();
-                }
-
-                spawn(&mut commands, texture.clone(), BaseWallType::Brick);
+                spawn(&mut tiles, BaseWallType::Brick, top);
             }
             game_data.base_wall_normal = !game_data.base_wall_normal;
         }
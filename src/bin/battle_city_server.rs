@@ -0,0 +1,87 @@
+//! Dedicated server for LAN games, e.g.
+//! `battle_city_server --port 7777 --players 2 --stage 1 --difficulty hard`, or a versus match with
+//! `battle_city_server --players 2 --versus 5 --kills 3`, or every player against every other one
+//! and AI with `battle_city_server --players 4 --teams ffa --friendly-fire`, or waves of AI
+//! without end with `battle_city_server --players 2 --survival`
+//!
+//! It runs until the stage, the match or the survival game is over.
+
+use std::{thread, time::Instant};
+
+use battle_city::{
+    server::{self, Server, ServerConfig},
+    simulation::TICK,
+    survival,
+    team::{FriendlyFire, Teams},
+    versus::Rules,
+};
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let port = match arg_value(&args, "--port") {
+        Some(port) => port.parse().expect("port must be an unsigned integer"),
+        None => server::DEFAULT_PORT,
+    };
+    let mut config = ServerConfig {
+        seed: rand::random(),
+        ..Default::default()
+    };
+    if let Some(seed) = arg_value(&args, "--seed") {
+        config.seed = seed.parse().expect("seed must be an unsigned integer");
+    }
+    if let Some(difficulty) = arg_value(&args, "--difficulty") {
+        config.difficulty = difficulty
+            .parse()
+            .expect("difficulty must be easy, normal, hard or nightmare");
+    }
+    if let Some(stage) = arg_value(&args, "--stage") {
+        config.stage = stage.parse().expect("stage must be an unsigned integer");
+    }
+    if let Some(players) = arg_value(&args, "--players") {
+        config.players = players.parse().expect("players must be 1 to 4");
+    }
+    if let Some(rounds) = arg_value(&args, "--versus") {
+        let mut rules = Rules {
+            rounds: rounds.parse().expect("rounds must be an unsigned integer"),
+            ..Default::default()
+        };
+        if let Some(kills) = arg_value(&args, "--kills") {
+            rules.kills = kills.parse().expect("kills must be an unsigned integer");
+        }
+        config.versus = Some(rules);
+        config.teams = Teams::VERSUS;
+    }
+    if args.iter().any(|arg| arg == "--survival") {
+        config.survival = Some(survival::Rules::default());
+    }
+    if let Some(teams) = arg_value(&args, "--teams") {
+        config.teams = teams
+            .parse()
+            .expect("teams must be coop, versus, ffa or a team per player like 0011");
+    }
+    if args.iter().any(|arg| arg == "--friendly-fire") {
+        config.friendly_fire = FriendlyFire::On;
+    }
+    println!("seed: {}", config.seed);
+
+    let mut server = Server::bind(("0.0.0.0", port), config).expect("failed to bind");
+    println!("listening on port {}", port);
+    let mut next = Instant::now();
+    while server.step() {
+        next += TICK;
+        if let Some(wait) = next.checked_duration_since(Instant::now()) {
+            thread::sleep(wait);
+        }
+    }
+    let status = server.snapshot().unwrap().status;
+    let scores = &status.scores[..status.players as usize];
+    println!("stage {} over, scores: {:?}", status.stage, scores);
+}
+
+/// Value of a command line option, e.g. `--port 7777`
+fn arg_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|i| args.get(i + 1))
+        .map(|value| value.as_str())
+}
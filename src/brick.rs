@@ -1,194 +1,221 @@
-use bevy::{math::const_vec2, prelude::*};
+use bevy::prelude::*;
 
 use crate::{
-    collision::Collider,
-    consts::{BLOCK_WIDTH, HALF_BLOCK_WIDTH, HALF_MIN_BLOCK_WIDTH, MIN_BLOCK_WIDTH, SCALE},
+    collision::{Collider, Hitbox},
+    consts::{MIN_BLOCK_WIDTH, SCALE},
     state,
     texture::SpriteIndex,
+    utils::Direction,
 };
 
-pub const BRICK_SIZE: Vec2 = const_vec2!([BLOCK_WIDTH, BLOCK_WIDTH]);
-
-#[derive(Component)]
+#[derive(Component, Clone)]
 pub struct Brick;
 
-#[derive(Debug)]
-pub enum BrickType {
-    Brick,
-    QuarterBrick,
-    HalfQuarterBrickTop,
-    HalfQuarterBrickRight,
-    HalfQuarterBrickBottom,
-    HalfQuarterBrickLeft,
-    MinBrick1,
-    MinBrick2,
+/// Cells of brick left in a quarter block.
+///
+/// A block of brick is a grid of 4x4 cells, so a quarter block has 2x2 of them. Bit 0 is the top
+/// left cell, bit 1 the top right one, bit 2 the bottom left one and bit 3 the bottom right one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cells(u8);
+
+impl Cells {
+    pub const FULL: Cells = Cells(0b1111);
+    pub const EMPTY: Cells = Cells(0);
+
+    const TOP: u8 = 0b0011;
+    const BOTTOM: u8 = 0b1100;
+    const LEFT: u8 = 0b0101;
+    const RIGHT: u8 = 0b1010;
+
+    pub fn from_bits(bits: u8) -> Self {
+        Self(bits & Self::FULL.0)
+    }
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Removes the row or column of cells facing a bullet flying in a direction
+    pub fn hit(self, direction: Direction) -> Self {
+        // the row or column the bullet meets first, then the one behind it
+        let lines = match direction {
+            Direction::Up => [Self::BOTTOM, Self::TOP],
+            Direction::Right => [Self::LEFT, Self::RIGHT],
+            Direction::Down => [Self::TOP, Self::BOTTOM],
+            Direction::Left => [Self::RIGHT, Self::LEFT],
+        };
+        match lines.iter().find(|line| self.0 & **line != 0) {
+            Some(line) => Self(self.0 & !line),
+            None => self,
+        }
+    }
+
+    /// The center of the cells left relative to the center of the quarter block, and their size
+    ///
+    /// A bullet always takes a whole row or column, so the cells left are a rectangle.
+    pub fn bounds(self) -> (Vec2, Vec2) {
+        if self.is_empty() {
+            return (Vec2::ZERO, Vec2::ZERO);
+        }
+        let (left, right) = (self.0 & Self::LEFT != 0, self.0 & Self::RIGHT != 0);
+        let (top, bottom) = (self.0 & Self::TOP != 0, self.0 & Self::BOTTOM != 0);
+        let half = MIN_BLOCK_WIDTH / 2.;
+        let center = Vec2::new(
+            match (left, right) {
+                (true, false) => -half,
+                (false, true) => half,
+                _ => 0.,
+            },
+            match (top, bottom) {
+                (true, false) => half,
+                (false, true) => -half,
+                _ => 0.,
+            },
+        );
+        let width = (left as u8 + right as u8) as f32 * MIN_BLOCK_WIDTH;
+        let height = (top as u8 + bottom as u8) as f32 * MIN_BLOCK_WIDTH;
+        (center, Vec2::new(width, height))
+    }
+
+    /// The sprite showing the cells left, a single cell has one of two brick patterns
+    pub fn sprite(self) -> usize {
+        match self.0 {
+            Self::TOP => SpriteIndex::BRICK[2],
+            Self::RIGHT => SpriteIndex::BRICK[3],
+            Self::BOTTOM => SpriteIndex::BRICK[4],
+            Self::LEFT => SpriteIndex::BRICK[5],
+            0b0001 | 0b1000 => SpriteIndex::BRICK[6],
+            0b0010 | 0b0100 => SpriteIndex::BRICK[7],
+            _ => SpriteIndex::BRICK[1],
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct State {
-    pub b_type: BrickType,
+    pub cells: Cells,
 }
 
+/// Spawns the cells left in a quarter block, `quarter` is the center of the quarter block
 pub fn spawn(
     commands: &mut Commands,
     texture: Handle<TextureAtlas>,
-    position: Vec3,
-    b_type: BrickType,
-) {
-    match b_type {
-        BrickType::MinBrick1 => {
-            commands
-                .spawn_bundle(SpriteSheetBundle {
-                    sprite: TextureAtlasSprite::new(SpriteIndex::BRICK[6]),
-                    texture_atlas: texture.clone(),
-                    transform: Transform {
-                        translation: position,
-                        scale: Vec3::splat(SCALE),
-                        ..Default::default()
-                    },
-                    ..Default::default()
-                })
-                .insert(Brick)
-                .insert(Collider::Brick)
-                .insert(state::State::Brick(State { b_type }));
-        }
-        BrickType::MinBrick2 => {
-            commands
-                .spawn_bundle(SpriteSheetBundle {
-                    sprite: TextureAtlasSprite::new(SpriteIndex::BRICK[7]),
-                    texture_atlas: texture.clone(),
-                    transform: Transform {
-                        translation: position,
-                        scale: Vec3::splat(SCALE),
-                        ..Default::default()
-                    },
-                    ..Default::default()
-                })
-                .insert(Brick)
-                .insert(Collider::Brick)
-                .insert(state::State::Brick(State { b_type }));
-        }
-        BrickType::HalfQuarterBrickTop => {
-            commands
-                .spawn_bundle(SpriteSheetBundle {
-                    sprite: TextureAtlasSprite::new(SpriteIndex::BRICK[2]),
-                    texture_atlas: texture.clone(),
-                    transform: Transform {
-                        translation: position,
-                        scale: Vec3::splat(SCALE),
-                        ..Default::default()
-                    },
-                    ..Default::default()
-                })
-                .insert(Brick)
-                .insert(Collider::Brick)
-                .insert(state::State::Brick(State { b_type }));
-        }
-        BrickType::HalfQuarterBrickRight => {
-            commands
-                .spawn_bundle(SpriteSheetBundle {
-                    sprite: TextureAtlasSprite::new(SpriteIndex::BRICK[3]),
-                    texture_atlas: texture.clone(),
-                    transform: Transform {
-                        translation: position,
-                        scale: Vec3::splat(SCALE),
-                        ..Default::default()
-                    },
-                    ..Default::default()
-                })
-                .insert(Brick)
-                .insert(Collider::Brick)
-                .insert(state::State::Brick(State { b_type }));
-        }
-        BrickType::HalfQuarterBrickBottom => {
-            commands
-                .spawn_bundle(SpriteSheetBundle {
-                    sprite: TextureAtlasSprite::new(SpriteIndex::BRICK[4]),
-                    texture_atlas: texture.clone(),
-                    transform: Transform {
-                        translation: position,
-                        scale: Vec3::splat(SCALE),
-                        ..Default::default()
-                    },
-                    ..Default::default()
-                })
-                .insert(Brick)
-                .insert(Collider::Brick)
-                .insert(state::State::Brick(State { b_type }));
-        }
-        BrickType::HalfQuarterBrickLeft => {
-            commands
-                .spawn_bundle(SpriteSheetBundle {
-                    sprite: TextureAtlasSprite::new(SpriteIndex::BRICK[5]),
-                    texture_atlas: texture.clone(),
-                    transform: Transform {
-                        translation: position,
-                        scale: Vec3::splat(SCALE),
-                        ..Default::default()
-                    },
-                    ..Default::default()
-                })
-                .insert(Brick)
-                .insert(Collider::Brick)
-                .insert(state::State::Brick(State { b_type }));
-        }
-        BrickType::QuarterBrick => {
-            commands
-                .spawn_bundle(SpriteSheetBundle {
-                    sprite: TextureAtlasSprite::new(SpriteIndex::BRICK[1]),
-                    texture_atlas: texture.clone(),
-                    transform: Transform {
-                        translation: position,
-                        scale: Vec3::splat(SCALE),
-                        ..Default::default()
-                    },
-                    ..Default::default()
-                })
-                .insert(Brick)
-                .insert(Collider::Brick)
-                .insert(state::State::Brick(State { b_type }));
-        }
-        BrickType::Brick => {
-            spawn(
-                commands,
-                texture.clone(),
-                Vec3::new(
-                    position.x - MIN_BLOCK_WIDTH,
-                    position.y + MIN_BLOCK_WIDTH,
-                    position.z,
-                ),
-                BrickType::QuarterBrick,
-            );
-            spawn(
-                commands,
-                texture.clone(),
-                Vec3::new(
-                    position.x + MIN_BLOCK_WIDTH,
-                    position.y + MIN_BLOCK_WIDTH,
-                    position.z,
-                ),
-                BrickType::QuarterBrick,
-            );
-            spawn(
-                commands,
-                texture.clone(),
-                Vec3::new(
-                    position.x - MIN_BLOCK_WIDTH,
-                    position.y - MIN_BLOCK_WIDTH,
-                    position.z,
-                ),
-                BrickType::QuarterBrick,
-            );
-            spawn(
-                commands,
-                texture.clone(),
-                Vec3::new(
-                    position.x + MIN_BLOCK_WIDTH,
-                    position.y - MIN_BLOCK_WIDTH,
-                    position.z,
-                ),
-                BrickType::QuarterBrick,
-            );
+    quarter: Vec3,
+    cells: Cells,
+) -> Entity {
+    let (center, size) = cells.bounds();
+    commands
+        .spawn_bundle(SpriteSheetBundle {
+            sprite: TextureAtlasSprite::new(cells.sprite()),
+            texture_atlas: texture,
+            transform: Transform {
+                translation: quarter + center.extend(0.),
+                scale: Vec3::splat(SCALE),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Brick)
+        .insert(Collider::Brick)
+        .insert(Hitbox(size))
+        .insert(state::State::Brick(State { cells }))
+        .id()
+}
+
+#[test]
+fn test_hit() {
+    // the row or column facing the bullet goes first, then the one behind it
+    let cases = [
+        (Direction::Up, Cells::TOP),
+        (Direction::Right, Cells::RIGHT),
+        (Direction::Down, Cells::BOTTOM),
+        (Direction::Left, Cells::LEFT),
+    ];
+    for (direction, left) in cases {
+        assert_eq!(Cells::FULL.hit(direction), Cells(left));
+        assert_eq!(Cells::FULL.hit(direction).hit(direction), Cells::EMPTY);
+        assert_eq!(Cells::EMPTY.hit(direction), Cells::EMPTY);
+    }
+    // a half hit from the side leaves a single cell
+    assert_eq!(Cells(Cells::TOP).hit(Direction::Right), Cells(0b0010));
+    assert_eq!(Cells(Cells::TOP).hit(Direction::Left), Cells(0b0001));
+    assert_eq!(Cells(Cells::BOTTOM).hit(Direction::Right), Cells(0b1000));
+    assert_eq!(Cells(Cells::BOTTOM).hit(Direction::Left), Cells(0b0100));
+    assert_eq!(Cells(Cells::RIGHT).hit(Direction::Up), Cells(0b0010));
+    assert_eq!(Cells(Cells::RIGHT).hit(Direction::Down), Cells(0b1000));
+    assert_eq!(Cells(Cells::LEFT).hit(Direction::Up), Cells(0b0001));
+    assert_eq!(Cells(Cells::LEFT).hit(Direction::Down), Cells(0b0100));
+    // a half hit on its long side is gone at once
+    assert_eq!(Cells(Cells::TOP).hit(Direction::Up), Cells::EMPTY);
+    assert_eq!(Cells(Cells::LEFT).hit(Direction::Left), Cells::EMPTY);
+    for bits in [0b0001, 0b0010, 0b0100, 0b1000] {
+        for (direction, _) in cases {
+            assert_eq!(Cells(bits).hit(direction), Cells::EMPTY);
         }
     }
 }
+
+#[test]
+fn test_bounds_and_sprite() {
+    let cell = MIN_BLOCK_WIDTH;
+    let half = cell / 2.;
+    let cases = [
+        (Cells::FULL, Vec2::ZERO, Vec2::new(2. * cell, 2. * cell), 1),
+        (
+            Cells(Cells::TOP),
+            Vec2::new(0., half),
+            Vec2::new(2. * cell, cell),
+            2,
+        ),
+        (
+            Cells(Cells::RIGHT),
+            Vec2::new(half, 0.),
+            Vec2::new(cell, 2. * cell),
+            3,
+        ),
+        (
+            Cells(Cells::BOTTOM),
+            Vec2::new(0., -half),
+            Vec2::new(2. * cell, cell),
+            4,
+        ),
+        (
+            Cells(Cells::LEFT),
+            Vec2::new(-half, 0.),
+            Vec2::new(cell, 2. * cell),
+            5,
+        ),
+        (
+            Cells(0b0001),
+            Vec2::new(-half, half),
+            Vec2::new(cell, cell),
+            6,
+        ),
+        (
+            Cells(0b0010),
+            Vec2::new(half, half),
+            Vec2::new(cell, cell),
+            7,
+        ),
+        (
+            Cells(0b0100),
+            Vec2::new(-half, -half),
+            Vec2::new(cell, cell),
+            7,
+        ),
+        (
+            Cells(0b1000),
+            Vec2::new(half, -half),
+            Vec2::new(cell, cell),
+            6,
+        ),
+    ];
+    for (cells, center, size, sprite) in cases {
+        assert_eq!(cells.bounds(), (center, size));
+        assert_eq!(cells.sprite(), SpriteIndex::BRICK[sprite]);
+    }
+}
@@ -1,30 +1,44 @@
-use std::collections::HashSet;
+use std::collections::BTreeSet;
 
-use bevy::{
-    math::const_vec2,
-    prelude::*,
-    sprite::collide_aabb::{collide, Collision},
-};
+use bevy::{math::const_vec2, prelude::*, sprite::collide_aabb::collide};
 
 use crate::{
-    base, brick,
-    brick::{Brick, BrickType},
-    collision::Collider,
-    consts::{BATTLE_FIELD_WIDTH, BLOCK_WIDTH, HALF_MIN_BLOCK_WIDTH, SCALE},
-    explosion, state,
+    base,
+    collision::{self, Collider, Hitbox},
+    consts::{BATTLE_FIELD_WIDTH, BLOCK_WIDTH, MIN_BLOCK_WIDTH, SCALE},
+    event::TankDestroyed,
+    explosion,
+    game_data::GameData,
+    simulation::AddSimulationSystem,
+    spatial::{Shape, SpatialGrid},
+    state,
     tank::{Tank, TANK_SIZE, TANK_SPEED},
+    team::FriendlyFire,
+    terrain::TileMap,
     texture::{SpriteIndex, Textures},
-    utils::{Direction, Owner, AI, P1, P2},
+    utils::{Direction, Owner, Player, AI},
+    versus::Match,
 };
 
 const BULLET_POS: f32 = 6. * SCALE; // the distance from the center of the tank to the center of bullet
 const BULLET_SPEED: f32 = TANK_SPEED + 1.;
 pub const BULLET_SIZE: Vec2 = const_vec2!([4. * SCALE, 4. * SCALE]);
+const CONTACT_TOLERANCE: f32 = 0.01; // things closer than this are touched at the same time
+
+/// Bullets flying, hitting things and the explosions they cause
+pub struct BulletPlugin;
+
+impl Plugin for BulletPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_simulation_system(movement)
+            .add_simulation_system(explosion::explode);
+    }
+}
 
-#[derive(Component)]
+#[derive(Component, Clone)]
 pub struct Bullet;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct State {
     pub direction: Direction,
     pub speed: f32,
@@ -68,7 +82,7 @@ pub fn spawn(
     bullet
         .insert(Bullet)
         .insert(Collider::Bullet)
-        .insert(Timer::from_seconds(0.01, true))
+        .insert(Hitbox(BULLET_SIZE))
         .insert(state::State::Bullet(State {
             direction: *direction,
             speed: BULLET_SPEED,
@@ -77,289 +91,192 @@ pub fn spawn(
         }));
 
     // add additional mark for the bullet, makes querying for bullet easier
-    match source {
-        Owner::P1 => bullet.insert(P1),
-        Owner::P2 => bullet.insert(P2),
-        Owner::AI => bullet.insert(AI),
+    match source.player {
+        Some(player) => bullet.insert(Player(player)),
+        None => bullet.insert(AI),
     };
 }
 
-// Movement system
+/// Moves bullets and handles what they run into.
+///
+/// A bullet sweeps its whole path of a tick and stops at the first contact, so it never skips
+/// over a thin piece of brick. Everything touched at that contact is hit at once, e.g. the pair
+/// of brick quarters in front of it, and the bullet explodes only once.
 pub fn movement(
-    time: Res<Time>,
-    mut bullets: Query<(&mut Timer, &mut Transform, &state::State), With<Bullet>>,
-) {
-    for (mut timer, mut transform, state) in bullets.iter_mut() {
-        if timer.tick(time.delta()).finished() {
-            let b_state = state.as_bullet();
-            transform.translation += match b_state.direction {
-                Direction::Up => Vec3::Y * b_state.speed,
-                Direction::Right => Vec3::X * b_state.speed,
-                Direction::Down => Vec3::Y * -b_state.speed,
-                Direction::Left => Vec3::X * -b_state.speed,
-            };
-        }
-    }
-}
-
-// Collision system
-pub fn collision(
     mut commands: Commands,
     textures: Res<Textures>,
-    texture_atlases: Res<Assets<TextureAtlas>>,
-    bullets: Query<(Entity, &Transform, &state::State), With<Bullet>>,
-    colliders: Query<(
-        Entity,
-        &Collider,
-        &Transform,
-        &state::State,
-        Option<&Sprite>,
-        Option<&TextureAtlasSprite>,
-    )>,
+    mut game_data: ResMut<GameData>,
+    mut grid: ResMut<SpatialGrid>,
+    mut tiles: ResMut<TileMap>,
+    mut td_events: EventWriter<TankDestroyed>,
+    mut versus: Option<ResMut<Match>>,
+    friendly_fire: Res<FriendlyFire>,
+    mut bullets: Query<(Entity, &mut Transform, &state::State), With<Bullet>>,
+    colliders: Query<&state::State, With<Collider>>,
 ) {
-    let mut size;
     let texture = &textures.texture;
-    let mut bullets_to_despawn = HashSet::new();
-    for (b_entity, b_transform, b_state) in bullets.iter() {
-        for (c_entity, collider, c_transform, c_state, sprite, atlas_sprite) in colliders.iter() {
-            if b_entity == c_entity {
-                continue;
-            }
-            size = match collider {
-                Collider::Boundary => sprite.unwrap().custom_size.unwrap(),
-                _ => {
-                    let index = atlas_sprite.unwrap().index;
-                    let texture_atlas = texture_atlases.get(texture).unwrap();
-                    let sprite = texture_atlas.textures.get(index).unwrap();
-                    sprite.size() * SCALE
-                }
-            };
+    // in the order they are hit, the order of despawning decides the order of the bullets left
+    let mut bullets_to_despawn = Vec::new();
+    let mut destroyed = BTreeSet::new(); // a tank or brick can be hit by several bullets in a tick
+    let entities: Vec<Entity> = bullets.iter().map(|(entity, _, _)| entity).collect();
+    for b_entity in entities {
+        if bullets_to_despawn.contains(&b_entity) {
+            continue; // hit by another bullet
+        }
+        let (_, mut b_transform, b_state) = bullets.get_mut(b_entity).unwrap();
+        let bullet = b_state.as_bullet();
+        let position = b_transform.translation;
+        let mut contacts: Vec<(f32, Shape)> = grid
+            .ahead(position, BULLET_SIZE, bullet.direction, bullet.speed)
+            .into_iter()
+            .filter(|shape| {
+                shape.entity != b_entity
+                    && !destroyed.contains(&shape.entity)
+                    && !bullets_to_despawn.contains(&shape.entity)
+            })
+            .filter_map(|shape| {
+                contact(position, bullet.direction, &shape)
+                    .filter(|distance| *distance <= bullet.speed)
+                    .map(|distance| (distance, shape))
+            })
+            .collect();
+        // stable, so things at the same distance stay in the order the grid gives them
+        contacts.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
 
-            let collision = collide(
-                b_transform.translation,
-                BULLET_SIZE,
-                c_transform.translation,
-                size,
-            );
-            if collision.is_none() {
-                continue;
+        let mut stop = None; // distance to the first contact
+        let mut blast = None; // where the bullet explodes
+        let mut hit_wall = false;
+        for (distance, shape) in contacts {
+            if stop.map_or(false, |stop| distance > stop + CONTACT_TOLERANCE) {
+                break;
             }
-            let collision = collision.unwrap();
-            match collider {
-                Collider::River | Collider::Snow | Collider::PowerUp => continue,
-                Collider::Grass => {
-                    // TODO: tanks with enough power ups can remove grass, e.g. 4 stars
-                }
-                Collider::Brick => {
-                    bullets_to_despawn.insert(b_entity);
-                    commands.entity(c_entity).despawn();
-                    // TO FIX: explosion will be spawned twice if bullet hit the middle of a Brick (hit 2 QuarteBrick in a single frame)
-                    explosion::spawn(
-                        &mut commands,
-                        texture.clone(),
-                        b_transform.translation,
-                        false,
-                    );
-                    let pos = c_transform.translation;
-                    match c_state.as_brick().b_type {
-                        BrickType::Brick => unreachable!(), // a Brick is actually 4 QuarterBrick
-                        BrickType::QuarterBrick => match collision {
-                            Collision::Top => brick::spawn(
-                                &mut commands,
-                                texture.clone(),
-                                Vec3::new(pos.x, pos.y - HALF_MIN_BLOCK_WIDTH, pos.z),
-                                BrickType::HalfQuarterBrickBottom,
-                            ),
-                            Collision::Right => brick::spawn(
-                                &mut commands,
-                                texture.clone(),
-                                Vec3::new(pos.x - HALF_MIN_BLOCK_WIDTH, pos.y, pos.z),
-                                BrickType::HalfQuarterBrickLeft,
-                            ),
-                            Collision::Bottom => brick::spawn(
-                                &mut commands,
-                                texture.clone(),
-                                Vec3::new(pos.x, pos.y + HALF_MIN_BLOCK_WIDTH, pos.z),
-                                BrickType::HalfQuarterBrickTop,
-                            ),
-                            Collision::Left => brick::spawn(
-                                &mut commands,
-                                texture.clone(),
-                                Vec3::new(pos.x + HALF_MIN_BLOCK_WIDTH, pos.y, pos.z),
-                                BrickType::HalfQuarterBrickRight,
-                            ),
-                        },
-                        BrickType::HalfQuarterBrickTop => match collision {
-                            Collision::Top | Collision::Bottom => (),
-                            Collision::Left => brick::spawn(
-                                &mut commands,
-                                texture.clone(),
-                                Vec3::new(pos.x + HALF_MIN_BLOCK_WIDTH, pos.y, pos.z),
-                                BrickType::MinBrick2,
-                            ),
-                            Collision::Right => brick::spawn(
-                                &mut commands,
-                                texture.clone(),
-                                Vec3::new(pos.x - HALF_MIN_BLOCK_WIDTH, pos.y, pos.z),
-                                BrickType::MinBrick1,
-                            ),
-                        },
-                        BrickType::HalfQuarterBrickRight => match collision {
-                            Collision::Left | Collision::Right => (),
-                            Collision::Top => brick::spawn(
-                                &mut commands,
-                                texture.clone(),
-                                Vec3::new(pos.x, pos.y - HALF_MIN_BLOCK_WIDTH, pos.z),
-                                BrickType::MinBrick1,
-                            ),
-                            Collision::Bottom => brick::spawn(
-                                &mut commands,
-                                texture.clone(),
-                                Vec3::new(pos.x, pos.y - HALF_MIN_BLOCK_WIDTH, pos.z),
-                                BrickType::MinBrick2,
-                            ),
-                        },
-                        BrickType::HalfQuarterBrickBottom => match collision {
-                            Collision::Top | Collision::Bottom => (),
-                            Collision::Left => brick::spawn(
-                                &mut commands,
-                                texture.clone(),
-                                Vec3::new(pos.x + HALF_MIN_BLOCK_WIDTH, pos.y, pos.z),
-                                BrickType::MinBrick1,
-                            ),
-                            Collision::Right => brick::spawn(
-                                &mut commands,
-                                texture.clone(),
-                                Vec3::new(pos.x - HALF_MIN_BLOCK_WIDTH, pos.y, pos.z),
-                                BrickType::MinBrick2,
-                            ),
-                        },
-                        BrickType::HalfQuarterBrickLeft => match collision {
-                            Collision::Left | Collision::Right => (),
-                            Collision::Top => brick::spawn(
-                                &mut commands,
-                                texture.clone(),
-                                Vec3::new(pos.x, pos.y - HALF_MIN_BLOCK_WIDTH, pos.z),
-                                BrickType::MinBrick2,
-                            ),
-                            Collision::Bottom => brick::spawn(
-                                &mut commands,
-                                texture.clone(),
-                                Vec3::new(pos.x, pos.y - HALF_MIN_BLOCK_WIDTH, pos.z),
-                                BrickType::MinBrick1,
-                            ),
-                        },
-                        BrickType::MinBrick1 | BrickType::MinBrick2 => (),
-                    }
-                }
-                Collider::Iron => {
-                    bullets_to_despawn.insert(b_entity);
-                    explosion::spawn(
-                        &mut commands,
-                        texture.clone(),
-                        b_transform.translation,
-                        false,
-                    );
-                    let bullet = b_state.as_bullet();
-                    if bullet.level > 2 {
-                        commands.entity(c_entity).despawn();
-                    }
+            let c_state = match colliders.get(shape.entity) {
+                Ok(c_state) => c_state,
+                Err(_) => continue,
+            };
+            let contact_position = position + direction_vec(bullet.direction) * distance;
+            match shape.collider {
+                // TODO: tanks with enough power ups can remove grass, e.g. 4 stars
+                Collider::River | Collider::Snow | Collider::PowerUp | Collider::Grass => continue,
+                Collider::Brick | Collider::Iron => {
+                    hit_wall = true;
+                    blast.get_or_insert(contact_position);
                 }
                 Collider::Boundary => {
-                    bullets_to_despawn.insert(b_entity);
-                    let pos = match b_state.as_bullet().direction {
-                        Direction::Up => {
-                            Vec3::new(b_transform.translation.x, BATTLE_FIELD_WIDTH / 2., 0.)
-                        }
-                        Direction::Right => {
-                            Vec3::new(6. * BLOCK_WIDTH, b_transform.translation.y, 0.)
-                        }
+                    let pos = match bullet.direction {
+                        Direction::Up => Vec3::new(contact_position.x, BATTLE_FIELD_WIDTH / 2., 0.),
+                        Direction::Right => Vec3::new(6. * BLOCK_WIDTH, contact_position.y, 0.),
                         Direction::Down => {
-                            Vec3::new(b_transform.translation.x, BATTLE_FIELD_WIDTH / -2., 0.)
-                        }
-                        Direction::Left => {
-                            Vec3::new(-7. * BLOCK_WIDTH, b_transform.translation.y, 0.)
+                            Vec3::new(contact_position.x, BATTLE_FIELD_WIDTH / -2., 0.)
                         }
+                        Direction::Left => Vec3::new(-7. * BLOCK_WIDTH, contact_position.y, 0.),
                     };
-                    explosion::spawn(&mut commands, texture.clone(), pos, false);
+                    blast.get_or_insert(pos);
                 }
                 Collider::Base => {
-                    bullets_to_despawn.insert(b_entity);
-                    commands.entity(c_entity).despawn();
-                    explosion::spawn(
-                        &mut commands,
-                        texture.clone(),
-                        b_transform.translation,
-                        false,
-                    );
-                    base::spawn(
-                        &mut commands,
-                        c_transform.translation,
-                        texture.clone(),
-                        true,
-                    );
-                    // TODO: Game Over
+                    destroyed.insert(shape.entity);
+                    commands.entity(shape.entity).despawn();
+                    blast.get_or_insert(contact_position);
+                    base::spawn(&mut commands, shape.translation, texture.clone(), true);
+                    game_data.base_destroyed = true; // TODO: Game Over
+                    if let Some(versus) = &mut versus {
+                        versus.base_destroyed(shape.translation);
+                    }
                 }
                 Collider::Bullet => {
-                    let c_bullet = c_state.as_bullet();
-                    let bullet = b_state.as_bullet();
-                    if c_bullet.source == bullet.source {
+                    if c_state.as_bullet().source == bullet.source {
                         continue;
                     }
-                    bullets_to_despawn.insert(b_entity);
-                    bullets_to_despawn.insert(c_entity);
+                    bullets_to_despawn.push(shape.entity);
                 }
                 Collider::Tank => {
                     let tank = c_state.as_tank();
-                    let bullet = b_state.as_bullet();
-                    match bullet.source {
-                        Owner::P1 => match tank.owner {
-                            Owner::P1 => continue,
-                            Owner::P2 => {
-                                bullets_to_despawn.insert(b_entity);
-                                // TODO: freeze P2 for some seconds
-                            }
-                            Owner::AI => {
-                                bullets_to_despawn.insert(b_entity);
-                                commands.entity(c_entity).despawn();
-                                explosion::spawn(
-                                    &mut commands,
-                                    texture.clone(),
-                                    c_transform.translation,
-                                    true,
-                                );
-                            }
-                        },
-                        Owner::P2 => match tank.owner {
-                            Owner::P1 => {
-                                bullets_to_despawn.insert(b_entity);
-                                // TODO: freeze P1 for some seconds
-                            }
-                            Owner::P2 => continue,
-                            Owner::AI => {
-                                bullets_to_despawn.insert(b_entity);
-                                commands.entity(c_entity).despawn();
-                                explosion::spawn(
-                                    &mut commands,
-                                    texture.clone(),
-                                    c_transform.translation,
-                                    true,
-                                );
-                            }
-                        },
-                        Owner::AI => match tank.owner {
-                            Owner::P1 | Owner::P2 => {
-                                bullets_to_despawn.insert(b_entity);
-                                // TODO: destroy players' tank
-                            }
-                            Owner::AI => continue,
-                        },
+                    if tank.owner == bullet.source {
+                        continue; // bullets fly through tanks of their own
+                    }
+                    // TODO: freeze a player for some seconds when a teammate hits it
+                    if friendly_fire.hurts(bullet.source, tank.owner) && !tank.shield {
+                        destroyed.insert(shape.entity);
+                        // in case tank has sub entity, like a shield
+                        commands.entity(shape.entity).despawn_recursive();
+                        explosion::spawn(&mut commands, texture.clone(), shape.translation, true);
+                        td_events.send(TankDestroyed {
+                            tank: tank.owner,
+                            level: tank.level,
+                            bonus: tank.bonus,
+                            by: bullet.source,
+                        });
                     }
                 }
             }
+            stop.get_or_insert(distance);
+        }
+
+        let distance = stop.unwrap_or(bullet.speed);
+        b_transform.translation += direction_vec(bullet.direction) * distance;
+        grid.update(b_entity, b_transform.translation);
+        if stop.is_none() {
+            continue;
+        }
+        bullets_to_despawn.push(b_entity);
+        if hit_wall {
+            let (center, size) = impact_area(b_transform.translation, bullet.direction);
+            for shape in grid.query(center, size) {
+                if destroyed.contains(&shape.entity) {
+                    continue;
+                }
+                match shape.collider {
+                    Collider::Brick => (),
+                    Collider::Iron if bullet.level > 2 => (),
+                    _ => continue,
+                }
+                destroyed.insert(shape.entity);
+                if let Some((x, y)) = TileMap::locate(shape.translation) {
+                    tiles.hit(x, y, bullet.direction, bullet.level > 2);
+                }
+            }
+        }
+        if let Some(pos) = blast {
+            explosion::spawn(&mut commands, texture.clone(), pos, false);
         }
     }
     for bullet in bullets_to_despawn {
         commands.entity(bullet).despawn();
     }
 }
+
+fn direction_vec(direction: Direction) -> Vec3 {
+    match direction {
+        Direction::Up => Vec3::Y,
+        Direction::Right => Vec3::X,
+        Direction::Down => -Vec3::Y,
+        Direction::Left => -Vec3::X,
+    }
+}
+
+/// How far a bullet flies before it touches a collider, zero if they already overlap
+fn contact(position: Vec3, direction: Direction, shape: &Shape) -> Option<f32> {
+    if collide(position, BULLET_SIZE, shape.translation, shape.size).is_some() {
+        return Some(0.);
+    }
+    collision::collide(
+        position,
+        BULLET_SIZE,
+        shape.translation,
+        shape.size,
+        &direction,
+    )
+}
+
+/// The area a bullet breaks walls in: as wide as a tank and a min block deep, right in front of
+/// the bullet, so it takes the pair of quarters it hits between them
+fn impact_area(position: Vec3, direction: Direction) -> (Vec3, Vec2) {
+    // shrink a little, so walls right next to the area are left alone
+    let (width, depth) = (BLOCK_WIDTH - 0.02, MIN_BLOCK_WIDTH - 0.02);
+    let center = position + direction_vec(direction) * (BULLET_SIZE.y + MIN_BLOCK_WIDTH) / 2.;
+    let size = match direction {
+        Direction::Up | Direction::Down => Vec2::new(width, depth),
+        Direction::Left | Direction::Right => Vec2::new(depth, width),
+    };
+    (center, size)
+}
@@ -4,7 +4,7 @@ use bevy::{
     prelude::Component,
 };
 
-#[derive(Debug, Copy, Clone, Component)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Component)]
 pub enum Collider {
     Boundary, // boundary of battle field
     Brick,
@@ -18,6 +18,10 @@ pub enum Collider {
     PowerUp,
 }
 
+/// Size of a collider on battle field, set when it spawns
+#[derive(Debug, Copy, Clone, Component)]
+pub struct Hitbox(pub Vec2);
+
 /// Calculates the distance between a tank and an obstacle in front of it
 ///
 /// a is tank and b is obstacle
@@ -65,3 +69,44 @@ pub fn collide(
         }
     }
 }
+
+/// Like `collide`, for two tanks of the same size which may already overlap, e.g. when one
+/// drove onto a spawn point while another tank was spawning there.
+///
+/// Overlapping tanks are allowed to drive apart but not deeper into each other, so zero is
+/// returned when a moves toward the center of b.
+pub fn collide_tanks(a_pos: Vec3, b_pos: Vec3, size: Vec2, direction: &Direction) -> Option<f32> {
+    let delta = b_pos.truncate() - a_pos.truncate();
+    if delta.x.abs() >= size.x || delta.y.abs() >= size.y {
+        return collide(a_pos, size, b_pos, size, direction);
+    }
+    let deeper = match *direction {
+        Direction::Up => delta.y > 0.,
+        Direction::Right => delta.x > 0.,
+        Direction::Down => delta.y < 0.,
+        Direction::Left => delta.x < 0.,
+    };
+    if deeper {
+        Some(0.)
+    } else {
+        None
+    }
+}
+
+#[test]
+fn test_collide_tanks() {
+    let size = Vec2::new(32., 32.);
+    let a = Vec3::ZERO;
+    // apart, the same as `collide`
+    let b = Vec3::new(0., 40., 0.);
+    assert_eq!(collide_tanks(a, b, size, &Direction::Up), Some(8.));
+    assert_eq!(collide_tanks(a, b, size, &Direction::Down), None);
+    // overlapping, only driving away is allowed
+    let b = Vec3::new(10., 20., 0.);
+    assert_eq!(collide_tanks(a, b, size, &Direction::Up), Some(0.));
+    assert_eq!(collide_tanks(a, b, size, &Direction::Right), Some(0.));
+    assert_eq!(collide_tanks(a, b, size, &Direction::Down), None);
+    assert_eq!(collide_tanks(a, b, size, &Direction::Left), None);
+    assert_eq!(collide_tanks(b, a, size, &Direction::Down), Some(0.));
+    assert_eq!(collide_tanks(b, a, size, &Direction::Up), None);
+}
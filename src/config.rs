@@ -0,0 +1,98 @@
+use std::{str::FromStr, time::Duration};
+
+use crate::{tank::controller::AiSettings, team::Teams};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+    Nightmare,
+}
+
+impl Difficulty {
+    pub const ALL: [Difficulty; 4] = [
+        Difficulty::Easy,
+        Difficulty::Normal,
+        Difficulty::Hard,
+        Difficulty::Nightmare,
+    ];
+
+    /// How AI tanks behave and come onto battle field at this difficulty
+    pub fn ai_settings(self) -> AiSettings {
+        match self {
+            Difficulty::Easy => AiSettings {
+                base_bias: 0.15,
+                player_bias: 0.,
+                reaction_time: Duration::from_millis(600),
+                aim_accuracy: 0.2,
+                fire_cooldown: Duration::from_millis(1500),
+                drive_time: (Duration::from_secs(1), Duration::from_secs(4)),
+                spawn_interval: Duration::from_secs(5),
+                armour_ratio: 0.05,
+                bonus_ratio: 0.25,
+            },
+            Difficulty::Normal => AiSettings {
+                base_bias: 0.25,
+                player_bias: 0.1,
+                reaction_time: Duration::from_millis(300),
+                aim_accuracy: 0.5,
+                fire_cooldown: Duration::from_millis(800),
+                drive_time: (Duration::from_millis(500), Duration::from_secs(3)),
+                spawn_interval: Duration::from_secs(3),
+                armour_ratio: 0.15,
+                bonus_ratio: 0.2,
+            },
+            Difficulty::Hard => AiSettings {
+                base_bias: 0.35,
+                player_bias: 0.2,
+                reaction_time: Duration::from_millis(150),
+                aim_accuracy: 0.75,
+                fire_cooldown: Duration::from_millis(500),
+                drive_time: (Duration::from_millis(500), Duration::from_secs(2)),
+                spawn_interval: Duration::from_secs(2),
+                armour_ratio: 0.3,
+                bonus_ratio: 0.15,
+            },
+            Difficulty::Nightmare => AiSettings {
+                base_bias: 0.45,
+                player_bias: 0.35,
+                reaction_time: Duration::from_millis(50),
+                aim_accuracy: 0.95,
+                fire_cooldown: Duration::from_millis(300),
+                drive_time: (Duration::from_millis(300), Duration::from_millis(1500)),
+                spawn_interval: Duration::from_secs(1),
+                armour_ratio: 0.5,
+                bonus_ratio: 0.1,
+            },
+        }
+    }
+}
+
+impl Default for Difficulty {
+    fn default() -> Self {
+        Difficulty::Normal
+    }
+}
+
+impl FromStr for Difficulty {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "easy" => Ok(Difficulty::Easy),
+            "normal" => Ok(Difficulty::Normal),
+            "hard" => Ok(Difficulty::Hard),
+            "nightmare" => Ok(Difficulty::Nightmare),
+            _ => Err(format!("unknown difficulty: {}", s)),
+        }
+    }
+}
+
+/// Options of a game, chosen on the title menu or from the command line before it starts
+#[derive(Debug, Default)]
+pub struct Config {
+    pub difficulty: Difficulty,
+    pub players: usize, // tanks played, P1's first
+    pub teams: Teams,
+}
@@ -0,0 +1,365 @@
+//! Reinforcement learning environment.
+//!
+//! The game runs headless, every step feeds one action per player to the simulation and runs a
+//! few ticks, then returns what the players see and the reward they get:
+//!
+//! ```no_run
+//! use battle_city::env::{Action, Env, EnvConfig};
+//!
+//! let mut env = Env::new(EnvConfig::default());
+//! let mut observation = env.reset(42, 1);
+//! loop {
+//!     let step = env.step([Action::default(), Action::default()]);
+//!     observation = step.observation;
+//!     if step.done {
+//!         break;
+//!     }
+//! }
+//! ```
+//!
+//! `cargo bench --bench env` measures how many steps per second it runs.
+
+use bevy::{
+    app::{Events, ManualEventReader},
+    prelude::*,
+};
+
+use crate::{
+    collision::{Collider, Hitbox},
+    config::Difficulty,
+    event::TankDestroyed,
+    game_data::{self, GameData},
+    input::{InputSource, PlayerInputs, TankInput},
+    simulation::Clock,
+    state,
+    team::{FriendlyFire, Teams},
+    utils::{grid_cells, Direction, GRID_SIZE, MAX_PLAYERS},
+    BattleCityPlugins,
+};
+
+pub const CHANNELS: usize = 12;
+
+/// Channels of the observation grid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Brick,
+    Iron,
+    River,
+    Grass,
+    Snow,
+    Base,
+    P1,
+    P2,
+    Enemy,
+    PlayerBullet,
+    EnemyBullet,
+    PowerUp,
+}
+
+/// What a player does during a step
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Action {
+    pub direction: Option<Direction>, // None to stay still
+    pub fire: bool,
+}
+
+impl From<Action> for TankInput {
+    fn from(action: Action) -> Self {
+        let mut input = TankInput::default();
+        if let Some(direction) = action.direction {
+            let button = match direction {
+                Direction::Up => TankInput::UP,
+                Direction::Right => TankInput::RIGHT,
+                Direction::Down => TankInput::DOWN,
+                Direction::Left => TankInput::LEFT,
+            };
+            input.set(button, true);
+        }
+        input.set(TankInput::FIRE, action.fire);
+        input
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Observation {
+    /// CHANNELS x GRID_SIZE x GRID_SIZE, a cell is 1 if anything of the channel covers the
+    /// quarter block
+    pub grid: Vec<u8>,
+    pub lifes: [u8; 2],
+    pub alive: [bool; 2], // if player's tank is on battle field
+    pub levels: [u8; 2],
+    pub scores: [u32; 2],
+    pub enemies: u8, // AI tanks on battle field
+    pub tick: u64,
+}
+
+impl Observation {
+    pub fn get(&self, channel: Channel, x: usize, y: usize) -> bool {
+        self.grid[(channel as usize * GRID_SIZE + y) * GRID_SIZE + x] != 0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub observation: Observation,
+    pub reward: [f32; 2],
+    pub done: bool,
+}
+
+pub struct EnvConfig {
+    pub difficulty: Difficulty,
+    pub ally: bool, // P2 is driven by the friendly AI, its actions are ignored
+    pub ticks_per_step: u32,
+    pub max_steps: u64,
+    pub kill_reward: f32, // for every 100 points scored
+    pub death_penalty: f32,
+    pub base_penalty: f32, // both players are punished when the base is destroyed
+}
+
+impl Default for EnvConfig {
+    fn default() -> Self {
+        Self {
+            difficulty: Difficulty::Normal,
+            ally: false,
+            ticks_per_step: 4,
+            max_steps: 10_000,
+            kill_reward: 1.,
+            death_penalty: 1.,
+            base_penalty: 10.,
+        }
+    }
+}
+
+pub struct Env {
+    app: App,
+    config: EnvConfig,
+    steps: u64,
+    scores: [u32; 2],
+    destroyed: ManualEventReader<TankDestroyed>,
+}
+
+impl Env {
+    pub fn new(config: EnvConfig) -> Self {
+        let mut env = Self {
+            app: App::new(), // built by reset
+            config,
+            steps: 0,
+            scores: [0; 2],
+            destroyed: ManualEventReader::default(),
+        };
+        env.reset(0, 1);
+        env
+    }
+
+    /// Starts a new game, the same seed, stage and actions always play the same game
+    pub fn reset(&mut self, seed: u64, stage: u8) -> Observation {
+        self.app = build_app(seed, stage, &self.config);
+        self.app.update(); // spawn the battle field
+        self.steps = 0;
+        self.scores = [0; 2];
+        self.destroyed = ManualEventReader::default();
+        self.observe()
+    }
+
+    pub fn step(&mut self, actions: [Action; 2]) -> Step {
+        let mut inputs = [TankInput::default(); MAX_PLAYERS];
+        inputs[0] = actions[0].into();
+        inputs[1] = actions[1].into();
+        let mut deaths = [0.; 2];
+        let mut done = false;
+        for _ in 0..self.config.ticks_per_step {
+            let mut player_inputs = self.app.world.get_resource_mut::<PlayerInputs>().unwrap();
+            player_inputs.push(inputs);
+            self.app.update();
+
+            let events = self
+                .app
+                .world
+                .get_resource::<Events<TankDestroyed>>()
+                .unwrap();
+            for event in self.destroyed.iter(events) {
+                match event.tank.player {
+                    Some(player) if player < 2 => deaths[player as usize] += 1.,
+                    _ => (),
+                }
+            }
+            done = game_data::is_over(&mut self.app.world);
+            if done {
+                break;
+            }
+        }
+        self.steps += 1;
+        done |= self.steps >= self.config.max_steps;
+
+        let observation = self.observe();
+        let base_destroyed = self
+            .app
+            .world
+            .get_resource::<GameData>()
+            .unwrap()
+            .base_destroyed;
+        let reward = [0, 1].map(|i| {
+            let points = (observation.scores[i] - self.scores[i]) as f32 / 100.;
+            let mut reward =
+                points * self.config.kill_reward - deaths[i] * self.config.death_penalty;
+            if base_destroyed {
+                reward -= self.config.base_penalty;
+            }
+            reward
+        });
+        self.scores = observation.scores;
+        Step {
+            observation,
+            reward,
+            done,
+        }
+    }
+
+    /// The game itself, e.g. to inspect entities or change resources
+    pub fn app(&mut self) -> &mut App {
+        &mut self.app
+    }
+
+    fn observe(&mut self) -> Observation {
+        let mut query = self
+            .app
+            .world
+            .query::<(&Collider, &Transform, &state::State, &Hitbox)>();
+        let world = &self.app.world;
+        let mut grid = vec![0; CHANNELS * GRID_SIZE * GRID_SIZE];
+        let mut alive = [false; 2];
+        let mut levels = [0; 2];
+        let mut enemies = 0;
+        for (collider, transform, state, hitbox) in query.iter(world) {
+            let channel = match collider {
+                Collider::Boundary => continue,
+                Collider::Brick => Channel::Brick,
+                Collider::Iron => Channel::Iron,
+                Collider::River => Channel::River,
+                Collider::Grass => Channel::Grass,
+                Collider::Snow => Channel::Snow,
+                Collider::Base => Channel::Base,
+                Collider::PowerUp => Channel::PowerUp,
+                Collider::Bullet => match state.as_bullet().source.player {
+                    Some(_) => Channel::PlayerBullet,
+                    None => Channel::EnemyBullet,
+                },
+                Collider::Tank => {
+                    let tank = state.as_tank();
+                    match tank.owner.player {
+                        Some(0) => {
+                            alive[0] = true;
+                            levels[0] = tank.level;
+                            Channel::P1
+                        }
+                        Some(1) => {
+                            alive[1] = true;
+                            levels[1] = tank.level;
+                            Channel::P2
+                        }
+                        Some(_) => continue, // only P1 and P2 play in the environment
+                        None => {
+                            enemies += 1;
+                            Channel::Enemy
+                        }
+                    }
+                }
+            };
+            mark(&mut grid, channel, transform.translation, hitbox.0);
+        }
+
+        let game_data = world.get_resource::<GameData>().unwrap();
+        Observation {
+            grid,
+            lifes: [game_data.lifes[0], game_data.lifes[1]],
+            alive,
+            levels,
+            scores: [game_data.scores[0], game_data.scores[1]],
+            enemies,
+            tick: world.get_resource::<Clock>().unwrap().tick,
+        }
+    }
+}
+
+fn build_app(seed: u64, stage: u8, config: &EnvConfig) -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugins(BattleCityPlugins {
+            headless: true,
+            seed,
+            difficulty: config.difficulty,
+            input: InputSource::External,
+            ally: config.ally,
+            save: None,
+            players: 2,
+            teams: Teams::COOP,
+            friendly_fire: FriendlyFire::Off,
+            survival: None,
+            versus: None,
+        });
+    app.world.get_resource_mut::<GameData>().unwrap().stage = stage;
+    app
+}
+
+/// Marks every quarter block covered by an entity
+fn mark(grid: &mut [u8], channel: Channel, translation: Vec3, size: Vec2) {
+    for (x, y) in grid_cells(translation, size) {
+        grid[(channel as usize * GRID_SIZE + y) * GRID_SIZE + x] = 1;
+    }
+}
+
+#[test]
+fn test_env() {
+    let mut env = Env::new(EnvConfig {
+        max_steps: 50,
+        ..Default::default()
+    });
+    let actions = |step: u64| {
+        let direction = [
+            Direction::Up,
+            Direction::Left,
+            Direction::Down,
+            Direction::Right,
+        ];
+        [
+            Action {
+                direction: Some(direction[(step / 5 % 4) as usize]),
+                fire: step % 3 == 0,
+            },
+            Action {
+                direction: Some(direction[(step / 7 % 4) as usize]),
+                fire: step % 2 == 0,
+            },
+        ]
+    };
+    let play = |env: &mut Env, seed| {
+        let observation = env.reset(seed, 1);
+        assert_eq!(observation.grid.len(), CHANNELS * GRID_SIZE * GRID_SIZE);
+        assert!(observation.alive[0]); // P2 comes out of a star a bit later
+        assert!(observation.get(Channel::Base, 12, 25));
+        let mut steps = Vec::new();
+        for step in 0.. {
+            let result = env.step(actions(step));
+            let done = result.done;
+            steps.push(result);
+            if done {
+                break;
+            }
+        }
+        steps
+    };
+
+    // the same seed and actions play the same game, which ends after max_steps at the latest
+    let first = play(&mut env, 7);
+    let second = play(&mut env, 7);
+    assert!(first.len() <= 50);
+    assert_eq!(first.len(), second.len());
+    for (a, b) in first.iter().zip(second.iter()) {
+        assert_eq!(a.observation.grid, b.observation.grid);
+        assert_eq!(a.observation.tick, b.observation.tick);
+        assert_eq!(a.reward, b.reward);
+    }
+    // a step ends early with the game
+    let last = &first.last().unwrap().observation;
+    assert!(last.tick <= first.len() as u64 * 4 + 1);
+}
@@ -5,16 +5,20 @@ use crate::{
     explosion,
     game_data::GameData,
     iron,
+    power_up::{self, PowerType, PowerUp},
     shield::Shield,
+    simulation::GameRng,
     state::State,
-    tank::Tank,
+    tank::{self, player, Tank},
     texture::Textures,
-    utils::Owner,
+    utils::{block2translation, Owner},
+    versus::{self, Match},
 };
 use bevy::{
     math::{const_vec2, const_vec3},
     prelude::*,
 };
+use rand::Rng;
 
 pub struct DestroyAllEnemies {
     pub by: Owner,
@@ -36,7 +40,7 @@ pub fn handle_destroy_all_enemies(
                     textures.texture.clone(),
                     transform.translation,
                     true,
-                )
+                );
             }
         }
 
@@ -46,3 +50,71 @@ pub fn handle_destroy_all_enemies(
         break;
     }
 }
+
+/// A tank is destroyed by a bullet
+#[derive(Clone)]
+pub struct TankDestroyed {
+    pub tank: Owner,
+    pub level: u8,
+    pub bonus: bool,
+    pub by: Owner,
+}
+
+/// Scores players' kills, drops power ups of bonus tanks and brings players' tanks back while
+/// they have lifes left, or always in versus
+pub fn handle_tank_destroyed(
+    mut commands: Commands,
+    mut event_reader: EventReader<TankDestroyed>,
+    textures: Res<Textures>,
+    mut game_data: ResMut<GameData>,
+    mut rng: ResMut<GameRng>,
+    power_ups: Query<Entity, With<PowerUp>>,
+    versus: Option<Res<Match>>,
+) {
+    for event in event_reader.iter() {
+        if event.tank == Owner::AI {
+            let points = 100 * (event.level as u32 + 1);
+            if let Some(player) = event.by.player {
+                game_data.scores[player as usize] += points;
+            }
+            if event.bonus {
+                // only one power up on battle field at a time
+                for entity in power_ups.iter() {
+                    commands.entity(entity).despawn();
+                }
+                let block = Vec2::new(rng.gen_range(-6..=6) as f32, rng.gen_range(-6..=6) as f32);
+                power_up::spawn(
+                    &mut commands,
+                    block2translation(block, 0.),
+                    PowerType::random(&mut *rng),
+                    textures.texture.clone(),
+                );
+            }
+        }
+
+        let id = match event.tank.player {
+            Some(id) => id,
+            None => continue,
+        };
+        let lifes = &mut game_data.lifes[id as usize];
+        if versus.is_some() {
+            // players' tanks always come back, on their teams' sides of battle field
+            tank::spawn(
+                &mut commands,
+                textures.texture.clone(),
+                versus::spawn_position(event.tank),
+                event.tank,
+                0,
+            );
+        } else if *lifes > 0 {
+            *lifes -= 1;
+            tank::spawn(
+                &mut commands,
+                textures.texture.clone(),
+                player::SPAWN_POSITIONS[id as usize],
+                event.tank,
+                0,
+            );
+        }
+    }
+}
@@ -1,13 +1,24 @@
 use bevy::prelude::*;
 
-use crate::{consts::SCALE, texture::SpriteIndex};
+use crate::{consts::SCALE, simulation::TICK, texture::SpriteIndex};
 
-#[derive(Component)]
+#[derive(Component, Clone)]
 pub struct Explosion {
     is_big: bool,
 }
 
-pub fn spawn(commands: &mut Commands, texture: Handle<TextureAtlas>, position: Vec3, is_big: bool) {
+impl Explosion {
+    pub fn is_big(&self) -> bool {
+        self.is_big
+    }
+}
+
+pub fn spawn(
+    commands: &mut Commands,
+    texture: Handle<TextureAtlas>,
+    position: Vec3,
+    is_big: bool,
+) -> Entity {
     commands
         .spawn_bundle(SpriteSheetBundle {
             sprite: TextureAtlasSprite::new(SpriteIndex::EXPLOSION[0]),
@@ -20,16 +31,16 @@ pub fn spawn(commands: &mut Commands, texture: Handle<TextureAtlas>, position: V
             ..Default::default()
         })
         .insert(Explosion { is_big })
-        .insert(Timer::from_seconds(0.1, true));
+        .insert(Timer::from_seconds(0.1, true))
+        .id()
 }
 
 pub fn explode(
-    time: Res<Time>,
     mut commands: Commands,
     mut explosions: Query<(&mut Timer, &mut TextureAtlasSprite, Entity, &Explosion)>,
 ) {
     for (mut timer, mut sprite, entity, explosion) in explosions.iter_mut() {
-        if timer.tick(time.delta()).just_finished() {
+        if timer.tick(TICK).just_finished() {
             match sprite.index {
                 289 => {
                     if explosion.is_big {
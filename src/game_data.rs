@@ -1,22 +1,19 @@
 use std::time::Duration;
 
 use crate::{
-    base::Base, brick, bullet::Bullet, power_up, shield::Shield, tank::Tank, texture::Textures,
-    utils,
+    base::Base, brick, bullet::Bullet, power_up, shield::Shield, star::Star, state,
+    survival::Waves, tank::Tank, texture::Textures, utils, versus::Match,
 };
 use bevy::prelude::*;
 
+#[derive(Clone)]
 pub struct GameData {
     // ai related data
     pub ai_tanks: u8, // the number of ai tanks left
 
-    // p1 related data
-    pub p1: u8, // P1's lifes
-    pub p1_score: u32,
-
-    // p2 related data
-    pub p2: u8, // P2's lifes
-    pub p2_score: u32,
+    // players related data, by player ID
+    pub lifes: [u8; utils::MAX_PLAYERS],
+    pub scores: [u32; utils::MAX_PLAYERS],
 
     // Shovel power up related data
     pub restore_timer: Timer,
@@ -24,21 +21,54 @@ pub struct GameData {
     pub base_wall_changed: bool,
     pub base_wall_changed_by: utils::Owner,
     pub base_wall_normal: bool,
+
+    pub base_destroyed: bool,
+    pub stage: u8,
 }
 
 impl GameData {
     pub fn new() -> Self {
         Self {
             ai_tanks: 20,
-            p1: 2,
-            p1_score: 0,
-            p2: 0,
-            p2_score: 0,
+            lifes: [2, 0, 0, 0],
+            scores: [0; utils::MAX_PLAYERS],
             restore_timer: Timer::new(power_up::SHOVEL_DURATION, false),
             blink_timer: Timer::new(Duration::from_millis(500), true),
             base_wall_changed: false,
             base_wall_changed_by: utils::Owner::P1,
             base_wall_normal: true,
+            base_destroyed: false,
+            stage: 1,
         }
     }
 }
+
+/// The base is destroyed, all enemies are destroyed or players have no tanks left, or a player
+/// won the versus match. Enemies never run out in survival.
+pub fn is_over(world: &mut World) -> bool {
+    if let Some(versus) = world.get_resource::<Match>() {
+        return versus.winner().is_some();
+    }
+    let survival = world.contains_resource::<Waves>();
+    let on_field = on_field(world);
+    let game_data = world.get_resource::<GameData>().unwrap();
+    game_data.base_destroyed
+        || (!survival && !on_field[utils::MAX_PLAYERS] && game_data.ai_tanks == 0)
+        || (0..utils::MAX_PLAYERS).all(|player| !on_field[player] && game_data.lifes[player] == 0)
+}
+
+/// If every player and AI, the last one, have a tank, or a star which is going to spawn a tank,
+/// on battle field
+fn on_field(world: &mut World) -> [bool; utils::MAX_PLAYERS + 1] {
+    let index = |owner: utils::Owner| owner.player.map_or(utils::MAX_PLAYERS, usize::from);
+    let mut result = [false; utils::MAX_PLAYERS + 1];
+    let mut tanks = world.query_filtered::<&state::State, With<Tank>>();
+    for state in tanks.iter(world) {
+        result[index(state.as_tank().owner)] = true;
+    }
+    let mut stars = world.query::<&Star>();
+    for star in stars.iter(world) {
+        result[index(star.owner())] = true;
+    }
+    result
+}
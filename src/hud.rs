@@ -0,0 +1,80 @@
+//! Players' lifes left, drawn in the grey panel right of battle field: a small tank in the
+//! player's colour, then two digits.
+
+use bevy::prelude::*;
+
+use crate::{
+    config::Config,
+    consts::SCALE,
+    game_data::GameData,
+    menu::AppState,
+    texture::{SpriteIndex, Textures},
+    utils::{get_sprite, Direction, PLAYER_COLORS},
+};
+
+pub struct HudPlugin;
+
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(SystemSet::on_enter(AppState::Playing).with_system(spawn))
+            .add_system_set(SystemSet::on_update(AppState::Playing).with_system(update));
+    }
+}
+
+/// A digit of a player's lifes, the tens or the units
+#[derive(Component)]
+pub struct LifesDigit {
+    player: usize,
+    tens: bool,
+}
+
+/// One row per player, P1's at the top
+pub fn spawn(mut commands: Commands, textures: Res<Textures>, config: Res<Config>) {
+    for player in 0..config.players {
+        let y = (-16. - 16. * player as f32) * SCALE;
+        commands.spawn_bundle(SpriteSheetBundle {
+            sprite: TextureAtlasSprite {
+                color: PLAYER_COLORS[player],
+                ..TextureAtlasSprite::new(get_sprite(
+                    config.teams.owner(player as u8),
+                    0,
+                    Direction::Up,
+                ))
+            },
+            texture_atlas: textures.texture.clone(),
+            transform: Transform {
+                translation: Vec3::new(102. * SCALE, y, 1.),
+                scale: Vec3::splat(SCALE / 2.),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        for (x, tens) in [(112., true), (120., false)] {
+            commands
+                .spawn_bundle(SpriteSheetBundle {
+                    sprite: TextureAtlasSprite::new(SpriteIndex::DIGITS[0]),
+                    texture_atlas: textures.texture.clone(),
+                    transform: Transform {
+                        translation: Vec3::new(x * SCALE, y, 1.),
+                        scale: Vec3::splat(SCALE),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .insert(LifesDigit { player, tens });
+        }
+    }
+}
+
+pub fn update(game_data: Res<GameData>, mut digits: Query<(&LifesDigit, &mut TextureAtlasSprite)>) {
+    if !game_data.is_changed() {
+        return;
+    }
+    for (digit, mut sprite) in digits.iter_mut() {
+        let lifes = game_data.lifes[digit.player].min(99) as usize;
+        sprite.index = match digit.tens {
+            true => SpriteIndex::DIGITS[lifes / 10],
+            false => SpriteIndex::DIGITS[lifes % 10],
+        };
+    }
+}
@@ -0,0 +1,511 @@
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use bevy::{app::AppExit, prelude::*};
+
+use crate::{
+    config::{Config, Difficulty},
+    net::{self, Lockstep, Session},
+    rollback::{self, Rollback},
+    simulation::{AddSimulationSystem, Clock, GameRng, SimulationStage},
+    survival::{self, Waves},
+    tank::{p2, player},
+    team::{FriendlyFire, Teams},
+    utils::{Direction, MAX_PLAYERS},
+    versus::{self, Match},
+};
+
+const REPLAY_MAGIC: &[u8; 4] = b"BCRP";
+const REPLAY_VERSION: u8 = 6;
+const REPLAY_HEADER: usize = 46; // bytes before the runs of ticks
+const STICK_THRESHOLD: f32 = 0.5; // how far a gamepad's stick is pushed to press a direction
+const FAST_FORWARD_SPEEDS: [u32; 4] = [1, 2, 4, 8];
+
+/// Buttons of one player held down during a tick, one bit per button
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TankInput(u8);
+
+impl TankInput {
+    pub const UP: u8 = 1;
+    pub const RIGHT: u8 = 1 << 1;
+    pub const DOWN: u8 = 1 << 2;
+    pub const LEFT: u8 = 1 << 3;
+    pub const FIRE: u8 = 1 << 4;
+
+    pub fn from_bits(bits: u8) -> Self {
+        Self(bits & (Self::UP | Self::RIGHT | Self::DOWN | Self::LEFT | Self::FIRE))
+    }
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn set(&mut self, button: u8, pressed: bool) {
+        if pressed {
+            self.0 |= button;
+        } else {
+            self.0 &= !button;
+        }
+    }
+
+    pub fn press(&mut self, direction: Direction) {
+        self.0 |= direction_bit(direction);
+    }
+
+    pub fn direction(self, direction: Direction) -> bool {
+        self.0 & direction_bit(direction) != 0
+    }
+
+    pub fn fire(self) -> bool {
+        self.0 & Self::FIRE != 0
+    }
+
+    /// Reads player's buttons from the keyboard, keys are in the order of up, right, down, left
+    pub fn from_keyboard(
+        keyboard: &Input<KeyCode>,
+        direction_keys: &[KeyCode; 4],
+        fire: KeyCode,
+    ) -> Self {
+        // a key can be pressed and released in the same frame
+        let held = |key: KeyCode| keyboard.pressed(key) || keyboard.just_pressed(key);
+        let mut input = Self::default();
+        input.set(Self::UP, held(direction_keys[0]));
+        input.set(Self::RIGHT, held(direction_keys[1]));
+        input.set(Self::DOWN, held(direction_keys[2]));
+        input.set(Self::LEFT, held(direction_keys[3]));
+        input.set(Self::FIRE, held(fire));
+        input
+    }
+
+    /// Reads player's buttons from a gamepad, its d-pad or its left stick, and its south button
+    pub fn from_gamepad(
+        buttons: &Input<GamepadButton>,
+        axes: &Axis<GamepadAxis>,
+        gamepad: Gamepad,
+    ) -> Self {
+        let held = |button| buttons.pressed(GamepadButton(gamepad, button));
+        let axis = |axis| axes.get(GamepadAxis(gamepad, axis)).unwrap_or_default();
+        let (x, y) = (
+            axis(GamepadAxisType::LeftStickX),
+            axis(GamepadAxisType::LeftStickY),
+        );
+        let mut input = Self::default();
+        input.set(
+            Self::UP,
+            held(GamepadButtonType::DPadUp) || y > STICK_THRESHOLD,
+        );
+        input.set(
+            Self::RIGHT,
+            held(GamepadButtonType::DPadRight) || x > STICK_THRESHOLD,
+        );
+        input.set(
+            Self::DOWN,
+            held(GamepadButtonType::DPadDown) || y < -STICK_THRESHOLD,
+        );
+        input.set(
+            Self::LEFT,
+            held(GamepadButtonType::DPadLeft) || x < -STICK_THRESHOLD,
+        );
+        input.set(Self::FIRE, held(GamepadButtonType::South));
+        input
+    }
+}
+
+fn direction_bit(direction: Direction) -> u8 {
+    match direction {
+        Direction::Up => TankInput::UP,
+        Direction::Right => TankInput::RIGHT,
+        Direction::Down => TankInput::DOWN,
+        Direction::Left => TankInput::LEFT,
+    }
+}
+
+/// Players' inputs of the current tick and the previous one.
+///
+/// Tank systems read inputs from here instead of the keyboard, so a tick only depends on
+/// what is pushed here, no matter it comes from a keyboard, a replay or a program.
+#[derive(Default, Clone)]
+pub struct PlayerInputs {
+    current: [TankInput; MAX_PLAYERS],
+    previous: [TankInput; MAX_PLAYERS],
+}
+
+impl PlayerInputs {
+    /// Inputs of a saved tick, held since the tick before, so nothing is just pressed when the
+    /// game resumes with the same buttons held
+    pub fn resumed(inputs: [TankInput; MAX_PLAYERS]) -> Self {
+        Self {
+            current: inputs,
+            previous: inputs,
+        }
+    }
+
+    /// Inputs of the next tick, by player ID, index 0 is P1
+    pub fn push(&mut self, inputs: [TankInput; MAX_PLAYERS]) {
+        self.previous = self.current;
+        self.current = inputs;
+    }
+
+    pub fn current(&self) -> [TankInput; MAX_PLAYERS] {
+        self.current
+    }
+
+    /// Replaces one player's input of the current tick, e.g. when the player is driven by AI
+    pub fn set_current(&mut self, player: usize, input: TankInput) {
+        self.current[player] = input;
+    }
+
+    pub fn pressed(&self, player: usize, direction: Direction) -> bool {
+        self.current[player].direction(direction)
+    }
+
+    pub fn just_pressed(&self, player: usize, direction: Direction) -> bool {
+        self.current[player].direction(direction) && !self.previous[player].direction(direction)
+    }
+
+    pub fn just_fired(&self, player: usize) -> bool {
+        self.current[player].fire() && !self.previous[player].fire()
+    }
+}
+
+/// A recorded game: the seed, the difficulty, the game's options and players' inputs of every tick
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Replay {
+    pub seed: u64,
+    pub difficulty: Difficulty,
+    pub players: u8,
+    pub teams: Teams,
+    pub friendly_fire: FriendlyFire,
+    pub versus: Option<versus::Rules>,
+    pub survival: Option<survival::Rules>,
+    pub frames: Vec<[TankInput; MAX_PLAYERS]>,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        Self::from_bytes(&bytes)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        File::create(path)?.write_all(&self.to_bytes())
+    }
+
+    /// magic, version, seed, difficulty, number of players, every player's team, friendly fire,
+    /// versus as 1 and its rules (rounds, kills) or 0 and two unused bytes, survival as 1 and its
+    /// rules (first wave u8, growth u8, ratio step f64, regrowth in nanoseconds u64) or 0 and 18
+    /// unused bytes, number of ticks, then runs of identical ticks as (every player's input from
+    /// P1's, run length), numbers are little endian
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(REPLAY_MAGIC);
+        bytes.push(REPLAY_VERSION);
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        bytes.push(self.difficulty as u8);
+        bytes.push(self.players);
+        bytes.extend_from_slice(&self.teams.0);
+        bytes.push(self.friendly_fire as u8);
+        match self.versus {
+            Some(rules) => bytes.extend_from_slice(&[1, rules.rounds, rules.kills]),
+            None => bytes.extend_from_slice(&[0; 3]),
+        }
+        match self.survival {
+            Some(rules) => {
+                bytes.extend_from_slice(&[1, rules.first_wave, rules.growth]);
+                bytes.extend_from_slice(&rules.ratio_step.to_le_bytes());
+                bytes.extend_from_slice(&(rules.regrowth.as_nanos() as u64).to_le_bytes());
+            }
+            None => bytes.extend_from_slice(&[0; 19]),
+        }
+        bytes.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        let mut i = 0;
+        while i < self.frames.len() {
+            let frame = self.frames[i];
+            let mut run = 1;
+            while i + run < self.frames.len()
+                && self.frames[i + run] == frame
+                && run < u16::MAX as usize
+            {
+                run += 1;
+            }
+            bytes.extend(frame.iter().map(|input| input.bits()));
+            bytes.extend_from_slice(&(run as u16).to_le_bytes());
+            i += run;
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        if bytes.len() < REPLAY_HEADER || &bytes[..4] != REPLAY_MAGIC {
+            return Err(invalid("not a replay file"));
+        }
+        if bytes[4] != REPLAY_VERSION {
+            return Err(invalid("unsupported replay version"));
+        }
+        let seed = u64::from_le_bytes(bytes[5..13].try_into().unwrap());
+        let difficulty = *Difficulty::ALL
+            .get(bytes[13] as usize)
+            .ok_or_else(|| invalid("unknown difficulty"))?;
+        let players = bytes[14];
+        if players == 0 || players as usize > MAX_PLAYERS {
+            return Err(invalid("unsupported number of players"));
+        }
+        let teams = Teams(bytes[15..19].try_into().unwrap());
+        let friendly_fire = match bytes[19] {
+            0 => FriendlyFire::Off,
+            1 => FriendlyFire::On,
+            _ => return Err(invalid("unknown friendly fire")),
+        };
+        let versus = match bytes[20] {
+            0 => None,
+            1 => Some(versus::Rules {
+                rounds: bytes[21],
+                kills: bytes[22],
+            }),
+            _ => return Err(invalid("unknown versus")),
+        };
+        let survival = match bytes[23] {
+            0 => None,
+            1 => Some(survival::Rules {
+                first_wave: bytes[24],
+                growth: bytes[25],
+                ratio_step: f64::from_le_bytes(bytes[26..34].try_into().unwrap()),
+                regrowth: Duration::from_nanos(u64::from_le_bytes(
+                    bytes[34..42].try_into().unwrap(),
+                )),
+            }),
+            _ => return Err(invalid("unknown survival")),
+        };
+        let ticks = u32::from_le_bytes(bytes[42..REPLAY_HEADER].try_into().unwrap()) as usize;
+        // a run is u16::MAX ticks at most, so a count of ticks the runs can't hold is not trusted
+        // to allocate for
+        let runs = bytes[REPLAY_HEADER..].len() / (MAX_PLAYERS + 2);
+        if ticks > runs * u16::MAX as usize {
+            return Err(invalid("corrupted replay file"));
+        }
+        let mut frames = Vec::with_capacity(ticks);
+        for run in bytes[REPLAY_HEADER..].chunks(MAX_PLAYERS + 2) {
+            if run.len() != MAX_PLAYERS + 2 {
+                return Err(invalid("truncated replay file"));
+            }
+            let frame = std::array::from_fn(|player| TankInput::from_bits(run[player]));
+            let count = u16::from_le_bytes([run[MAX_PLAYERS], run[MAX_PLAYERS + 1]]) as usize;
+            if frames.len() + count > ticks {
+                return Err(invalid("corrupted replay file"));
+            }
+            frames.extend(std::iter::repeat(frame).take(count));
+        }
+        if frames.len() != ticks {
+            return Err(invalid("corrupted replay file"));
+        }
+        Ok(Self {
+            seed,
+            difficulty,
+            players,
+            teams,
+            friendly_fire,
+            versus,
+            survival,
+            frames,
+        })
+    }
+}
+
+/// Where players' inputs come from
+#[derive(Clone)]
+pub enum InputSource {
+    Keyboard,
+    Record(PathBuf), // keyboard, saved to the file when the game exits
+    Replay(Replay),
+    External,         // pushed to `PlayerInputs` by the program driving the game
+    Network(Session), // the local player's keyboard and the other peer's inputs in lockstep
+}
+
+pub struct PlayerInputPlugin {
+    pub source: InputSource,
+    pub ally: bool, // P2 is driven by a friendly AI
+}
+
+impl Plugin for PlayerInputPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PlayerInputs>();
+        match &self.source {
+            InputSource::Keyboard | InputSource::Record(_) => {
+                app.add_simulation_system(keyboard);
+            }
+            InputSource::Replay(replay) => {
+                // the replay stops at its last tick
+                let mut clock = app.world.get_resource_mut::<Clock>().unwrap();
+                clock.limit = Some(replay.frames.len() as u64);
+                app.insert_resource(Playback {
+                    replay: replay.clone(),
+                    speed: 0,
+                })
+                .add_simulation_system(playback)
+                .add_system(playback_controls);
+            }
+            InputSource::Network(session) if session.rollback => {
+                // ticks run ahead of the other peer's inputs, but not too far
+                let mut clock = app.world.get_resource_mut::<Clock>().unwrap();
+                clock.limit = Some(session.delay + rollback::MAX_PREDICTION);
+                app.insert_resource(Rollback::new(session.clone()))
+                    .add_system_to_stage(CoreStage::PreUpdate, rollback::receive.exclusive_system())
+                    .add_system_to_stage(
+                        SimulationStage,
+                        rollback::save_snapshot.exclusive_system().at_start(),
+                    )
+                    .add_simulation_system(rollback::inputs)
+                    .add_system_to_stage(CoreStage::PostUpdate, rollback::send);
+            }
+            InputSource::Network(session) => {
+                // ticks wait for the other peer's inputs, except the first ones without any
+                let mut clock = app.world.get_resource_mut::<Clock>().unwrap();
+                clock.limit = Some(session.delay);
+                app.insert_resource(Lockstep::new(session.clone()))
+                    .add_system_to_stage(CoreStage::PreUpdate, net::receive)
+                    .add_simulation_system(net::exchange)
+                    .add_system_to_stage(CoreStage::PostUpdate, net::send);
+            }
+            InputSource::External => (),
+        }
+        // the ally's inputs are recorded, so a replay does not need the ally
+        if self.ally && !matches!(self.source, InputSource::Replay(_)) {
+            app.init_resource::<p2::Ally>()
+                .add_simulation_system(p2::ally);
+        }
+        if let InputSource::Record(path) = &self.source {
+            app.insert_resource(Recorder {
+                path: path.clone(),
+                replay: Replay::default(),
+            })
+            .add_simulation_system(record)
+            .add_system_to_stage(CoreStage::Last, save_record);
+        }
+    }
+}
+
+/// Samples the keyboard and gamepads at the beginning of a tick, gamepad N drives player N
+pub fn keyboard(
+    keyboard: Res<Input<KeyCode>>,
+    buttons: Res<Input<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+    mut inputs: ResMut<PlayerInputs>,
+) {
+    inputs.push(std::array::from_fn(|player| {
+        let keys = TankInput::from_keyboard(
+            &keyboard,
+            &player::DIRECTION_KEYS[player],
+            player::FIRE_KEYS[player],
+        );
+        let gamepad = TankInput::from_gamepad(&buttons, &axes, Gamepad(player));
+        TankInput::from_bits(keys.bits() | gamepad.bits())
+    }));
+}
+
+pub struct Recorder {
+    path: PathBuf,
+    replay: Replay,
+}
+
+pub fn record(inputs: Res<PlayerInputs>, mut recorder: ResMut<Recorder>) {
+    recorder.replay.frames.push(inputs.current());
+}
+
+pub fn save_record(
+    mut exit_events: EventReader<AppExit>,
+    rng: Res<GameRng>,
+    config: Res<Config>,
+    friendly_fire: Res<FriendlyFire>,
+    versus: Option<Res<Match>>,
+    waves: Option<Res<Waves>>,
+    mut recorder: ResMut<Recorder>,
+) {
+    if exit_events.iter().next().is_none() {
+        return;
+    }
+    recorder.replay.seed = rng.seed();
+    recorder.replay.difficulty = config.difficulty;
+    recorder.replay.players = config.players as u8;
+    recorder.replay.teams = config.teams;
+    recorder.replay.friendly_fire = *friendly_fire;
+    recorder.replay.versus = versus.map(|versus| versus.rules);
+    recorder.replay.survival = waves.map(|waves| waves.rules);
+    match recorder.replay.save(&recorder.path) {
+        Ok(()) => info!("replay saved to {}", recorder.path.display()),
+        Err(err) => error!("failed to save replay: {}", err),
+    }
+}
+
+pub struct Playback {
+    replay: Replay,
+    speed: usize, // index of FAST_FORWARD_SPEEDS
+}
+
+/// Feeds recorded inputs to the tick being simulated
+pub fn playback(clock: Res<Clock>, playback: Res<Playback>, mut inputs: ResMut<PlayerInputs>) {
+    // ticks count from 1
+    if let Some(frame) = playback.replay.frames.get(clock.tick as usize - 1) {
+        inputs.push(*frame);
+    }
+}
+
+/// Space pauses, F changes fast forward speed and "." steps a single tick while paused
+pub fn playback_controls(
+    keyboard: Res<Input<KeyCode>>,
+    mut playback: ResMut<Playback>,
+    mut clock: ResMut<Clock>,
+) {
+    if keyboard.just_pressed(KeyCode::Space) {
+        clock.paused = !clock.paused;
+    }
+    if keyboard.just_pressed(KeyCode::F) {
+        playback.speed = (playback.speed + 1) % FAST_FORWARD_SPEEDS.len();
+        clock.speed = FAST_FORWARD_SPEEDS[playback.speed];
+    }
+    if keyboard.just_pressed(KeyCode::Period) && clock.paused {
+        clock.step();
+    }
+}
+
+#[test]
+fn test_replay_bytes() {
+    let mut up = TankInput::default();
+    up.set(TankInput::UP, true);
+    let mut fire = up;
+    fire.set(TankInput::FIRE, true);
+    let replay = Replay {
+        seed: 42,
+        difficulty: Difficulty::Hard,
+        players: 4,
+        teams: Teams::FREE_FOR_ALL,
+        friendly_fire: FriendlyFire::On,
+        versus: Some(versus::Rules {
+            rounds: 5,
+            kills: 3,
+        }),
+        survival: Some(survival::Rules {
+            first_wave: 6,
+            ..Default::default()
+        }),
+        frames: vec![
+            [TankInput::default(), up, TankInput::default(), fire],
+            [TankInput::default(), up, TankInput::default(), fire],
+            [fire, TankInput::default(), up, TankInput::default()],
+        ],
+    };
+    let bytes = replay.to_bytes();
+    assert_eq!(bytes.len(), REPLAY_HEADER + 2 * (MAX_PLAYERS + 2));
+    assert_eq!(Replay::from_bytes(&bytes).unwrap(), replay);
+
+    // a broken file is an error, never a huge allocation
+    let mut broken = bytes.clone();
+    broken[42..REPLAY_HEADER].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(Replay::from_bytes(&broken).is_err());
+    assert!(Replay::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+}
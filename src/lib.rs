@@ -0,0 +1,139 @@
+use std::path::PathBuf;
+
+use bevy::{app::PluginGroupBuilder, prelude::*};
+
+pub mod base;
+pub mod brick;
+pub mod bullet;
+pub mod collision;
+pub mod config;
+pub mod consts;
+pub mod env;
+pub mod event;
+pub mod explosion;
+pub mod game_data;
+pub mod grass;
+pub mod hud;
+pub mod input;
+pub mod iron;
+pub mod menu;
+pub mod net;
+pub mod pathfinding;
+pub mod power_up;
+pub mod river;
+pub mod rollback;
+pub mod save;
+pub mod server;
+pub mod setup;
+pub mod shield;
+pub mod simulation;
+pub mod snapshot;
+pub mod snow;
+pub mod spatial;
+pub mod spectator;
+pub mod stage;
+pub mod star;
+pub mod state;
+pub mod survival;
+pub mod tank;
+pub mod team;
+pub mod terrain;
+pub mod texture;
+pub mod utils;
+pub mod versus;
+
+/// Every plugin of the game, added on top of bevy's `DefaultPlugins` or `MinimalPlugins`.
+///
+/// A single subsystem can be replaced by disabling its plugin and adding your own systems:
+/// `app.add_plugins_with(BattleCityPlugins { .. }, |group| group.disable::<TankPlugin>())`
+///
+/// When headless, every `App::update` simulates exactly one tick, so simulations are not limited
+/// by the wall clock, and the game starts without the title menu.
+pub struct BattleCityPlugins {
+    pub headless: bool,
+    pub seed: u64,
+    pub difficulty: config::Difficulty,
+    pub input: input::InputSource,
+    pub ally: bool,                    // P2 is driven by a friendly AI
+    pub save: Option<PathBuf>,         // where the game is saved when it exits and continued from
+    pub players: usize,                // tanks played, 1 to `utils::MAX_PLAYERS`
+    pub versus: Option<versus::Rules>, // P1's team against P2's team, no AI
+    pub teams: team::Teams,            // two teams at most in versus
+    pub friendly_fire: team::FriendlyFire,
+    pub survival: Option<survival::Rules>, // AI tanks in waves without end
+}
+
+impl PluginGroup for BattleCityPlugins {
+    fn build(&mut self, group: &mut PluginGroupBuilder) {
+        group
+            .add(simulation::SimulationPlugin {
+                seed: self.seed,
+                realtime: !self.headless,
+            })
+            .add(spatial::SpatialPlugin)
+            .add(input::PlayerInputPlugin {
+                source: self.input.clone(),
+                ally: self.ally,
+            })
+            .add(setup::SetupPlugin {
+                headless: self.headless,
+                difficulty: self.difficulty,
+                players: self.players,
+                teams: self.teams,
+                friendly_fire: self.friendly_fire,
+            })
+            .add(menu::MenuPlugin {
+                // a replay or a network game starts right away with its own difficulty
+                enabled: !self.headless
+                    && matches!(
+                        self.input,
+                        input::InputSource::Keyboard | input::InputSource::Record(_)
+                    ),
+            })
+            .add(stage::StagePlugin)
+            .add(tank::TankPlugin)
+            .add(bullet::BulletPlugin)
+            .add(power_up::PowerUpPlugin)
+            .add(base::BasePlugin)
+            // last, so entities of terrain follow every change to the tile map in the tick
+            .add(terrain::TerrainPlugin);
+        if !self.headless {
+            group.add(hud::HudPlugin);
+        }
+        if let Some(rules) = self.versus {
+            assert!(self.teams.two_sided(), "versus is played by two teams");
+            group.add(versus::VersusPlugin { rules });
+        }
+        if let Some(rules) = self.survival {
+            assert!(self.versus.is_none(), "survival is played against AI");
+            // a replay is not a game of its own, so it does not go into the high-score table again
+            let replay = matches!(self.input, input::InputSource::Replay(_));
+            group.add(survival::SurvivalPlugin {
+                rules,
+                high_scores: (!self.headless && !replay)
+                    .then(|| PathBuf::from(survival::HIGH_SCORES_PATH)),
+            });
+        }
+        if let Some(path) = &self.save {
+            group.add(save::SavePlugin { path: path.clone() });
+        }
+    }
+}
+
+pub use base::BasePlugin;
+pub use bullet::BulletPlugin;
+pub use hud::HudPlugin;
+pub use input::PlayerInputPlugin;
+pub use menu::MenuPlugin;
+pub use power_up::PowerUpPlugin;
+pub use save::SavePlugin;
+pub use server::StreamPlugin;
+pub use setup::SetupPlugin;
+pub use simulation::SimulationPlugin;
+pub use spatial::SpatialPlugin;
+pub use spectator::SpectatorPlugin;
+pub use stage::StagePlugin;
+pub use survival::SurvivalPlugin;
+pub use tank::TankPlugin;
+pub use terrain::TerrainPlugin;
+pub use versus::VersusPlugin;
@@ -1,239 +1,222 @@
-use bevy::prelude::*;
+use std::{path::PathBuf, time::Duration};
 
-mod base;
-mod brick;
-mod bullet;
-mod collision;
-mod consts;
-mod event;
-mod explosion;
-mod game_data;
-mod grass;
-mod iron;
-mod power_up;
-mod river;
-mod shield;
-mod snow;
-mod star;
-mod state;
-mod tank;
-mod texture;
-mod utils;
-use brick::BrickType;
-use collision::Collider;
-use consts::{
-    BATTLE_FIELD_WIDTH, BLOCK_WIDTH, HALF_BLOCK_WIDTH, SCALE, WINDOW_HEIGHT, WINDOW_WIDTH,
+use battle_city::{
+    consts::{WINDOW_HEIGHT, WINDOW_WIDTH},
+    input::{InputSource, Replay},
+    net::{self, AskedOptions, Conditions, GameOptions, Session},
+    save::SAVE_PATH,
+    server::{Client, Clients, StreamPlugin},
+    spectator::{Spectator, SpectatorPlugin},
+    survival,
+    team::{FriendlyFire, Teams},
+    utils::MAX_PLAYERS,
+    versus::Rules,
+    BattleCityPlugins,
 };
-use game_data::GameData;
-use tank::{ai, p1, p2};
-use texture::{load_texture_atlas, Textures};
-use utils::block2translation as b2t;
+use bevy::{input::InputPlugin, log::LogPlugin, prelude::*};
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(addr) = arg_value(&args, "--spectate") {
+        // watch a game of a server, or of a game streaming with `--stream`
+        let client = Client::connect(addr).expect("failed to connect");
+        App::new()
+            .insert_resource(window())
+            .insert_resource(ClearColor(Color::BLACK))
+            .insert_resource(Spectator::new(client))
+            .add_plugins(DefaultPlugins)
+            .add_plugin(SpectatorPlugin)
+            .run();
+        return;
+    }
+    let headless = args.iter().any(|arg| arg == "--headless");
+    // tanks played on this machine, P3 and P4 with the numpad and the keys above the arrows, or
+    // with gamepads
+    let mut players = match arg_value(&args, "--players") {
+        Some(players) => players.parse().expect("players must be 1 to 4"),
+        None => 2,
+    };
+    assert!(
+        (1..=MAX_PLAYERS).contains(&players),
+        "players must be 1 to 4"
+    );
+    // a friendly AI plays P2, e.g. for a single player
+    let ally = args.iter().any(|arg| arg == "--ally");
+    // options asked for on the command line, those not given are left to the host when joining
+    let asked = AskedOptions {
+        // the same seed and the same inputs always play the same game
+        seed: arg_value(&args, "--seed")
+            .map(|seed| seed.parse().expect("seed must be an unsigned integer")),
+        difficulty: arg_value(&args, "--difficulty").map(|difficulty| {
+            difficulty
+                .parse()
+                .expect("difficulty must be easy, normal, hard or nightmare")
+        }),
+        // who is whose enemy, P1's team against P2's team in versus, e.g. `--teams ffa` or
+        // `--teams 0011` for P1 and P2 against P3 and P4
+        teams: arg_value(&args, "--teams").map(|teams| {
+            teams
+                .parse()
+                .expect("teams must be coop, versus, ffa or a team per player like 0011")
+        }),
+        // if bullets hurt teammates
+        friendly_fire: args
+            .iter()
+            .any(|arg| arg == "--friendly-fire")
+            .then(|| FriendlyFire::On),
+        // P1 against P2, best of `--versus` rounds, a round is also won by `--kills` kills
+        versus: arg_value(&args, "--versus").map(|rounds| {
+            let mut rules = Rules {
+                rounds: rounds.parse().expect("rounds must be an unsigned integer"),
+                ..Default::default()
+            };
+            if let Some(kills) = arg_value(&args, "--kills") {
+                rules.kills = kills.parse().expect("kills must be an unsigned integer");
+            }
+            rules
+        }),
+        // waves of AI tanks without end, the score is the number of waves survived
+        survival: args
+            .iter()
+            .any(|arg| arg == "--survival")
+            .then(survival::Rules::default),
+    };
+    let mut options = GameOptions {
+        seed: asked.seed.unwrap_or_else(rand::random),
+        difficulty: asked.difficulty.unwrap_or_default(),
+        teams: match asked.teams {
+            Some(teams) => teams,
+            None if asked.versus.is_some() => Teams::VERSUS,
+            None => Teams::COOP,
+        },
+        friendly_fire: asked.friendly_fire.unwrap_or(FriendlyFire::Off),
+        versus: asked.versus,
+        survival: asked.survival,
+    };
+    let input = if let Some(path) = arg_value(&args, "--replay") {
+        let replay = Replay::load(path.as_ref()).expect("failed to load replay");
+        options = GameOptions {
+            seed: replay.seed,
+            difficulty: replay.difficulty,
+            teams: replay.teams,
+            friendly_fire: replay.friendly_fire,
+            versus: replay.versus,
+            survival: replay.survival,
+        };
+        players = replay.players as usize;
+        InputSource::Replay(replay)
+    } else if let Some(port) = arg_value(&args, "--host") {
+        // a game over network, the host plays P1 and chooses the game's options and lockstep or
+        // rollback
+        let port = port.parse().expect("port must be an unsigned integer");
+        let rollback = args.iter().any(|arg| arg == "--rollback");
+        let delay = match arg_value(&args, "--delay") {
+            Some(delay) => delay.parse().expect("delay must be a number of ticks"),
+            None if rollback => net::DEFAULT_ROLLBACK_DELAY,
+            None => net::DEFAULT_DELAY,
+        };
+        println!("waiting for a player to join on port {}", port);
+        let mut session = Session::host(port, options, delay, rollback).expect("failed to host");
+        session.conditions = conditions(&args);
+        players = 2;
+        InputSource::Network(session)
+    } else if let Some(addr) = arg_value(&args, "--join") {
+        // the player joining plays P2, in the game the host chose
+        println!("joining {}", addr);
+        let mut session = Session::join(addr, &asked).expect("failed to join");
+        session.conditions = conditions(&args);
+        options = session.options;
+        players = 2;
+        InputSource::Network(session)
+    } else if let Some(path) = arg_value(&args, "--record") {
+        InputSource::Record(PathBuf::from(path))
+    } else {
+        InputSource::Keyboard
+    };
+    let GameOptions {
+        seed,
+        difficulty,
+        teams,
+        friendly_fire,
+        versus,
+        survival,
+    } = options;
+    println!("seed: {}", seed);
+    // a game played on the title menu is saved when the window closes, but not a versus match or
+    // a survival game
+    let save = (!headless
+        && versus.is_none()
+        && survival.is_none()
+        && matches!(input, InputSource::Keyboard | InputSource::Record(_)))
+    .then(|| PathBuf::from(SAVE_PATH));
+
     let mut app = App::new();
-    app.insert_resource(WindowDescriptor {
+    if headless {
+        // only game logic, no window, rendering or asset loading, but still the log
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(LogPlugin)
+            .add_plugin(InputPlugin);
+    } else {
+        app.insert_resource(window())
+            .insert_resource(ClearColor(Color::BLACK))
+            .add_plugins(DefaultPlugins);
+    }
+    if let Some(port) = arg_value(&args, "--stream") {
+        // spectators can watch the game
+        let port: u16 = port.parse().expect("port must be an unsigned integer");
+        let clients = Clients::bind(("0.0.0.0", port), 0).expect("failed to stream");
+        app.insert_resource(clients).add_plugin(StreamPlugin);
+    }
+    app.add_plugins(BattleCityPlugins {
+        headless,
+        seed,
+        difficulty,
+        input,
+        ally,
+        save,
+        players,
+        teams,
+        friendly_fire,
+        versus,
+        survival,
+    })
+    .run();
+}
+
+fn window() -> WindowDescriptor {
+    WindowDescriptor {
         title: "Battle City".to_string(),
         width: WINDOW_WIDTH,
         height: WINDOW_HEIGHT,
         ..Default::default()
-    })
-    .insert_resource(ClearColor(Color::BLACK))
-    .add_startup_system(setup)
-    .add_startup_stage("game_setup", SystemStage::single(spawn_tank))
-    .add_startup_stage("terrian_setup", SystemStage::single(spawn_terrian))
-    .add_system(star::twinkling)
-    .add_system(p1::movement)
-    .add_system(p2::movement)
-    .add_system(p1::animation)
-    .add_system(p2::animation)
-    .add_system(p1::firing)
-    .add_system(ai::movement)
-    .add_system(ai::animation)
-    .add_system(bullet::movement)
-    .add_system(bullet::collision)
-    .add_system(explosion::explode)
-    .add_system(river::wave)
-    .add_system(shield::animation)
-    .add_plugins(DefaultPlugins)
-    .add_event::<event::DestroyAllEnemies>()
-    .add_system(event::handle_destroy_all_enemies)
-    .add_event::<base::wall::ChangeBaseWall>()
-    .add_system(base::wall::handle_change_base_wall)
-    .add_system(base::wall::change_basewall_count_down)
-    .run();
+    }
 }
 
-fn setup(
-    mut commands: Commands,
-    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
-    asset_server: Res<AssetServer>,
-) {
-    // Add the game's entities to our world
-    let texture_handle = texture_atlases.add(load_texture_atlas(asset_server));
-    // cameras
-    commands.spawn_bundle(OrthographicCameraBundle::new_2d());
-    commands.insert_resource(Textures {
-        texture: texture_handle,
+/// A worse link than the real one, e.g. `--latency 80 --jitter 40 --loss 5` in milliseconds and
+/// percent
+fn conditions(args: &[String]) -> Conditions {
+    let millis = |name| {
+        arg_value(args, name).map_or(Duration::ZERO, |ms| {
+            Duration::from_millis(
+                ms.parse()
+                    .expect("milliseconds must be an unsigned integer"),
+            )
+        })
+    };
+    let loss: f32 = arg_value(args, "--loss").map_or(0., |percent| {
+        percent.parse().expect("loss must be a percentage")
     });
-    commands.insert_resource(GameData::new());
+    Conditions {
+        latency: millis("--latency"),
+        jitter: millis("--jitter"),
+        loss: loss / 100.,
+    }
 }
 
-fn spawn_tank(mut commands: Commands, textures: Res<Textures>) {
-    let texture = &textures.texture;
-    p1::spawn(&mut commands, texture.clone());
-    tank::spawn(
-        &mut commands,
-        texture.clone(),
-        p2::SPAWN_POSITION,
-        utils::Owner::P2,
-        0,
-    );
-    tank::spawn(
-        &mut commands,
-        texture.clone(),
-        ai::SPAWN_POSITION1,
-        utils::Owner::AI,
-        1,
-    );
-    tank::spawn(
-        &mut commands,
-        texture.clone(),
-        ai::SPAWN_POSITION2,
-        utils::Owner::AI,
-        2,
-    );
-    tank::spawn(
-        &mut commands,
-        texture.clone(),
-        ai::SPAWN_POSITION3,
-        utils::Owner::AI,
-        3,
-    );
-}
-
-fn spawn_terrian(mut commands: Commands, textures: Res<Textures>) {
-    let texture = &textures.texture;
-
-    // let wall_thickness = 10.;
-    let left_size = Vec2::new(BLOCK_WIDTH, WINDOW_HEIGHT);
-    let right_size = Vec2::new(2. * BLOCK_WIDTH, WINDOW_HEIGHT);
-    let top_size = Vec2::new(BATTLE_FIELD_WIDTH, HALF_BLOCK_WIDTH);
-    // left
-    commands
-        .spawn_bundle(SpriteBundle {
-            transform: Transform::from_translation(Vec3::new(-120. * SCALE, 0., 0.)),
-            sprite: Sprite {
-                custom_size: Some(left_size),
-                color: Color::GRAY,
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Collider::Boundary)
-        .insert(state::State::Boundary);
-    // right
-    commands
-        .spawn_bundle(SpriteBundle {
-            transform: Transform::from_translation(Vec3::new(112. * SCALE, 0., 0.)),
-            sprite: Sprite {
-                custom_size: Some(right_size),
-                color: Color::GRAY,
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Collider::Boundary)
-        .insert(state::State::Boundary);
-    // top
-    commands
-        .spawn_bundle(SpriteBundle {
-            transform: Transform::from_translation(Vec3::new(-HALF_BLOCK_WIDTH, 108. * SCALE, 0.)),
-            sprite: Sprite {
-                custom_size: Some(top_size),
-                color: Color::GRAY,
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Collider::Boundary)
-        .insert(state::State::Boundary);
-    // bottom
-    commands
-        .spawn_bundle(SpriteBundle {
-            transform: Transform::from_translation(Vec3::new(-HALF_BLOCK_WIDTH, -108. * SCALE, 0.)),
-            sprite: Sprite {
-                custom_size: Some(top_size),
-                color: Color::GRAY,
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Collider::Boundary)
-        .insert(state::State::Boundary);
-
-    brick::spawn(
-        &mut commands,
-        texture.clone(),
-        b2t(Vec2::new(0., 0.), 0.),
-        BrickType::Brick,
-    );
-
-    iron::spawn(
-        &mut commands,
-        b2t(Vec2::new(1., 0.), 0.),
-        texture.clone(),
-        iron::IronType::Iron,
-    );
-    grass::spawn(&mut commands, b2t(Vec2::new(-1., 0.), 1.), texture.clone());
-    snow::spawn(&mut commands, b2t(Vec2::new(0., -1.), 0.), texture.clone());
-    river::spawn(&mut commands, b2t(Vec2::new(1., -1.), 0.), texture.clone());
-    base::spawn(
-        &mut commands,
-        b2t(Vec2::new(0., -6.), 0.),
-        texture.clone(),
-        false,
-    );
-
-    power_up::spawn(
-        &mut commands,
-        b2t(Vec2::new(-3., 1.), 0.),
-        power_up::PowerType::Helmet,
-        texture.clone(),
-    );
-    power_up::spawn(
-        &mut commands,
-        b2t(Vec2::new(-2., 1.), 0.),
-        power_up::PowerType::Clock,
-        texture.clone(),
-    );
-    power_up::spawn(
-        &mut commands,
-        b2t(Vec2::new(-1., 1.), 0.),
-        power_up::PowerType::Shovel,
-        texture.clone(),
-    );
-    power_up::spawn(
-        &mut commands,
-        b2t(Vec2::new(0., 1.), 0.),
-        power_up::PowerType::Star,
-        texture.clone(),
-    );
-    power_up::spawn(
-        &mut commands,
-        b2t(Vec2::new(1., 1.), 0.),
-        power_up::PowerType::Grenade,
-        texture.clone(),
-    );
-    power_up::spawn(
-        &mut commands,
-        b2t(Vec2::new(2., 1.), 0.),
-        power_up::PowerType::Tank,
-        texture.clone(),
-    );
-    power_up::spawn(
-        &mut commands,
-        b2t(Vec2::new(3., 1.), 0.),
-        power_up::PowerType::Gun,
-        texture.clone(),
-    );
+/// Value of a command line option, e.g. `--seed 42`
+fn arg_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|i| args.get(i + 1))
+        .map(|value| value.as_str())
 }
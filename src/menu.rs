@@ -0,0 +1,202 @@
+use bevy::prelude::*;
+
+use crate::{
+    config::{Config, Difficulty},
+    consts::{BLOCK_WIDTH, SCALE},
+    save::{Resume, SaveFile, SaveGame},
+    simulation::Clock,
+    texture::{SpriteIndex, Textures},
+};
+
+// enemy tanks facing right, from the basic one to the armoured one
+const ENEMY_SPRITES: [usize; 4] = [78, 94, 110, 126];
+const CURSOR_SPRITE: usize = 6; // P1's tank facing right
+const CONTINUE_SPRITES: [usize; 2] = [6, 134]; // P1's and P2's tanks facing right
+const UP_KEYS: [KeyCode; 2] = [KeyCode::W, KeyCode::Up];
+const DOWN_KEYS: [KeyCode; 2] = [KeyCode::S, KeyCode::Down];
+const CONFIRM_KEYS: [KeyCode; 2] = [KeyCode::J, KeyCode::Return];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AppState {
+    Menu, // the title menu
+    Playing,
+}
+
+/// The title menu, where the difficulty is chosen before a game starts, or a saved game is
+/// continued.
+///
+/// Without it, the game starts right away with the difficulty in `Config`.
+pub struct MenuPlugin {
+    pub enabled: bool,
+}
+
+impl Plugin for MenuPlugin {
+    fn build(&self, app: &mut App) {
+        if !self.enabled {
+            app.add_state(AppState::Playing);
+            return;
+        }
+        app.add_state(AppState::Menu)
+            .add_system_set(SystemSet::on_enter(AppState::Menu).with_system(spawn))
+            .add_system_set(SystemSet::on_update(AppState::Menu).with_system(navigate))
+            .add_system_set(SystemSet::on_exit(AppState::Menu).with_system(despawn));
+    }
+}
+
+#[derive(Component)]
+pub struct MenuItem;
+
+#[derive(Component)]
+pub struct Cursor;
+
+pub struct Menu {
+    selected: usize,        // row of the cursor
+    save: Option<SaveGame>, // the first row continues it
+}
+
+impl Menu {
+    fn rows(&self) -> usize {
+        Difficulty::ALL.len() + self.save.is_some() as usize
+    }
+
+    /// The difficulty of the selected row, None for continuing the saved game
+    fn difficulty(&self) -> Option<Difficulty> {
+        let offset = self.save.is_some() as usize;
+        self.selected
+            .checked_sub(offset)
+            .map(|index| Difficulty::ALL[index])
+    }
+}
+
+fn row_translation(row: usize) -> Vec3 {
+    Vec3::new(-3. * BLOCK_WIDTH, (1. - row as f32) * 1.5 * BLOCK_WIDTH, 0.)
+}
+
+fn spawn_sprite(
+    commands: &mut Commands,
+    texture: Handle<TextureAtlas>,
+    index: usize,
+    translation: Vec3,
+) -> Entity {
+    commands
+        .spawn_bundle(SpriteSheetBundle {
+            sprite: TextureAtlasSprite::new(index),
+            texture_atlas: texture,
+            transform: Transform {
+                translation,
+                scale: Vec3::splat(SCALE),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(MenuItem)
+        .id()
+}
+
+/// Every difficulty is a row of enemy tanks, more and stronger ones for a harder difficulty.
+/// When there is a saved game, a row of players' tanks above them continues it.
+pub fn spawn(
+    mut commands: Commands,
+    textures: Res<Textures>,
+    config: Res<Config>,
+    save_file: Option<Res<SaveFile>>,
+    mut clock: ResMut<Clock>,
+) {
+    clock.paused = true; // nothing moves before the game starts
+    let texture = &textures.texture;
+    spawn_sprite(
+        &mut commands,
+        texture.clone(),
+        SpriteIndex::BASE[0],
+        Vec3::new(-0.5 * BLOCK_WIDTH, 4. * BLOCK_WIDTH, 0.),
+    );
+    let save = save_file.and_then(|file| SaveGame::load(&file.0).ok());
+    let first = save.is_some() as usize; // row of the first difficulty
+    if save.is_some() {
+        for (i, sprite) in CONTINUE_SPRITES.iter().enumerate() {
+            let offset = Vec3::X * (i + 1) as f32 * 1.25 * BLOCK_WIDTH;
+            spawn_sprite(
+                &mut commands,
+                texture.clone(),
+                *sprite,
+                row_translation(0) + offset,
+            );
+        }
+    }
+    for (row, sprite) in ENEMY_SPRITES.iter().enumerate() {
+        for i in 0..=row {
+            let offset = Vec3::X * (i + 1) as f32 * 1.25 * BLOCK_WIDTH;
+            spawn_sprite(
+                &mut commands,
+                texture.clone(),
+                *sprite,
+                row_translation(first + row) + offset,
+            );
+        }
+    }
+
+    // the saved game is selected if there is one
+    let selected = match save {
+        Some(_) => 0,
+        None => Difficulty::ALL
+            .iter()
+            .position(|difficulty| *difficulty == config.difficulty)
+            .unwrap(),
+    };
+    let cursor = spawn_sprite(
+        &mut commands,
+        texture.clone(),
+        CURSOR_SPRITE,
+        row_translation(selected),
+    );
+    commands.entity(cursor).insert(Cursor);
+    commands.insert_resource(Menu { selected, save });
+}
+
+/// Up and down choose a difficulty or the saved game, J or Enter starts the game
+pub fn navigate(
+    mut commands: Commands,
+    keyboard: Res<Input<KeyCode>>,
+    mut menu: ResMut<Menu>,
+    mut config: ResMut<Config>,
+    mut state: ResMut<State<AppState>>,
+    mut cursor: Query<&mut Transform, With<Cursor>>,
+) {
+    let rows = menu.rows();
+    if keyboard.any_just_pressed(UP_KEYS) {
+        menu.selected = (menu.selected + rows - 1) % rows;
+    }
+    if keyboard.any_just_pressed(DOWN_KEYS) {
+        menu.selected = (menu.selected + 1) % rows;
+    }
+    for mut transform in cursor.iter_mut() {
+        transform.translation = row_translation(menu.selected);
+    }
+    if keyboard.any_just_pressed(CONFIRM_KEYS) {
+        match menu.difficulty() {
+            Some(difficulty) => config.difficulty = difficulty,
+            None => {
+                // played again with the options it was started with
+                let save = menu.save.take().unwrap();
+                config.difficulty = save.difficulty;
+                config.players = save.players as usize;
+                config.teams = save.teams;
+                commands.insert_resource(save.friendly_fire);
+                commands.insert_resource(Resume(save));
+            }
+        }
+        state.set(AppState::Playing).unwrap();
+    }
+}
+
+pub fn despawn(
+    mut commands: Commands,
+    items: Query<Entity, With<MenuItem>>,
+    mut clock: ResMut<Clock>,
+) {
+    for entity in items.iter() {
+        commands.entity(entity).despawn();
+    }
+    commands.remove_resource::<Menu>();
+    clock.paused = false;
+}
@@ -0,0 +1,573 @@
+//! Co-op over UDP in lockstep.
+//!
+//! Both peers run the same deterministic simulation. Every tick each peer samples its own
+//! keyboard for a tick some ticks ahead, the input delay, and sends it to the other peer. A tick
+//! is only simulated once both players' inputs of it are known, so both peers always simulate
+//! the same inputs. The host plays P1 and the peer joining it plays P2, both with P1's keys.
+//! More than two players play over the network through a `server` instead.
+//!
+//! Every packet carries all local inputs the other peer has not acknowledged yet, so a lost
+//! packet is covered by the next one.
+//!
+//! With rollback, see `rollback`, the same inputs are exchanged, but ticks do not wait for them.
+//! `Conditions` makes a link worse than it is, to try both on a single machine.
+
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::{
+    config::Difficulty,
+    input::{PlayerInputs, TankInput},
+    simulation::Clock,
+    survival,
+    tank::player,
+    team::{FriendlyFire, Teams},
+    utils::MAX_PLAYERS,
+    versus,
+};
+
+/// Ticks between sampling an input and simulating it
+pub const DEFAULT_DELAY: u64 = 6;
+/// Rollback hides most of the latency, a short delay only saves some rollbacks
+pub const DEFAULT_ROLLBACK_DELAY: u64 = 2;
+const NET_MAGIC: &[u8; 4] = b"BCNT";
+const HELLO: u8 = 0;
+const WELCOME: u8 = 1;
+const INPUTS: u8 = 2;
+const MAX_INPUTS_PER_PACKET: usize = 255;
+const HANDSHAKE_RETRY: Duration = Duration::from_millis(500);
+const GAME_OPTIONS_LEN: usize = 36;
+
+/// How the game starts, chosen by the host and adopted by the peer joining it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GameOptions {
+    pub seed: u64,
+    pub difficulty: Difficulty,
+    pub teams: Teams,
+    pub friendly_fire: FriendlyFire,
+    pub versus: Option<versus::Rules>,
+    pub survival: Option<survival::Rules>,
+}
+
+impl GameOptions {
+    /// seed, difficulty, every player's team, friendly fire, versus and survival as in a replay
+    fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = self.seed.to_le_bytes().to_vec();
+        bytes.push(self.difficulty as u8);
+        bytes.extend_from_slice(&self.teams.0);
+        bytes.push(self.friendly_fire as u8);
+        match self.versus {
+            Some(rules) => bytes.extend_from_slice(&[1, rules.rounds, rules.kills]),
+            None => bytes.extend_from_slice(&[0; 3]),
+        }
+        match self.survival {
+            Some(rules) => {
+                bytes.extend_from_slice(&[1, rules.first_wave, rules.growth]);
+                bytes.extend_from_slice(&rules.ratio_step.to_le_bytes());
+                bytes.extend_from_slice(&(rules.regrowth.as_nanos() as u64).to_le_bytes());
+            }
+            None => bytes.extend_from_slice(&[0; 19]),
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != GAME_OPTIONS_LEN {
+            return None;
+        }
+        let friendly_fire = match bytes[13] {
+            0 => FriendlyFire::Off,
+            1 => FriendlyFire::On,
+            _ => return None,
+        };
+        let versus = match bytes[14] {
+            0 => None,
+            1 => Some(versus::Rules {
+                rounds: bytes[15],
+                kills: bytes[16],
+            }),
+            _ => return None,
+        };
+        let survival = match bytes[17] {
+            0 => None,
+            1 => Some(survival::Rules {
+                first_wave: bytes[18],
+                growth: bytes[19],
+                ratio_step: f64::from_le_bytes(bytes[20..28].try_into().unwrap()),
+                regrowth: Duration::from_nanos(u64::from_le_bytes(
+                    bytes[28..36].try_into().unwrap(),
+                )),
+            }),
+            _ => return None,
+        };
+        Some(Self {
+            seed: u64::from_le_bytes(bytes[..8].try_into().unwrap()),
+            difficulty: *Difficulty::ALL.get(bytes[8] as usize)?,
+            teams: Teams(bytes[9..13].try_into().unwrap()),
+            friendly_fire,
+            versus,
+            survival,
+        })
+    }
+
+    /// The option the peer joining asked for which the host chose differently, e.g. `--versus`
+    /// to join a co-op game
+    pub fn conflict(&self, asked: &AskedOptions) -> Option<&'static str> {
+        if asked.seed.map_or(false, |seed| seed != self.seed) {
+            Some("seed")
+        } else if asked
+            .difficulty
+            .map_or(false, |difficulty| difficulty != self.difficulty)
+        {
+            Some("difficulty")
+        } else if asked.teams.map_or(false, |teams| teams != self.teams) {
+            Some("teams")
+        } else if asked
+            .friendly_fire
+            .map_or(false, |on| on != self.friendly_fire)
+        {
+            Some("friendly fire")
+        } else if asked
+            .versus
+            .map_or(false, |rules| Some(rules) != self.versus)
+        {
+            Some("versus")
+        } else if asked
+            .survival
+            .map_or(false, |rules| Some(rules) != self.survival)
+        {
+            Some("survival")
+        } else {
+            None
+        }
+    }
+}
+
+/// Options given on the command line of the peer joining, None for those left to the host
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AskedOptions {
+    pub seed: Option<u64>,
+    pub difficulty: Option<Difficulty>,
+    pub teams: Option<Teams>,
+    pub friendly_fire: Option<FriendlyFire>,
+    pub versus: Option<versus::Rules>,
+    pub survival: Option<survival::Rules>,
+}
+
+/// A connection between two peers agreeing on how the game starts
+#[derive(Clone)]
+pub struct Session {
+    socket: Arc<UdpSocket>,
+    peer: SocketAddr,
+    pub player: usize, // the local player, index in PlayerInputs
+    pub options: GameOptions,
+    pub delay: u64,
+    pub rollback: bool, // predict the other player's inputs instead of waiting for them
+    pub conditions: Conditions, // of the packets this peer sends, not agreed with the other peer
+}
+
+impl Session {
+    /// Waits for a peer to join on a port, and tells it how the game starts
+    pub fn host(port: u16, options: GameOptions, delay: u64, rollback: bool) -> io::Result<Self> {
+        Self::host_on(
+            UdpSocket::bind(("0.0.0.0", port))?,
+            options,
+            delay,
+            rollback,
+        )
+    }
+
+    /// Waits for a peer to join on a bound socket, and tells it how the game starts
+    pub fn host_on(
+        socket: UdpSocket,
+        options: GameOptions,
+        delay: u64,
+        rollback: bool,
+    ) -> io::Result<Self> {
+        // without any delay, no tick could ever be confirmed
+        let delay = delay.clamp(1, u8::MAX as u64);
+        let mut buf = [0; 64];
+        let peer = loop {
+            let (len, from) = socket.recv_from(&mut buf)?;
+            if buf[..len] == hello()[..] {
+                break from;
+            }
+        };
+        let session = Self {
+            socket: Arc::new(socket),
+            peer,
+            player: 0,
+            options,
+            delay,
+            rollback,
+            conditions: Conditions::default(),
+        };
+        session.send(&session.welcome())?;
+        session.socket.set_nonblocking(true)?;
+        Ok(session)
+    }
+
+    /// Joins a host, and learns how the game starts from it, an error if the host chose other
+    /// options than those asked for
+    pub fn join(addr: impl ToSocketAddrs, asked: &AskedOptions) -> io::Result<Self> {
+        let peer = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| invalid("no address to join"))?;
+        let any = if peer.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(any)?;
+        socket.set_read_timeout(Some(HANDSHAKE_RETRY))?;
+        let mut buf = [0; 64];
+        loop {
+            socket.send_to(&hello(), peer)?;
+            let len = match socket.recv_from(&mut buf) {
+                Ok((len, from)) if from == peer => len,
+                Ok(_) => continue,
+                Err(err)
+                    if err.kind() == io::ErrorKind::WouldBlock
+                        || err.kind() == io::ErrorKind::TimedOut =>
+                {
+                    continue; // the host is not there yet, or the packet is lost
+                }
+                Err(err) => return Err(err),
+            };
+            if let Some((options, delay, rollback)) = parse_welcome(&buf[..len]) {
+                if let Some(option) = options.conflict(asked) {
+                    return Err(invalid(&format!("the host chose another {}", option)));
+                }
+                socket.set_nonblocking(true)?;
+                return Ok(Self {
+                    socket: Arc::new(socket),
+                    peer,
+                    player: 1,
+                    options,
+                    delay,
+                    rollback,
+                    conditions: Conditions::default(),
+                });
+            }
+        }
+    }
+
+    fn send(&self, packet: &[u8]) -> io::Result<()> {
+        self.socket.send_to(packet, self.peer).map(|_| ())
+    }
+
+    /// magic, kind, delay, rollback, then the game's options
+    fn welcome(&self) -> Vec<u8> {
+        let mut packet = header(WELCOME);
+        packet.push(self.delay as u8);
+        packet.push(self.rollback as u8);
+        packet.extend(self.options.to_bytes());
+        packet
+    }
+}
+
+/// Latency, jitter and loss added to a link, e.g. to try netcode on a single machine
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Conditions {
+    pub latency: Duration,
+    pub jitter: Duration, // up to this much more latency, so packets can arrive out of order
+    pub loss: f32,        // chance of a packet being dropped
+}
+
+impl Conditions {
+    /// When a packet sent now arrives, None if it is lost
+    fn arrival(&self, now: Instant) -> Option<Instant> {
+        let mut rng = rand::thread_rng();
+        if self.loss > 0. && rng.gen::<f32>() < self.loss {
+            return None;
+        }
+        let jitter = if self.jitter > Duration::ZERO {
+            rng.gen_range(Duration::ZERO..=self.jitter)
+        } else {
+            Duration::ZERO
+        };
+        Some(now + self.latency + jitter)
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn header(kind: u8) -> Vec<u8> {
+    let mut packet = NET_MAGIC.to_vec();
+    packet.push(kind);
+    packet
+}
+
+fn hello() -> Vec<u8> {
+    header(HELLO)
+}
+
+fn parse_welcome(packet: &[u8]) -> Option<(GameOptions, u64, bool)> {
+    if packet.len() < 7 || packet[..5] != header(WELCOME)[..] {
+        return None;
+    }
+    let options = GameOptions::from_bytes(&packet[7..])?;
+    Some((options, packet[5] as u64, packet[6] != 0))
+}
+
+/// Inputs of both players, and what the peers know about each other's
+pub struct Lockstep {
+    session: Session,
+    local: Vec<TankInput>,  // the local player's inputs, index 0 is tick 1
+    remote: Vec<TankInput>, // the other player's inputs received so far, without gaps
+    acked: usize,           // local inputs the other peer has received
+    delayed: Vec<(Instant, Vec<u8>)>, // packets held back by the conditions, by arrival
+}
+
+impl Lockstep {
+    pub fn new(session: Session) -> Self {
+        // nothing is pressed during the first ticks, before any input can arrive
+        let start = vec![TankInput::default(); session.delay as usize];
+        Self {
+            session,
+            local: start.clone(),
+            remote: start,
+            acked: 0,
+            delayed: Vec::new(),
+        }
+    }
+
+    /// The local player, index in `PlayerInputs`
+    pub fn player(&self) -> usize {
+        self.session.player
+    }
+
+    pub fn delay(&self) -> u64 {
+        self.session.delay
+    }
+
+    /// The number of ticks the local player's inputs are known
+    pub fn sampled(&self) -> u64 {
+        self.local.len() as u64
+    }
+
+    /// The number of ticks the other player's inputs are known
+    pub fn received(&self) -> u64 {
+        self.remote.len() as u64
+    }
+
+    pub fn local(&self, tick: u64) -> Option<TankInput> {
+        self.local.get(tick as usize - 1).copied()
+    }
+
+    pub fn remote(&self, tick: u64) -> Option<TankInput> {
+        self.remote.get(tick as usize - 1).copied()
+    }
+
+    /// The last tick both players' inputs are known
+    pub fn confirmed(&self) -> u64 {
+        self.local.len().min(self.remote.len()) as u64
+    }
+
+    /// Adds the local input of the next tick which has none yet
+    pub fn add_local(&mut self, input: TankInput) {
+        self.local.push(input);
+    }
+
+    /// Inputs of a confirmed tick, by player ID, P3 and P4 never press anything
+    pub fn inputs(&self, tick: u64) -> [TankInput; MAX_PLAYERS] {
+        let i = tick as usize - 1;
+        let mut inputs = [TankInput::default(); MAX_PLAYERS];
+        inputs[1 - self.session.player] = self.remote[i];
+        inputs[self.session.player] = self.local[i];
+        inputs
+    }
+
+    /// magic, kind, the number of inputs received from the other peer, the tick of the first
+    /// input, the number of inputs, then the inputs not acknowledged yet
+    pub fn packet(&self) -> Vec<u8> {
+        let unacked = &self.local[self.acked..];
+        let unacked = &unacked[..unacked.len().min(MAX_INPUTS_PER_PACKET)];
+        let mut packet = header(INPUTS);
+        packet.extend_from_slice(&(self.remote.len() as u64).to_le_bytes());
+        packet.extend_from_slice(&(self.acked as u64 + 1).to_le_bytes());
+        packet.push(unacked.len() as u8);
+        packet.extend(unacked.iter().map(|input| input.bits()));
+        packet
+    }
+
+    /// Takes inputs from a packet of the other peer, returns false if it is not one
+    pub fn receive(&mut self, packet: &[u8]) -> bool {
+        if packet.len() < 22 || packet[..5] != header(INPUTS)[..] {
+            return false;
+        }
+        let ack = u64::from_le_bytes(packet[5..13].try_into().unwrap()) as usize;
+        let first = u64::from_le_bytes(packet[13..21].try_into().unwrap()) as usize;
+        let count = packet[21] as usize;
+        if packet.len() != 22 + count || first == 0 {
+            return false;
+        }
+        self.acked = self.acked.max(ack.min(self.local.len()));
+        for (i, bits) in packet[22..].iter().enumerate() {
+            // inputs already received or after a gap are skipped
+            if first + i == self.remote.len() + 1 {
+                self.remote.push(TankInput::from_bits(*bits));
+            }
+        }
+        true
+    }
+
+    /// Reads every packet which arrived from the other peer
+    pub fn poll(&mut self) {
+        let mut buf = [0; 512];
+        loop {
+            let (len, from) = match self.session.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                // nothing more to read, or e.g. the other peer is not listening yet
+                Err(_) => break,
+            };
+            if from != self.session.peer {
+                continue;
+            }
+            if self.session.player == 0 && buf[..len] == hello()[..] {
+                // the welcome was lost, the peer asks again
+                let _ = self.session.send(&self.session.welcome());
+                continue;
+            }
+            self.receive(&buf[..len]);
+        }
+    }
+
+    /// Sends a packet of the inputs not acknowledged, and the packets held back which are due
+    pub fn transmit(&mut self, now: Instant) {
+        if let Some(arrival) = self.session.conditions.arrival(now) {
+            self.delayed.push((arrival, self.packet()));
+        }
+        // in the order they arrive, jitter can overtake earlier packets
+        self.delayed.sort_by_key(|(arrival, _)| *arrival);
+        let due = self
+            .delayed
+            .iter()
+            .take_while(|(arrival, _)| *arrival <= now)
+            .count();
+        for (_, packet) in self.delayed.drain(..due) {
+            let _ = self.session.send(&packet);
+        }
+    }
+}
+
+/// Reads packets from the other peer, and lets the simulation run up to the confirmed tick
+pub fn receive(mut lockstep: ResMut<Lockstep>, mut clock: ResMut<Clock>) {
+    lockstep.poll();
+    clock.limit = Some(lockstep.confirmed());
+}
+
+/// Samples the local keyboard for a tick ahead, and pushes both players' inputs of this tick
+pub fn exchange(
+    keyboard: Res<Input<KeyCode>>,
+    mut lockstep: ResMut<Lockstep>,
+    mut clock: ResMut<Clock>,
+    mut inputs: ResMut<PlayerInputs>,
+) {
+    lockstep.add_local(TankInput::from_keyboard(
+        &keyboard,
+        &player::DIRECTION_KEYS[0],
+        player::FIRE_KEYS[0],
+    ));
+    inputs.push(lockstep.inputs(clock.tick));
+    clock.limit = Some(lockstep.confirmed());
+}
+
+/// Sends local inputs the other peer has not acknowledged, once per frame
+pub fn send(mut lockstep: ResMut<Lockstep>) {
+    lockstep.transmit(Instant::now());
+}
+
+/// Polls until a condition holds, false if it does not within a second
+#[cfg(test)]
+fn poll_until(lockstep: &mut Lockstep, condition: impl Fn(&Lockstep) -> bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while Instant::now() < deadline {
+        lockstep.poll();
+        if condition(lockstep) {
+            return true;
+        }
+        std::thread::yield_now();
+    }
+    false
+}
+
+#[test]
+fn test_lockstep() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let port = socket.local_addr().unwrap().port();
+    let options = GameOptions {
+        seed: 42,
+        difficulty: Difficulty::Hard,
+        teams: Teams::VERSUS,
+        friendly_fire: FriendlyFire::On,
+        versus: Some(versus::Rules {
+            rounds: 5,
+            kills: 7,
+        }),
+        survival: None,
+    };
+    let asked = AskedOptions {
+        versus: options.versus,
+        ..Default::default()
+    };
+    let joining = std::thread::spawn(move || Session::join(("127.0.0.1", port), &asked).unwrap());
+    let host = Session::host_on(socket, options, 2, false).unwrap();
+    let guest = joining.join().unwrap();
+    assert_eq!((host.player, guest.player), (0, 1));
+    assert_eq!((guest.options, guest.delay), (options, 2));
+
+    // a peer asking for another game than the host's is not let in
+    let survival = AskedOptions {
+        survival: Some(survival::Rules::default()),
+        ..Default::default()
+    };
+    assert_eq!(options.conflict(&survival), Some("survival"));
+    assert_eq!(options.conflict(&asked), None);
+    assert_eq!(GameOptions::from_bytes(&options.to_bytes()), Some(options));
+
+    let mut a = Lockstep::new(host);
+    let mut b = Lockstep::new(guest);
+    assert_eq!(a.confirmed(), 2);
+    let mut fire = TankInput::default();
+    fire.set(TankInput::FIRE, true);
+    a.add_local(fire);
+    assert_eq!(a.confirmed(), 2); // P2's input of tick 3 is not there yet
+
+    // a lost packet is covered by the next one
+    let _lost = a.packet();
+    b.add_local(TankInput::default());
+    assert!(b.receive(&a.packet()));
+    assert!(a.receive(&b.packet()));
+    assert_eq!(a.confirmed(), 3);
+    assert_eq!(b.confirmed(), 3);
+    assert_eq!(a.inputs(3), b.inputs(3));
+    assert_eq!(b.inputs(3)[0], fire);
+
+    // only what is not acknowledged is sent again
+    assert!(b.receive(&a.packet()));
+    assert_eq!(a.packet().len(), 22);
+    assert!(!a.receive(&hello()));
+
+    // packets are held back by latency and dropped by loss
+    a.add_local(fire);
+    a.session.conditions.latency = Duration::from_millis(50);
+    let now = Instant::now();
+    a.transmit(now);
+    b.poll();
+    assert_eq!(b.received(), 3);
+    a.session.conditions.loss = 1.;
+    a.transmit(now + Duration::from_millis(60));
+    assert!(poll_until(&mut b, |b| b.received() == 4));
+    assert_eq!(b.remote(4), Some(fire));
+}
@@ -0,0 +1,235 @@
+//! A* path finding for tanks over the quarter block grid.
+//!
+//! A tank covers 2x2 quarter blocks, so a node of a path is the quarter block under the tank's
+//! top left corner. Bricks, and iron for tanks whose bullets break it, are not walls but cost
+//! extra, since the tank has to shoot its way through.
+
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use bevy::prelude::*;
+
+use crate::{
+    consts::HALF_BLOCK_WIDTH,
+    terrain::{Tile, TileMap},
+    utils::{grid2translation, translation2grid, Direction, GRID_SIZE},
+};
+
+/// Number of nodes in a row or a column
+pub const NODES: i32 = GRID_SIZE as i32 - 1;
+const STEP_COST: u32 = 1;
+const BRICK_COST: u32 = 4; // per quarter block of brick in the way
+const IRON_COST: u32 = 8;
+
+/// Quarter block position of a tank's top left corner
+pub type Node = (i32, i32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cell {
+    Empty, // including grass and snow
+    Brick,
+    Iron,
+    River,
+}
+
+/// Terrain of the battle field, rebuilt from the tile map when it changes
+#[derive(Clone)]
+pub struct PathGrid {
+    cells: Vec<Cell>,
+    version: u64, // increased when terrain changes, so paths can be recomputed
+}
+
+impl Default for PathGrid {
+    fn default() -> Self {
+        Self {
+            cells: vec![Cell::Empty; GRID_SIZE * GRID_SIZE],
+            version: 0,
+        }
+    }
+}
+
+impl PathGrid {
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn cell(&self, x: usize, y: usize) -> Cell {
+        self.cells[y * GRID_SIZE + x]
+    }
+
+    /// Built from terrain at a version, e.g. the one of a saved game, so routes found on it before
+    /// are still up to date
+    pub fn from_tiles(tiles: &TileMap, version: u64) -> Self {
+        Self {
+            cells: cells(tiles),
+            version,
+        }
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, cell: Cell) {
+        if self.cells[y * GRID_SIZE + x] != cell {
+            self.cells[y * GRID_SIZE + x] = cell;
+            self.version += 1;
+        }
+    }
+
+    /// Cost of driving onto a node, None if the tank can not get there
+    fn cost(&self, node: Node, amphibious: bool, break_iron: bool) -> Option<u32> {
+        let mut cost = STEP_COST;
+        for y in node.1..node.1 + 2 {
+            for x in node.0..node.0 + 2 {
+                cost += match self.cell(x as usize, y as usize) {
+                    Cell::Empty => 0,
+                    Cell::Brick => BRICK_COST,
+                    Cell::Iron if break_iron => IRON_COST,
+                    Cell::River if amphibious => 0,
+                    Cell::Iron | Cell::River => return None,
+                };
+            }
+        }
+        Some(cost)
+    }
+
+    /// Cheapest path from a node to another, both included.
+    ///
+    /// Ties are broken by node position, so the same grid always gives the same path.
+    pub fn find_path(
+        &self,
+        from: Node,
+        to: Node,
+        amphibious: bool,
+        break_iron: bool,
+    ) -> Option<Vec<Node>> {
+        let index = |node: Node| (node.1 * NODES + node.0) as usize;
+        let heuristic = |node: Node| ((node.0 - to.0).abs() + (node.1 - to.1).abs()) as u32;
+        let mut costs = vec![u32::MAX; (NODES * NODES) as usize];
+        let mut previous = vec![None; (NODES * NODES) as usize];
+        let mut open = BinaryHeap::new();
+        costs[index(from)] = 0;
+        open.push(Reverse((heuristic(from), 0, from)));
+        while let Some(Reverse((_, cost, node))) = open.pop() {
+            if node == to {
+                let mut path = vec![to];
+                let mut node = to;
+                while let Some(prev) = previous[index(node)] {
+                    path.push(prev);
+                    node = prev;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            if cost > costs[index(node)] {
+                continue; // already reached with a lower cost
+            }
+            for next in [
+                (node.0, node.1 - 1),
+                (node.0 + 1, node.1),
+                (node.0, node.1 + 1),
+                (node.0 - 1, node.1),
+            ] {
+                if next.0 < 0 || next.1 < 0 || next.0 >= NODES || next.1 >= NODES {
+                    continue;
+                }
+                let step = match self.cost(next, amphibious, break_iron) {
+                    Some(step) => step,
+                    None if next == to => STEP_COST, // e.g. the base sits on the target
+                    None => continue,
+                };
+                if cost + step < costs[index(next)] {
+                    costs[index(next)] = cost + step;
+                    previous[index(next)] = Some(node);
+                    open.push(Reverse((cost + step + heuristic(next), cost + step, next)));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// The node closest to a tank
+pub fn node(translation: Vec3) -> Node {
+    let corner = translation + Vec3::new(-HALF_BLOCK_WIDTH / 2., HALF_BLOCK_WIDTH / 2., 0.);
+    let (x, y) = translation2grid(corner);
+    (x.clamp(0, NODES - 1), y.clamp(0, NODES - 1))
+}
+
+/// Translation of a tank standing on a node
+pub fn node_translation(node: Node, z: f32) -> Vec3 {
+    grid2translation(node.0, node.1, z)
+        + Vec3::new(HALF_BLOCK_WIDTH / 2., -HALF_BLOCK_WIDTH / 2., 0.)
+}
+
+/// Direction to drive from one point to another, along the longer axis
+pub fn direction(from: Vec3, to: Vec3) -> Direction {
+    let delta = to - from;
+    if delta.x.abs() > delta.y.abs() {
+        if delta.x > 0. {
+            Direction::Right
+        } else {
+            Direction::Left
+        }
+    } else if delta.y > 0. {
+        Direction::Up
+    } else {
+        Direction::Down
+    }
+}
+
+/// Rebuilds the grid from the tile map when terrain changes
+pub fn update(mut grid: ResMut<PathGrid>, tiles: Res<TileMap>) {
+    if !tiles.is_changed() {
+        return;
+    }
+    let cells = cells(&tiles);
+    if grid.cells != cells {
+        grid.cells = cells;
+        grid.version += 1;
+    }
+}
+
+fn cells(tiles: &TileMap) -> Vec<Cell> {
+    let mut cells = vec![Cell::Empty; GRID_SIZE * GRID_SIZE];
+    for (x, y, tile) in tiles.iter() {
+        cells[y * GRID_SIZE + x] = match tile {
+            Tile::Brick(_) => Cell::Brick,
+            Tile::Iron => Cell::Iron,
+            Tile::River => Cell::River,
+            Tile::Empty | Tile::Grass | Tile::Snow => Cell::Empty,
+        };
+    }
+    cells
+}
+
+#[test]
+fn test_find_path() {
+    let mut grid = PathGrid::default();
+    // a river wall with a gap at the bottom
+    for y in 0..GRID_SIZE - 2 {
+        grid.set(10, y, Cell::River);
+    }
+    let path = grid.find_path((0, 0), (20, 0), false, false).unwrap();
+    assert_eq!(path.first(), Some(&(0, 0)));
+    assert_eq!(path.last(), Some(&(20, 0)));
+    assert!(path.iter().any(|node| node.1 == NODES - 1));
+    // an amphibious tank drives across
+    let path = grid.find_path((0, 0), (20, 0), true, false).unwrap();
+    assert_eq!(path.len(), 21);
+
+    // a thin brick wall is cheaper to shoot through than to go around
+    let mut grid = PathGrid::default();
+    for y in 0..GRID_SIZE - 2 {
+        grid.set(10, y, Cell::Brick);
+    }
+    let path = grid.find_path((0, 0), (20, 0), false, false).unwrap();
+    assert_eq!(path.len(), 21);
+    for y in 0..GRID_SIZE {
+        grid.set(10, y, Cell::Iron);
+    }
+    assert!(grid.find_path((0, 0), (20, 0), false, false).is_none());
+    assert!(grid.find_path((0, 0), (20, 0), false, true).is_some());
+}
+
+#[test]
+fn test_node_translation() {
+    let position = (3, 7);
+    assert_eq!(node(node_translation(position, 0.)), position);
+}
@@ -1,10 +1,13 @@
 use std::time::Duration;
 
 use bevy::{math::const_vec2, prelude::*};
+use rand::Rng;
 
 use crate::{
-    collision::Collider,
+    collision::{Collider, Hitbox},
     consts::{BLOCK_WIDTH, SCALE},
+    event,
+    simulation::AddSimulationSystem,
     state,
     texture::SpriteIndex,
 };
@@ -13,10 +16,20 @@ pub const SIZE: Vec2 = const_vec2!([BLOCK_WIDTH, BLOCK_WIDTH]);
 pub const SHOVEL_DURATION: Duration = Duration::from_secs(20);
 pub const BLINK_DURATION: Duration = Duration::from_secs(3);
 
-#[derive(Component)]
+/// Effects of power ups which are not handled by the tank picking them up
+pub struct PowerUpPlugin;
+
+impl Plugin for PowerUpPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<event::DestroyAllEnemies>()
+            .add_simulation_system(event::handle_destroy_all_enemies);
+    }
+}
+
+#[derive(Component, Clone)]
 pub struct PowerUp;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PowerType {
     Helmet,
     Clock,
@@ -27,6 +40,21 @@ pub enum PowerType {
     Gun,
 }
 
+impl PowerType {
+    pub fn random(rng: &mut impl Rng) -> Self {
+        match rng.gen_range(0..7) {
+            0 => PowerType::Helmet,
+            1 => PowerType::Clock,
+            2 => PowerType::Shovel,
+            3 => PowerType::Star,
+            4 => PowerType::Grenade,
+            5 => PowerType::Tank,
+            6 => PowerType::Gun,
+            _ => unreachable!(),
+        }
+    }
+}
+
 impl From<PowerType> for usize {
     fn from(power_up: PowerType) -> Self {
         match power_up {
@@ -60,5 +88,6 @@ pub fn spawn(
         })
         .insert(PowerUp)
         .insert(Collider::PowerUp)
+        .insert(Hitbox(Vec2::new(BLOCK_WIDTH, BLOCK_WIDTH)))
         .insert(state::State::PowerUp(p_type));
 }
@@ -1,6 +1,7 @@
 use crate::{
-    collision::Collider,
-    consts::{HALF_BLOCK_WIDTH, MIN_BLOCK_WIDTH, SCALE},
+    collision::{Collider, Hitbox},
+    consts::{HALF_BLOCK_WIDTH, SCALE},
+    simulation::TICK,
     state,
     texture::SpriteIndex,
 };
@@ -9,20 +10,17 @@ use bevy::{math::const_vec2, prelude::*};
 const SIZE: Vec2 = const_vec2!([HALF_BLOCK_WIDTH, HALF_BLOCK_WIDTH]);
 const TIMER_INTERVAL: f32 = 0.7;
 
-#[derive(Component)]
+#[derive(Component, Clone)]
 pub struct River;
 
-pub fn spawn(commands: &mut Commands, position: Vec3, texture: Handle<TextureAtlas>) {
+/// Spawns river of a quarter block
+pub fn spawn(commands: &mut Commands, position: Vec3, texture: Handle<TextureAtlas>) -> Entity {
     commands
         .spawn_bundle(SpriteSheetBundle {
             sprite: TextureAtlasSprite::new(SpriteIndex::RIVER[1]),
-            texture_atlas: texture.clone(),
+            texture_atlas: texture,
             transform: Transform {
-                translation: Vec3::new(
-                    position.x - MIN_BLOCK_WIDTH,
-                    position.y + MIN_BLOCK_WIDTH,
-                    position.z,
-                ),
+                translation: position,
                 scale: Vec3::splat(SCALE),
                 ..Default::default()
             },
@@ -30,70 +28,15 @@ pub fn spawn(commands: &mut Commands, position: Vec3, texture: Handle<TextureAtl
         })
         .insert(River)
         .insert(Collider::River)
+        .insert(Hitbox(SIZE))
         .insert(Timer::from_seconds(TIMER_INTERVAL, true))
-        .insert(state::State::River);
-    commands
-        .spawn_bundle(SpriteSheetBundle {
-            sprite: TextureAtlasSprite::new(SpriteIndex::RIVER[1]),
-            texture_atlas: texture.clone(),
-            transform: Transform {
-                translation: Vec3::new(
-                    position.x + MIN_BLOCK_WIDTH,
-                    position.y + MIN_BLOCK_WIDTH,
-                    position.z,
-                ),
-                scale: Vec3::splat(SCALE),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(River)
-        .insert(Collider::River)
-        .insert(Timer::from_seconds(TIMER_INTERVAL, true))
-        .insert(state::State::River);
-    commands
-        .spawn_bundle(SpriteSheetBundle {
-            sprite: TextureAtlasSprite::new(SpriteIndex::RIVER[1]),
-            texture_atlas: texture.clone(),
-            transform: Transform {
-                translation: Vec3::new(
-                    position.x + MIN_BLOCK_WIDTH,
-                    position.y - MIN_BLOCK_WIDTH,
-                    position.z,
-                ),
-                scale: Vec3::splat(SCALE),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(River)
-        .insert(Collider::River)
-        .insert(Timer::from_seconds(TIMER_INTERVAL, true))
-        .insert(state::State::River);
-    commands
-        .spawn_bundle(SpriteSheetBundle {
-            sprite: TextureAtlasSprite::new(SpriteIndex::RIVER[1]),
-            texture_atlas: texture.clone(),
-            transform: Transform {
-                translation: Vec3::new(
-                    position.x - MIN_BLOCK_WIDTH,
-                    position.y - MIN_BLOCK_WIDTH,
-                    position.z,
-                ),
-                scale: Vec3::splat(SCALE),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(River)
-        .insert(Collider::River)
-        .insert(Timer::from_seconds(TIMER_INTERVAL, true))
-        .insert(state::State::River);
+        .insert(state::State::River)
+        .id()
 }
 
-pub fn wave(time: Res<Time>, mut query: Query<(&mut TextureAtlasSprite, &mut Timer), With<River>>) {
+pub fn wave(mut query: Query<(&mut TextureAtlasSprite, &mut Timer), With<River>>) {
     for (mut sprite, mut timer) in query.iter_mut() {
-        if timer.tick(time.delta()).just_finished() {
+        if timer.tick(TICK).just_finished() {
             if sprite.index == SpriteIndex::RIVER[1] {
                 sprite.index += 1;
             } else {
@@ -0,0 +1,450 @@
+//! Rollback for co-op over network, in the way of GGPO.
+//!
+//! Ticks do not wait for the other player's inputs: a missing one is predicted to be the same
+//! as the last one received. The state at the beginning of every tick simulated with a
+//! prediction is kept in a snapshot. When an input arrives which is not what was predicted, the
+//! game goes back to the snapshot of its tick and simulates the ticks since then again, all
+//! before the next frame is drawn.
+//!
+//! A snapshot copies every entity of the game with the components in `COMPONENTS`, and the
+//! resources in `RESOURCES`. Anything else the simulation changes has to be added there. Events
+//! are handled during the tick they are sent, except tanks destroyed, which are handled during
+//! the next one and so are kept in the snapshot too.
+//! Entities come back as the same entities, but something spawned after a rollback may get
+//! another entity than it did before, so the simulation must not depend on which entity is
+//! which.
+
+use std::{collections::VecDeque, time::Instant};
+
+use bevy::{
+    app::{Events, ManualEventReader},
+    ecs::world::EntityMut,
+    prelude::*,
+    render::view::Visibility,
+    transform::components::PreviousParent,
+};
+
+use crate::{
+    base::{wall::ChangeBaseWall, Base},
+    brick::Brick,
+    bullet::Bullet,
+    collision::{Collider, Hitbox},
+    event::{DestroyAllEnemies, TankDestroyed},
+    explosion::Explosion,
+    game_data::GameData,
+    grass::Grass,
+    input::{PlayerInputs, TankInput},
+    iron::Iron,
+    net::{Lockstep, Session},
+    pathfinding::PathGrid,
+    power_up::PowerUp,
+    river::River,
+    shield::Shield,
+    simulation::{Clock, GameRng},
+    snow::Snow,
+    spatial::SpatialGrid,
+    star::Star,
+    state,
+    survival::Waves,
+    tank::{ai::EnemySpawner, controller::Controller, p2::Ally, player, AnimationTimer, Tank},
+    terrain::{TileEntities, TileMap},
+    utils::{Player, AI, MAX_PLAYERS},
+    versus::Match,
+};
+
+/// Ticks simulated at most after the last one the other player's input is known
+pub const MAX_PREDICTION: u64 = 30;
+
+/// Entities of the game, as opposed to e.g. the camera or the title menu
+pub type Simulated = Or<(
+    With<state::State>,
+    With<Star>,
+    With<Explosion>,
+    With<Shield>,
+)>;
+
+type CopyComponent = fn(&World, Entity) -> Option<Box<dyn SavedComponent>>;
+type CopyResource = fn(&World) -> Option<Box<dyn SavedResource>>;
+
+const COMPONENTS: &[CopyComponent] = &[
+    copy_component::<Transform>,
+    copy_component::<GlobalTransform>,
+    copy_component::<Parent>,
+    copy_component::<PreviousParent>,
+    copy_component::<Children>,
+    copy_component::<Visibility>,
+    copy_component::<Sprite>,
+    copy_component::<Handle<Image>>,
+    copy_component::<TextureAtlasSprite>,
+    copy_component::<Handle<TextureAtlas>>,
+    copy_component::<Timer>,
+    copy_component::<AnimationTimer>,
+    copy_component::<Collider>,
+    copy_component::<Hitbox>,
+    copy_component::<state::State>,
+    copy_component::<Controller>,
+    copy_component::<Star>,
+    copy_component::<Explosion>,
+    copy_component::<Shield>,
+    copy_component::<Tank>,
+    copy_component::<Player>,
+    copy_component::<AI>,
+    copy_component::<Bullet>,
+    copy_component::<Brick>,
+    copy_component::<Iron>,
+    copy_component::<River>,
+    copy_component::<Grass>,
+    copy_component::<Snow>,
+    copy_component::<Base>,
+    copy_component::<PowerUp>,
+];
+
+const RESOURCES: &[CopyResource] = &[
+    copy_resource::<GameRng>,
+    copy_resource::<GameData>,
+    copy_resource::<PlayerInputs>,
+    copy_resource::<TileMap>,
+    copy_resource::<TileEntities>,
+    copy_resource::<PathGrid>,
+    copy_resource::<EnemySpawner>,
+    copy_resource::<Ally>,
+    copy_resource::<Waves>,
+    copy_resource::<Match>,
+];
+
+trait SavedComponent: Send + Sync {
+    fn insert(&self, entity: &mut EntityMut);
+}
+
+impl<T: Component + Clone> SavedComponent for T {
+    fn insert(&self, entity: &mut EntityMut) {
+        entity.insert(self.clone());
+    }
+}
+
+trait SavedResource: Send + Sync {
+    fn insert(&self, world: &mut World);
+}
+
+impl<T: Send + Sync + Clone + 'static> SavedResource for T {
+    fn insert(&self, world: &mut World) {
+        world.insert_resource(self.clone());
+    }
+}
+
+fn copy_component<T: Component + Clone>(
+    world: &World,
+    entity: Entity,
+) -> Option<Box<dyn SavedComponent>> {
+    let component = world.get::<T>(entity)?;
+    Some(Box::new(component.clone()))
+}
+
+fn copy_resource<T: Send + Sync + Clone + 'static>(
+    world: &World,
+) -> Option<Box<dyn SavedResource>> {
+    let resource = world.get_resource::<T>()?;
+    Some(Box::new(resource.clone()))
+}
+
+/// The game at the beginning of a tick
+pub struct Snapshot {
+    tick: u64,
+    entities: Vec<(Entity, Vec<Box<dyn SavedComponent>>)>, // in the order of queries
+    resources: Vec<Box<dyn SavedResource>>,
+    destroyed: Vec<TankDestroyed>, // sent during the tick before, handled during this one
+}
+
+impl Snapshot {
+    /// `destroyed` reads events of destroyed tanks, it has to be given every tick
+    pub fn take(
+        world: &mut World,
+        tick: u64,
+        destroyed: &mut ManualEventReader<TankDestroyed>,
+    ) -> Self {
+        let entities: Vec<Entity> = world
+            .query_filtered::<Entity, Simulated>()
+            .iter(world)
+            .collect();
+        let entities = entities
+            .into_iter()
+            .map(|entity| {
+                let components = COMPONENTS
+                    .iter()
+                    .filter_map(|copy| copy(world, entity))
+                    .collect();
+                (entity, components)
+            })
+            .collect();
+        let resources = RESOURCES.iter().filter_map(|copy| copy(world)).collect();
+        let events = world.get_resource::<Events<TankDestroyed>>().unwrap();
+        Self {
+            tick,
+            entities,
+            resources,
+            destroyed: destroyed.iter(events).cloned().collect(),
+        }
+    }
+
+    /// Puts the game back as it was, except the clock
+    pub fn restore(&self, world: &mut World) {
+        let entities: Vec<Entity> = world
+            .query_filtered::<Entity, Simulated>()
+            .iter(world)
+            .collect();
+        for entity in entities {
+            world.despawn(entity);
+        }
+        // spawned in the same order, so queries go through them in the same order as before
+        for (entity, components) in &self.entities {
+            if let Some(mut entity) = world.get_or_spawn(*entity) {
+                for component in components {
+                    (**component).insert(&mut entity);
+                }
+            }
+        }
+        for resource in &self.resources {
+            (**resource).insert(world);
+        }
+        // rebuilt at the beginning of the tick, since every collider is spawned again
+        world.insert_resource(SpatialGrid::new());
+        let mut events = world.get_resource_mut::<Events<TankDestroyed>>().unwrap();
+        events.clear();
+        for event in &self.destroyed {
+            events.send(event.clone());
+        }
+        if world.contains_resource::<Match>() {
+            world.resource_scope(|world, mut versus: Mut<Match>| {
+                versus.skip_destroyed(world.get_resource().unwrap());
+            });
+        }
+        // handled during the ticks gone back over, which are simulated again
+        clear_events::<ChangeBaseWall>(world);
+        clear_events::<DestroyAllEnemies>(world);
+    }
+}
+
+fn clear_events<T: Send + Sync + 'static>(world: &mut World) {
+    if let Some(mut events) = world.get_resource_mut::<Events<T>>() {
+        events.clear();
+    }
+}
+
+/// Inputs exchanged with the other peer, and what is needed to go back when a prediction is
+/// wrong
+pub struct Rollback {
+    exchange: Lockstep,
+    predicted: Vec<TankInput>, // the other player's inputs ticks were simulated with
+    snapshots: VecDeque<Snapshot>, // of the ticks which can still be wrong, oldest first
+    destroyed: ManualEventReader<TankDestroyed>,
+}
+
+impl Rollback {
+    pub fn new(session: Session) -> Self {
+        Self {
+            exchange: Lockstep::new(session),
+            predicted: Vec::new(),
+            snapshots: VecDeque::new(),
+            destroyed: ManualEventReader::default(),
+        }
+    }
+
+    /// The other player's input of a tick, or the last one received if it has not arrived
+    fn predict(&self, tick: u64) -> TankInput {
+        let received = self.exchange.received();
+        if received == 0 {
+            return TankInput::default();
+        }
+        self.exchange.remote(tick.min(received)).unwrap()
+    }
+
+    /// The first tick simulated with a prediction which turned out wrong
+    fn mispredicted(&self, simulated: u64) -> Option<u64> {
+        let first = self.snapshots.front()?.tick;
+        let last = self.exchange.received().min(simulated);
+        (first..=last).find(|tick| {
+            self.exchange.remote(*tick) != self.predicted.get(*tick as usize - 1).copied()
+        })
+    }
+}
+
+/// Reads the other peer's inputs, and goes back to the first tick they were predicted wrong
+pub fn receive(world: &mut World) {
+    world.resource_scope(|world, mut rollback: Mut<Rollback>| {
+        rollback.exchange.poll();
+        let simulated = world.get_resource::<Clock>().unwrap().tick;
+        if let Some(tick) = rollback.mispredicted(simulated) {
+            let index = rollback
+                .snapshots
+                .iter()
+                .position(|snapshot| snapshot.tick == tick)
+                .unwrap();
+            // the ticks after it are simulated again, and so are their snapshots taken again
+            let snapshot = rollback.snapshots.drain(index..).next().unwrap();
+            snapshot.restore(world);
+            rollback.predicted.truncate(tick as usize - 1);
+            let mut clock = world.get_resource_mut::<Clock>().unwrap();
+            clock.rewind(tick - 1);
+        }
+        // inputs of both players are known up to here, these ticks are never simulated again
+        let settled = rollback.exchange.received().min(simulated);
+        rollback
+            .snapshots
+            .retain(|snapshot| snapshot.tick > settled);
+        let mut clock = world.get_resource_mut::<Clock>().unwrap();
+        clock.limit = Some(rollback.exchange.received() + MAX_PREDICTION);
+    });
+}
+
+/// Takes a snapshot at the beginning of every tick
+pub fn save_snapshot(world: &mut World) {
+    world.resource_scope(|world, mut rollback: Mut<Rollback>| {
+        let tick = world.get_resource::<Clock>().unwrap().tick;
+        let snapshot = Snapshot::take(world, tick, &mut rollback.destroyed);
+        rollback.snapshots.push_back(snapshot);
+    });
+}
+
+/// Samples the local keyboard for a tick ahead, and pushes both players' inputs of this tick,
+/// the other player's predicted if it has not arrived
+pub fn inputs(
+    keyboard: Res<Input<KeyCode>>,
+    clock: Res<Clock>,
+    mut rollback: ResMut<Rollback>,
+    mut inputs: ResMut<PlayerInputs>,
+) {
+    let tick = clock.tick;
+    // a tick simulated again keeps the input sampled the first time
+    if rollback.exchange.sampled() < tick + rollback.exchange.delay() {
+        rollback.exchange.add_local(TankInput::from_keyboard(
+            &keyboard,
+            &player::DIRECTION_KEYS[0],
+            player::FIRE_KEYS[0],
+        ));
+    }
+    let remote = rollback.predict(tick);
+    rollback.predicted.truncate(tick as usize - 1);
+    rollback.predicted.push(remote);
+    let mut frame = [TankInput::default(); MAX_PLAYERS];
+    frame[1 - rollback.exchange.player()] = remote;
+    frame[rollback.exchange.player()] = rollback.exchange.local(tick).unwrap();
+    inputs.push(frame);
+}
+
+/// Sends local inputs the other peer has not acknowledged, once per frame
+pub fn send(mut rollback: ResMut<Rollback>) {
+    rollback.exchange.transmit(Instant::now());
+}
+
+#[test]
+fn test_snapshot() {
+    use rand::RngCore;
+
+    use crate::{
+        config::Difficulty,
+        input::InputSource,
+        team::{FriendlyFire, Teams},
+        utils::Direction,
+        versus::Rules,
+        BattleCityPlugins,
+    };
+
+    // in co-op against AI, and in a versus match, whose score is compared too
+    for versus in [
+        None,
+        Some(Rules {
+            rounds: 9,
+            kills: 9,
+        }),
+    ] {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(BattleCityPlugins {
+                headless: true,
+                seed: 7,
+                difficulty: Difficulty::Nightmare,
+                input: InputSource::External,
+                ally: true,
+                save: None,
+                players: 2,
+                teams: match versus {
+                    Some(_) => Teams::VERSUS,
+                    None => Teams::COOP,
+                },
+                friendly_fire: FriendlyFire::Off,
+                survival: None,
+                versus,
+            });
+        // P1 drives around and fires, the ally plays P2, in versus P1 destroys it during tick 161
+        // and wins a round by tick 500, compared during the round and after it
+        let script = |tick: u64| {
+            let directions = [
+                Direction::Up,
+                Direction::Right,
+                Direction::Up,
+                Direction::Left,
+            ];
+            let mut input = TankInput::default();
+            input.press(directions[(tick / 60 % 4) as usize]);
+            input.set(TankInput::FIRE, tick % 20 < 10);
+            let mut frame = [TankInput::default(); MAX_PLAYERS];
+            frame[0] = input;
+            frame
+        };
+        let fingerprint = |world: &mut World| {
+            let mut things: Vec<String> = world
+                .query_filtered::<(&Transform, Option<&state::State>), Simulated>()
+                .iter(world)
+                .map(|(transform, state)| format!("{:?} {:?}", transform.translation, state))
+                .collect();
+            things.sort();
+            let mut rng = world.get_resource::<GameRng>().unwrap().clone();
+            things.push(rng.next_u64().to_string());
+            if let Some(versus) = world.get_resource::<Match>() {
+                things.push(format!("{:?} {:?}", versus.wins, versus.kills));
+            }
+            things
+        };
+
+        let mut destroyed = ManualEventReader::default();
+        let mut snapshot = None;
+        let mut expected = Vec::new();
+        for tick in 1..=500 {
+            let taken = Snapshot::take(&mut app.world, tick, &mut destroyed);
+            // the tanks destroyed during the tick before are in it
+            if tick == 162 {
+                snapshot = Some(taken);
+            }
+            let mut inputs = app.world.get_resource_mut::<PlayerInputs>().unwrap();
+            inputs.push(script(tick));
+            app.update();
+            if tick % 100 == 0 {
+                expected.push(fingerprint(&mut app.world));
+            }
+        }
+
+        // the same ticks again from the snapshot play the same, also when what spawns since then
+        // gets other entities than the first time, as it may on the other peer, here once some
+        // entities were taken and freed again
+        let snapshot = snapshot.unwrap();
+        for taken in [0, 37] {
+            snapshot.restore(&mut app.world);
+            let taken: Vec<Entity> = (0..taken).map(|_| app.world.spawn().id()).collect();
+            for entity in taken {
+                app.world.despawn(entity);
+            }
+            app.world.get_resource_mut::<Clock>().unwrap().tick = 161;
+            for tick in 162..=500 {
+                let mut inputs = app.world.get_resource_mut::<PlayerInputs>().unwrap();
+                inputs.push(script(tick));
+                app.update();
+                if tick % 100 == 0 {
+                    assert_eq!(
+                        fingerprint(&mut app.world),
+                        expected[tick as usize / 100 - 1]
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,1027 @@
+//! Saving a game when the window closes, and resuming it from the title menu.
+//!
+//! A save holds everything the simulation needs to go on: terrain with its damage, tanks with
+//! the state of their AI controllers, stars, bullets, power ups, explosions, every timer, the
+//! position of the random number generator and the options the game was started with. A
+//! resumed game plays on tick for tick the same as the one saved would have.
+
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use bevy::{
+    app::{AppExit, Events, ManualEventReader},
+    prelude::*,
+};
+
+use crate::{
+    brick::Cells,
+    bullet::{self, Bullet},
+    config::{Config, Difficulty},
+    consts::SCALE,
+    explosion::{self, Explosion},
+    game_data::GameData,
+    input::{PlayerInputs, TankInput},
+    menu::AppState,
+    pathfinding::{Node, PathGrid, NODES},
+    power_up::{self, PowerType, PowerUp},
+    shield::{self, Shield},
+    simulation::{Clock, GameRng},
+    star::{self, Star},
+    state,
+    tank::{
+        ai::{self, EnemySpawner},
+        controller::{Controller, Controllers, TankController},
+        p2::Ally,
+        player, AnimationTimer, Data, Tank,
+    },
+    team::{FriendlyFire, Teams},
+    terrain::{self, Tile, TileEntities, TileMap},
+    texture::Textures,
+    utils::{Direction, Owner, AI_TEAM, GRID_SIZE, MAX_PLAYERS, PLAYER_COLORS},
+};
+
+pub const SAVE_PATH: &str = "battle_city.sav";
+const SAVE_MAGIC: &[u8; 4] = b"BCSV";
+const SAVE_VERSION: u8 = 3;
+const DIRECTIONS: [Direction; 4] = [
+    Direction::Up,
+    Direction::Right,
+    Direction::Down,
+    Direction::Left,
+];
+const POWER_TYPES: [PowerType; 7] = [
+    PowerType::Helmet,
+    PowerType::Clock,
+    PowerType::Shovel,
+    PowerType::Star,
+    PowerType::Grenade,
+    PowerType::Tank,
+    PowerType::Gun,
+];
+
+/// Saves the game to a file when the game exits, and offers "Continue" on the title menu
+pub struct SavePlugin {
+    pub path: PathBuf,
+}
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SaveFile(self.path.clone()))
+            .init_resource::<ExitReader>()
+            .add_system_set(
+                SystemSet::on_enter(AppState::Playing).with_system(resume.after("spawn_terrian")),
+            )
+            .add_system_to_stage(CoreStage::Last, save_on_exit.exclusive_system());
+    }
+}
+
+/// Where the game is saved
+pub struct SaveFile(pub PathBuf);
+
+/// A saved game to resume when the game starts, instead of a new stage
+pub struct Resume(pub SaveGame);
+
+#[derive(Default)]
+struct ExitReader(ManualEventReader<AppExit>);
+
+#[derive(Debug, Clone)]
+pub struct TankSave {
+    pub translation: Vec3,
+    pub sprite: usize,
+    pub animation: Timer,
+    pub data: Data,
+    pub shield: Option<(usize, Timer)>, // sprite and timer of the shield
+    pub controller: Vec<u8>,            // state of an AI tank's controller
+}
+
+#[derive(Debug, Clone)]
+pub struct StarSave {
+    pub translation: Vec3,
+    pub owner: Owner,
+    pub level: u8,
+    pub bonus: bool,
+    pub done: bool,
+    pub state: star::State,
+    pub sprite: usize,
+    pub timer: Timer,
+}
+
+#[derive(Debug, Clone)]
+pub struct BulletSave {
+    pub translation: Vec3,
+    pub direction: Direction,
+    pub source: Owner,
+    pub level: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExplosionSave {
+    pub translation: Vec3,
+    pub is_big: bool,
+    pub sprite: usize,
+    pub timer: Timer,
+}
+
+/// Everything on battle field and in resources of the simulation at the end of a tick
+#[derive(Clone)]
+pub struct SaveGame {
+    pub seed: u64,
+    pub rng_position: u128, // how far the game's randomness went from the seed
+    pub tick: u64,
+    pub difficulty: Difficulty,
+    pub players: u8,
+    pub teams: Teams,
+    pub friendly_fire: FriendlyFire,
+    pub game_data: GameData,
+    pub spawner_elapsed: Duration,
+    pub spawner_next: usize,
+    pub inputs: [TankInput; MAX_PLAYERS], // of the saved tick, by player ID
+    pub ally: Option<Vec<u8>>,            // state of the ally driving P2
+    pub path_version: u64,
+    pub tiles: Vec<Tile>, // row by row from the top left quarter block
+    pub tanks: Vec<TankSave>,
+    pub stars: Vec<StarSave>,
+    pub bullets: Vec<BulletSave>,
+    pub power_ups: Vec<(Vec3, PowerType)>,
+    pub explosions: Vec<ExplosionSave>,
+}
+
+impl SaveGame {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        Self::from_bytes(&bytes)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        File::create(path)?.write_all(&self.to_bytes())
+    }
+
+    /// magic, version, then the fields in order, numbers are little endian and lists start with
+    /// their length
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer(Vec::new());
+        w.0.extend_from_slice(SAVE_MAGIC);
+        w.u8(SAVE_VERSION);
+        w.u64(self.seed);
+        w.u128(self.rng_position);
+        w.u64(self.tick);
+        w.u8(self.difficulty as u8);
+        w.u8(self.players);
+        w.0.extend_from_slice(&self.teams.0);
+        w.u8(self.friendly_fire as u8);
+
+        let game = &self.game_data;
+        w.u8(game.stage);
+        w.u8(game.ai_tanks);
+        for player in 0..MAX_PLAYERS {
+            w.u8(game.lifes[player]);
+            w.u32(game.scores[player]);
+        }
+        w.timer(&game.restore_timer);
+        w.timer(&game.blink_timer);
+        w.bool(game.base_wall_changed);
+        w.owner(game.base_wall_changed_by);
+        w.bool(game.base_wall_normal);
+        w.bool(game.base_destroyed);
+        w.duration(self.spawner_elapsed);
+        w.u8(self.spawner_next as u8);
+        for input in self.inputs {
+            w.u8(input.bits());
+        }
+        w.bool(self.ally.is_some());
+        if let Some(ally) = &self.ally {
+            w.bytes(ally);
+        }
+        w.u64(self.path_version);
+
+        for tile in &self.tiles {
+            let (kind, cells) = match tile {
+                Tile::Empty => (0, 0),
+                Tile::Brick(cells) => (1, cells.bits()),
+                Tile::Iron => (2, 0),
+                Tile::River => (3, 0),
+                Tile::Grass => (4, 0),
+                Tile::Snow => (5, 0),
+            };
+            w.u8(kind);
+            w.u8(cells);
+        }
+
+        w.u32(self.tanks.len() as u32);
+        for tank in &self.tanks {
+            w.vec3(tank.translation);
+            w.u32(tank.sprite as u32);
+            w.timer(&tank.animation);
+            let data = &tank.data;
+            w.direction(data.direction);
+            w.owner(data.owner);
+            w.u8(data.level);
+            w.bool(data.shield);
+            w.bool(data.amphibious);
+            w.u32(data.base_sprite as u32);
+            w.bool(data.bonus);
+            w.bool(tank.shield.is_some());
+            if let Some((sprite, timer)) = &tank.shield {
+                w.u32(*sprite as u32);
+                w.timer(timer);
+            }
+            w.bytes(&tank.controller);
+        }
+        w.u32(self.stars.len() as u32);
+        for star in &self.stars {
+            w.vec3(star.translation);
+            w.owner(star.owner);
+            w.u8(star.level);
+            w.bool(star.bonus);
+            w.bool(star.done);
+            w.bool(star.state == star::State::Shrink);
+            w.u32(star.sprite as u32);
+            w.timer(&star.timer);
+        }
+        w.u32(self.bullets.len() as u32);
+        for bullet in &self.bullets {
+            w.vec3(bullet.translation);
+            w.direction(bullet.direction);
+            w.owner(bullet.source);
+            w.u8(bullet.level);
+        }
+        w.u32(self.power_ups.len() as u32);
+        for (translation, p_type) in &self.power_ups {
+            w.vec3(*translation);
+            w.u8(POWER_TYPES.iter().position(|p| p == p_type).unwrap() as u8);
+        }
+        w.u32(self.explosions.len() as u32);
+        for explosion in &self.explosions {
+            w.vec3(explosion.translation);
+            w.bool(explosion.is_big);
+            w.u32(explosion.sprite as u32);
+            w.timer(&explosion.timer);
+        }
+        w.0
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < 5 || &bytes[..4] != SAVE_MAGIC {
+            return Err(invalid("not a save file"));
+        }
+        if bytes[4] != SAVE_VERSION {
+            return Err(invalid("unsupported save version"));
+        }
+        let mut r = Reader { bytes, pos: 5 };
+        let seed = r.u64()?;
+        let rng_position = r.u128()?;
+        let tick = r.u64()?;
+        let difficulty = *Difficulty::ALL
+            .get(r.u8()? as usize)
+            .ok_or_else(|| invalid("unknown difficulty"))?;
+        let players = r.u8()?;
+        if !(1..=MAX_PLAYERS).contains(&(players as usize)) {
+            return Err(invalid("players must be 1 to 4"));
+        }
+        let teams = Teams(r.take()?);
+        let friendly_fire = match r.u8()? {
+            0 => FriendlyFire::Off,
+            1 => FriendlyFire::On,
+            _ => return Err(invalid("unknown friendly fire")),
+        };
+
+        let mut game_data = GameData::new();
+        game_data.stage = r.u8()?;
+        game_data.ai_tanks = r.u8()?;
+        for player in 0..MAX_PLAYERS {
+            game_data.lifes[player] = r.u8()?;
+            game_data.scores[player] = r.u32()?;
+        }
+        game_data.restore_timer = r.timer()?;
+        game_data.blink_timer = r.timer()?;
+        game_data.base_wall_changed = r.bool()?;
+        game_data.base_wall_changed_by = r.owner()?;
+        game_data.base_wall_normal = r.bool()?;
+        game_data.base_destroyed = r.bool()?;
+        let spawner_elapsed = r.duration()?;
+        let spawner_next = r.u8()? as usize;
+        let mut inputs = [TankInput::default(); MAX_PLAYERS];
+        for input in &mut inputs {
+            *input = TankInput::from_bits(r.u8()?);
+        }
+        let ally = match r.bool()? {
+            true => Some(r.bytes()?),
+            false => None,
+        };
+        let path_version = r.u64()?;
+
+        let mut tiles = Vec::with_capacity(GRID_SIZE * GRID_SIZE);
+        for _ in 0..GRID_SIZE * GRID_SIZE {
+            let (kind, cells) = (r.u8()?, r.u8()?);
+            tiles.push(match kind {
+                0 => Tile::Empty,
+                1 => Tile::Brick(Cells::from_bits(cells)),
+                2 => Tile::Iron,
+                3 => Tile::River,
+                4 => Tile::Grass,
+                5 => Tile::Snow,
+                _ => return Err(invalid("unknown tile")),
+            });
+        }
+
+        let mut tanks = Vec::new();
+        for _ in 0..r.u32()? {
+            tanks.push(TankSave {
+                translation: r.vec3()?,
+                sprite: r.u32()? as usize,
+                animation: r.timer()?,
+                data: Data {
+                    direction: r.direction()?,
+                    owner: r.owner()?,
+                    level: r.u8()?,
+                    shield: r.bool()?,
+                    amphibious: r.bool()?,
+                    base_sprite: r.u32()? as usize,
+                    bonus: r.bool()?,
+                },
+                shield: match r.bool()? {
+                    true => Some((r.u32()? as usize, r.timer()?)),
+                    false => None,
+                },
+                controller: r.bytes()?,
+            });
+        }
+        let mut stars = Vec::new();
+        for _ in 0..r.u32()? {
+            stars.push(StarSave {
+                translation: r.vec3()?,
+                owner: r.owner()?,
+                level: r.u8()?,
+                bonus: r.bool()?,
+                done: r.bool()?,
+                state: match r.bool()? {
+                    true => star::State::Shrink,
+                    false => star::State::Enlarge,
+                },
+                sprite: r.u32()? as usize,
+                timer: r.timer()?,
+            });
+        }
+        let mut bullets = Vec::new();
+        for _ in 0..r.u32()? {
+            bullets.push(BulletSave {
+                translation: r.vec3()?,
+                direction: r.direction()?,
+                source: r.owner()?,
+                level: r.u8()?,
+            });
+        }
+        let mut power_ups = Vec::new();
+        for _ in 0..r.u32()? {
+            let translation = r.vec3()?;
+            let p_type = *POWER_TYPES
+                .get(r.u8()? as usize)
+                .ok_or_else(|| invalid("unknown power up"))?;
+            power_ups.push((translation, p_type));
+        }
+        let mut explosions = Vec::new();
+        for _ in 0..r.u32()? {
+            explosions.push(ExplosionSave {
+                translation: r.vec3()?,
+                is_big: r.bool()?,
+                sprite: r.u32()? as usize,
+                timer: r.timer()?,
+            });
+        }
+        if r.pos != bytes.len() {
+            return Err(invalid("corrupted save file"));
+        }
+        Ok(Self {
+            seed,
+            rng_position,
+            tick,
+            difficulty,
+            players,
+            teams,
+            friendly_fire,
+            game_data,
+            spawner_elapsed,
+            spawner_next,
+            inputs,
+            ally,
+            path_version,
+            tiles,
+            tanks,
+            stars,
+            bullets,
+            power_ups,
+            explosions,
+        })
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Writes values of a saved game, e.g. the state of a `TankController`
+pub struct Writer(Vec<u8>);
+
+impl Writer {
+    pub fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.0.push(value as u8);
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u128(&mut self, value: u128) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn duration(&mut self, value: Duration) {
+        self.u64(value.as_nanos() as u64);
+    }
+
+    /// duration, elapsed time, then if it repeats
+    pub fn timer(&mut self, value: &Timer) {
+        self.duration(value.duration());
+        self.duration(value.elapsed());
+        self.bool(value.repeating());
+    }
+
+    pub fn vec3(&mut self, value: Vec3) {
+        for v in value.to_array() {
+            self.0.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+
+    pub fn direction(&mut self, value: Direction) {
+        self.u8(DIRECTIONS.iter().position(|d| *d == value).unwrap() as u8);
+    }
+
+    /// player ID, or 255 for AI, then team
+    pub fn owner(&mut self, value: Owner) {
+        self.u8(value.player.unwrap_or(u8::MAX));
+        self.u8(value.team);
+    }
+
+    pub fn node(&mut self, value: Node) {
+        self.u8(value.0 as u8);
+        self.u8(value.1 as u8);
+    }
+
+    /// length, then the bytes
+    pub fn bytes(&mut self, value: &[u8]) {
+        self.u32(value.len() as u32);
+        self.0.extend_from_slice(value);
+    }
+}
+
+/// Reads back what `Writer` wrote, in the same order
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos + N)
+            .ok_or_else(|| invalid("truncated save file"))?;
+        self.pos += N;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    pub fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    pub fn bool(&mut self) -> io::Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    pub fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    pub fn u128(&mut self) -> io::Result<u128> {
+        Ok(u128::from_le_bytes(self.take()?))
+    }
+
+    pub fn f32(&mut self) -> io::Result<f32> {
+        Ok(f32::from_le_bytes(self.take()?))
+    }
+
+    pub fn duration(&mut self) -> io::Result<Duration> {
+        Ok(Duration::from_nanos(self.u64()?))
+    }
+
+    pub fn timer(&mut self) -> io::Result<Timer> {
+        let (duration, elapsed) = (self.duration()?, self.duration()?);
+        let mut timer = Timer::new(duration, self.bool()?);
+        if !timer.repeating() && elapsed >= duration {
+            timer.tick(duration); // finished, `set_elapsed` does not mark it
+        } else {
+            timer.set_elapsed(elapsed);
+        }
+        Ok(timer)
+    }
+
+    pub fn vec3(&mut self) -> io::Result<Vec3> {
+        Ok(Vec3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    pub fn direction(&mut self) -> io::Result<Direction> {
+        DIRECTIONS
+            .get(self.u8()? as usize)
+            .copied()
+            .ok_or_else(|| invalid("unknown direction"))
+    }
+
+    pub fn owner(&mut self) -> io::Result<Owner> {
+        match (self.u8()?, self.u8()?) {
+            (u8::MAX, AI_TEAM) => Ok(Owner::AI),
+            (player, team) if (player as usize) < MAX_PLAYERS && team != AI_TEAM => Ok(Owner {
+                team,
+                player: Some(player),
+            }),
+            _ => Err(invalid("unknown owner")),
+        }
+    }
+
+    pub fn node(&mut self) -> io::Result<Node> {
+        let node = (self.u8()? as i32, self.u8()? as i32);
+        match node.0 < NODES && node.1 < NODES {
+            true => Ok(node),
+            false => Err(invalid("node out of battle field")),
+        }
+    }
+
+    pub fn bytes(&mut self) -> io::Result<Vec<u8>> {
+        let len = self.u32()? as usize;
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| invalid("truncated save file"))?;
+        self.pos += len;
+        Ok(bytes.to_vec())
+    }
+}
+
+/// The state of a controller, as `TankController::save` writes it
+fn save_controller(controller: &dyn TankController) -> Vec<u8> {
+    let mut w = Writer(Vec::new());
+    controller.save(&mut w);
+    w.0
+}
+
+/// Gives a controller back the state `save_controller` took, it keeps its own if the state does
+/// not fit, e.g. when `Controllers` picked another kind of controller
+fn load_controller(controller: &mut dyn TankController, bytes: &[u8]) {
+    let mut r = Reader { bytes, pos: 0 };
+    if let Err(err) = controller.load(&mut r) {
+        error!("failed to resume a controller: {}", err);
+    }
+}
+
+/// The game being played, as it is at the end of the tick
+pub fn capture(world: &mut World) -> SaveGame {
+    let mut tanks = world.query_filtered::<(
+        &Transform,
+        &TextureAtlasSprite,
+        &AnimationTimer,
+        &state::State,
+        Option<&Controller>,
+        Option<&Children>,
+    ), With<Tank>>();
+    let mut shields = world.query_filtered::<(&TextureAtlasSprite, &Timer), With<Shield>>();
+    let mut stars = world.query::<(&Transform, &TextureAtlasSprite, &Timer, &Star)>();
+    let mut bullets = world.query_filtered::<(&Transform, &state::State), With<Bullet>>();
+    let mut power_ups = world.query_filtered::<(&Transform, &state::State), With<PowerUp>>();
+    let mut explosions = world.query::<(&Transform, &TextureAtlasSprite, &Timer, &Explosion)>();
+    let world = &*world;
+
+    let config = world.get_resource::<Config>().unwrap();
+    let rng = world.get_resource::<GameRng>().unwrap();
+    let spawner = world.get_resource::<EnemySpawner>().unwrap();
+    let tiles = world.get_resource::<TileMap>().unwrap();
+    SaveGame {
+        seed: rng.seed(),
+        rng_position: rng.position(),
+        tick: world.get_resource::<Clock>().unwrap().tick,
+        difficulty: config.difficulty,
+        players: config.players as u8,
+        teams: config.teams,
+        friendly_fire: *world.get_resource::<FriendlyFire>().unwrap(),
+        game_data: world.get_resource::<GameData>().unwrap().clone(),
+        spawner_elapsed: spawner.elapsed,
+        spawner_next: spawner.next,
+        inputs: world.get_resource::<PlayerInputs>().unwrap().current(),
+        ally: world
+            .get_resource::<Ally>()
+            .map(|ally| save_controller(&ally.0)),
+        path_version: world.get_resource::<PathGrid>().unwrap().version(),
+        tiles: tiles.iter().map(|(_, _, tile)| tile).collect(),
+        tanks: tanks
+            .iter(world)
+            .map(
+                |(transform, sprite, animation, state, controller, children)| {
+                    let shield = children
+                        .and_then(|children| {
+                            children.iter().find_map(|c| shields.get(world, *c).ok())
+                        })
+                        .map(|(sprite, timer)| (sprite.index, timer.clone()));
+                    TankSave {
+                        translation: transform.translation,
+                        sprite: sprite.index,
+                        animation: animation.0.clone(),
+                        data: state.as_tank().clone(),
+                        shield,
+                        controller: controller.map_or(Vec::new(), |c| save_controller(&*c.0)),
+                    }
+                },
+            )
+            .collect(),
+        stars: stars
+            .iter(world)
+            .map(|(transform, sprite, timer, star)| StarSave {
+                translation: transform.translation,
+                owner: star.owner(),
+                level: star.level(),
+                bonus: star.bonus(),
+                done: star.done(),
+                state: star.state(),
+                sprite: sprite.index,
+                timer: timer.clone(),
+            })
+            .collect(),
+        bullets: bullets
+            .iter(world)
+            .map(|(transform, state)| {
+                let bullet = state.as_bullet();
+                BulletSave {
+                    translation: transform.translation,
+                    direction: bullet.direction,
+                    source: bullet.source,
+                    level: bullet.level,
+                }
+            })
+            .collect(),
+        power_ups: power_ups
+            .iter(world)
+            .map(|(transform, state)| (transform.translation, *state.as_power_up()))
+            .collect(),
+        explosions: explosions
+            .iter(world)
+            .map(|(transform, sprite, timer, explosion)| ExplosionSave {
+                translation: transform.translation,
+                is_big: explosion.is_big(),
+                sprite: sprite.index,
+                timer: timer.clone(),
+            })
+            .collect(),
+    }
+}
+
+/// Saves the game being played when the game exits. A lost game is not worth continuing, so its
+/// save is removed.
+pub fn save_on_exit(world: &mut World) {
+    let exited = world.resource_scope(|world, mut reader: Mut<ExitReader>| {
+        let events = world.get_resource::<Events<AppExit>>().unwrap();
+        reader.0.iter(events).next().is_some()
+    });
+    let app_state = world.get_resource::<State<AppState>>().unwrap();
+    if !exited || *app_state.current() != AppState::Playing {
+        return;
+    }
+    let path = world.get_resource::<SaveFile>().unwrap().0.clone();
+    if world.get_resource::<GameData>().unwrap().base_destroyed {
+        let _ = fs::remove_file(&path);
+        return;
+    }
+    match capture(world).save(&path) {
+        Ok(()) => info!("game saved to {}", path.display()),
+        Err(err) => error!("failed to save game: {}", err),
+    }
+}
+
+/// Puts a saved game onto battle field, after the stage has spawned boundaries and the base
+pub fn resume(
+    mut commands: Commands,
+    resume: Option<Res<Resume>>,
+    textures: Res<Textures>,
+    controllers: Res<Controllers>,
+    ally: Option<ResMut<Ally>>,
+    mut clock: ResMut<Clock>,
+    mut rng: ResMut<GameRng>,
+    mut game_data: ResMut<GameData>,
+    mut spawner: ResMut<EnemySpawner>,
+    mut inputs: ResMut<PlayerInputs>,
+    mut grid: ResMut<PathGrid>,
+    mut tiles: ResMut<TileMap>,
+    mut tile_entities: ResMut<TileEntities>,
+) {
+    let resume = match resume {
+        Some(resume) => resume,
+        None => return,
+    };
+    let save = &resume.0;
+    let texture = &textures.texture;
+    clock.tick = save.tick;
+    *rng = GameRng::resumed(save.seed, save.rng_position);
+    *game_data = save.game_data.clone();
+    spawner.elapsed = save.spawner_elapsed;
+    spawner.next = save.spawner_next;
+    *inputs = PlayerInputs::resumed(save.inputs);
+    if let (Some(mut ally), Some(state)) = (ally, &save.ally) {
+        load_controller(&mut ally.0, state);
+    }
+    for (i, tile) in save.tiles.iter().enumerate() {
+        tiles.set(i % GRID_SIZE, i / GRID_SIZE, *tile);
+    }
+    *grid = PathGrid::from_tiles(&tiles, save.path_version);
+    // bricks are on battle field in the first tick, as they were before saving
+    terrain::respawn_changed(&mut commands, texture, &mut tiles, &mut tile_entities);
+
+    for tank in &save.tanks {
+        let data = &tank.data;
+        let entity = match data.owner.player {
+            Some(_) => player::spawn(&mut commands, texture.clone(), tank.translation, data.owner),
+            None => {
+                let mut controller = (controllers.select)(save.game_data.stage, data.level);
+                load_controller(&mut *controller, &tank.controller);
+                ai::spawn(
+                    &mut commands,
+                    texture.clone(),
+                    tank.translation,
+                    data.level,
+                    data.bonus,
+                    controller,
+                )
+            }
+        };
+        commands
+            .entity(entity)
+            .insert(Transform {
+                translation: tank.translation,
+                scale: Vec3::splat(SCALE),
+                ..Default::default()
+            })
+            .insert(TextureAtlasSprite {
+                color: match data.owner.player {
+                    Some(id) => PLAYER_COLORS[id as usize],
+                    None => Color::WHITE,
+                },
+                ..TextureAtlasSprite::new(tank.sprite)
+            })
+            .insert(AnimationTimer(tank.animation.clone()))
+            .insert(state::State::Tank(data.clone()));
+        if let Some((sprite, timer)) = &tank.shield {
+            let shield = shield::spawn(&mut commands, entity, texture.clone());
+            commands
+                .entity(shield)
+                .insert(TextureAtlasSprite::new(*sprite))
+                .insert(timer.clone());
+        }
+    }
+    for s in &save.stars {
+        let entity = star::spawn(
+            &mut commands,
+            texture.clone(),
+            s.translation,
+            s.owner,
+            s.level,
+            s.bonus,
+        );
+        commands
+            .entity(entity)
+            .insert(Star::new(
+                s.owner,
+                s.level,
+                s.bonus,
+                s.done,
+                s.state.clone(),
+            ))
+            .insert(TextureAtlasSprite::new(s.sprite))
+            .insert(s.timer.clone());
+    }
+    for b in &save.bullets {
+        bullet::spawn(
+            &mut commands,
+            texture.clone(),
+            b.translation,
+            &b.direction,
+            b.source,
+            b.level,
+        );
+    }
+    for (translation, p_type) in &save.power_ups {
+        power_up::spawn(&mut commands, *translation, *p_type, texture.clone());
+    }
+    for e in &save.explosions {
+        let entity = explosion::spawn(&mut commands, texture.clone(), e.translation, e.is_big);
+        commands
+            .entity(entity)
+            .insert(TextureAtlasSprite::new(e.sprite))
+            .insert(e.timer.clone());
+    }
+    commands.remove_resource::<Resume>();
+}
+
+#[test]
+fn test_save_bytes() {
+    let mut tiles = vec![Tile::Empty; GRID_SIZE * GRID_SIZE];
+    tiles[0] = Tile::Brick(Cells::FULL.hit(Direction::Left));
+    tiles[1] = Tile::Iron;
+    tiles[GRID_SIZE * GRID_SIZE - 1] = Tile::Grass;
+    let mut game_data = GameData::new();
+    game_data.scores[0] = 1200;
+    game_data.base_wall_changed = true;
+    game_data.base_wall_changed_by = Teams::VERSUS.owner(1);
+    game_data
+        .restore_timer
+        .set_elapsed(Duration::from_millis(4560));
+    game_data.blink_timer.tick(Duration::from_millis(700));
+    let mut finished = Timer::from_seconds(0.3, false);
+    finished.tick(Duration::from_secs(1));
+    let save = SaveGame {
+        seed: 7,
+        rng_position: u64::MAX as u128 + 3,
+        tick: 12345,
+        difficulty: Difficulty::Hard,
+        players: 3,
+        teams: Teams::FREE_FOR_ALL,
+        friendly_fire: FriendlyFire::On,
+        game_data,
+        spawner_elapsed: Duration::from_millis(1230),
+        spawner_next: 2,
+        inputs: [TankInput::from_bits(TankInput::UP | TankInput::FIRE); MAX_PLAYERS],
+        ally: Some(vec![1, 2, 3]),
+        path_version: 9,
+        tiles,
+        tanks: vec![TankSave {
+            translation: Vec3::new(-80., -192., 0.),
+            sprite: 22,
+            animation: Timer::from_seconds(0.1, true),
+            data: Data {
+                direction: Direction::Right,
+                level: 2,
+                shield: true,
+                base_sprite: 16,
+                ..Default::default()
+            },
+            shield: Some((281, finished)),
+            controller: Vec::new(),
+        }],
+        stars: vec![StarSave {
+            translation: Vec3::new(-16., 192., 0.),
+            owner: Owner::AI,
+            level: 3,
+            bonus: true,
+            done: true,
+            state: star::State::Shrink,
+            sprite: 274,
+            timer: Timer::from_seconds(0.1, true),
+        }],
+        bullets: vec![BulletSave {
+            translation: Vec3::new(10.5, -3.25, 0.),
+            direction: Direction::Down,
+            source: Owner::AI,
+            level: 1,
+        }],
+        power_ups: vec![(Vec3::new(32., 64., 0.), PowerType::Shovel)],
+        explosions: vec![ExplosionSave {
+            translation: Vec3::new(0., 8., 0.),
+            is_big: true,
+            sprite: 290,
+            timer: Timer::from_seconds(0.1, true),
+        }],
+    };
+    let bytes = save.to_bytes();
+    let loaded = SaveGame::from_bytes(&bytes).unwrap();
+    assert_eq!(loaded.to_bytes(), bytes);
+    assert_eq!(loaded.tiles[0], save.tiles[0]);
+    assert_eq!(loaded.tanks[0].data.direction, Direction::Right);
+    assert_eq!(
+        loaded.game_data.restore_timer.elapsed(),
+        Duration::from_millis(4560)
+    );
+    assert_eq!(loaded.rng_position, save.rng_position);
+    assert_eq!(loaded.teams, Teams::FREE_FOR_ALL);
+    // a timer which finished stays finished
+    assert!(loaded.tanks[0].shield.as_ref().unwrap().1.finished());
+    assert!(SaveGame::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    let mut newer = bytes.clone();
+    newer[4] = SAVE_VERSION + 1;
+    assert!(SaveGame::from_bytes(&newer).is_err());
+}
+
+#[test]
+fn test_resume() {
+    use crate::{input::InputSource, BattleCityPlugins};
+
+    const SAVED: u64 = 400; // tick of the save
+    const AFTER: u64 = 400; // ticks played after it
+
+    let build = |resume: Option<SaveGame>| {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(BattleCityPlugins {
+                headless: true,
+                seed: 5,
+                difficulty: Difficulty::Easy,
+                input: InputSource::External,
+                ally: true,
+                save: Some(std::env::temp_dir().join("battle_city_test_resume.sav")),
+                players: 2,
+                teams: Teams::COOP,
+                friendly_fire: FriendlyFire::Off,
+                versus: None,
+                survival: None,
+            });
+        if let Some(save) = resume {
+            app.insert_resource(Resume(save));
+        }
+        app
+    };
+    // P1 drives around and fires now and then, never toward the base
+    let inputs = |tick: u64| {
+        let directions = [
+            Direction::Up,
+            Direction::Left,
+            Direction::Down,
+            Direction::Up,
+        ];
+        let mut input = TankInput::default();
+        input.press(directions[(tick / 37 % 4) as usize]);
+        input.set(TankInput::FIRE, tick % 23 < 5);
+        [
+            input,
+            TankInput::default(),
+            TankInput::default(),
+            TankInput::default(),
+        ]
+    };
+    let play = |app: &mut App, inputs: [TankInput; MAX_PLAYERS]| {
+        let mut player_inputs = app.world.get_resource_mut::<PlayerInputs>().unwrap();
+        player_inputs.push(inputs);
+        app.update();
+    };
+    // entities come in the order of their archetypes, which the resumed game creates in another
+    // order, e.g. P1 coming out of a star after AI tanks were spawned by the save
+    let fingerprint = |app: &mut App| {
+        let mut save = capture(&mut app.world);
+        save.tanks.sort_by_key(|tank| format!("{:?}", tank));
+        save.stars.sort_by_key(|star| format!("{:?}", star));
+        save.bullets.sort_by_key(|bullet| format!("{:?}", bullet));
+        save.power_ups
+            .sort_by_key(|power_up| format!("{:?}", power_up));
+        save.explosions
+            .sort_by_key(|explosion| format!("{:?}", explosion));
+        save.to_bytes()
+    };
+
+    let mut played = build(None);
+    for tick in 1..=SAVED {
+        play(&mut played, inputs(tick));
+    }
+    let save = SaveGame::from_bytes(&capture(&mut played.world).to_bytes()).unwrap();
+    assert!(save.tanks.iter().any(|tank| !tank.controller.is_empty()));
+    assert!(save.ally.is_some());
+
+    // the first tick of the resumed game goes on with the buttons held when it was saved
+    let mut resumed = build(Some(save));
+    resumed.update();
+    play(&mut played, inputs(SAVED));
+    for tick in SAVED + 2..=SAVED + AFTER {
+        play(&mut played, inputs(tick));
+        play(&mut resumed, inputs(tick));
+    }
+    assert!(
+        !played
+            .world
+            .get_resource::<GameData>()
+            .unwrap()
+            .base_destroyed
+    );
+    assert_eq!(
+        resumed.world.get_resource::<Clock>().unwrap().tick,
+        SAVED + AFTER
+    );
+    assert_eq!(fingerprint(&mut resumed), fingerprint(&mut played));
+}
@@ -0,0 +1,595 @@
+//! Dedicated server for LAN games, and streams of games to spectators.
+//!
+//! Only the server simulates the game, headless, so no player's machine hosts it. Up to
+//! `MAX_CLIENTS` clients connect over TCP, the first ones play P1 to P4, as many as the game has
+//! players, and the others watch.
+//! Clients send the buttons they hold, and after every tick the server sends them a snapshot of
+//! the world, see `snapshot`, as a delta against the last snapshot they acknowledged. A game
+//! played on a machine is streamed the same way, to spectators only, by `StreamPlugin`.
+//!
+//! Every message is its length, u32 little endian, then its kind and its body:
+//!
+//! - `JOIN`, client to server: the magic
+//! - `INPUT`, client to server: the buttons held, bits of `TankInput`
+//! - `ACK`, client to server: the tick of the last snapshot received, u64
+//! - `WELCOME`, server to client: the player, `SPECTATOR` for a client which only watches
+//! - `DELTA`, server to client: a snapshot, see `snapshot::Delta::encode`
+//!
+//! Messages are validated, a client sending anything but the buttons of a player or the ticks of
+//! snapshots it was sent is disconnected after `MAX_VIOLATIONS` messages.
+
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use bevy::prelude::*;
+
+use crate::{
+    config::Difficulty,
+    game_data::GameData,
+    input::{InputSource, PlayerInputs, TankInput},
+    simulation::Clock,
+    snapshot::{Delta, Snapshot},
+    survival,
+    team::{FriendlyFire, Teams},
+    utils::MAX_PLAYERS,
+    versus::Rules,
+    BattleCityPlugins,
+};
+
+pub const DEFAULT_PORT: u16 = 7777;
+pub const MAX_CLIENTS: usize = 8;
+/// The player of a client which only watches
+pub const SPECTATOR: u8 = u8::MAX;
+const SERVER_MAGIC: &[u8; 4] = b"BCSR";
+const JOIN: u8 = 0;
+const INPUT: u8 = 1;
+const WELCOME: u8 = 2;
+const DELTA: u8 = 3;
+const ACK: u8 = 4;
+const MAX_MESSAGE: usize = 1 << 20;
+const MAX_VIOLATIONS: u32 = 3;
+/// Snapshots kept to send deltas against, a client acknowledging older ones gets whole snapshots
+const HISTORY: usize = 64;
+
+pub struct ServerConfig {
+    pub seed: u64,
+    pub difficulty: Difficulty,
+    pub stage: u8,
+    pub players: usize, // players who must join before the game starts
+    pub teams: Teams,
+    pub friendly_fire: FriendlyFire,
+    pub versus: Option<Rules>, // P1's team against P2's team instead of co-op
+    pub survival: Option<survival::Rules>, // waves of AI tanks without end instead of a stage
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            difficulty: Difficulty::default(),
+            stage: 1,
+            players: 1,
+            teams: Teams::default(),
+            friendly_fire: FriendlyFire::default(),
+            versus: None,
+            survival: None,
+        }
+    }
+}
+
+/// A TCP stream of messages which never blocks
+struct Framed {
+    stream: TcpStream,
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>, // what the stream could not take yet
+}
+
+impl Framed {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            incoming: Vec::new(),
+            outgoing: Vec::new(),
+        })
+    }
+
+    fn send(&mut self, kind: u8, body: &[u8]) -> io::Result<()> {
+        self.outgoing
+            .extend_from_slice(&(body.len() as u32 + 1).to_le_bytes());
+        self.outgoing.push(kind);
+        self.outgoing.extend_from_slice(body);
+        self.flush()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(written) => {
+                    self.outgoing.drain(..written);
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Every whole message which arrived, kind first, an error once the stream is closed
+    fn receive(&mut self) -> io::Result<Vec<Vec<u8>>> {
+        let mut buf = [0; 4096];
+        let mut closed = false;
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => {
+                    closed = true;
+                    break;
+                }
+                Ok(len) => self.incoming.extend_from_slice(&buf[..len]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+        let mut messages = Vec::new();
+        while self.incoming.len() >= 4 {
+            let len = u32::from_le_bytes(self.incoming[..4].try_into().unwrap()) as usize;
+            if len == 0 || len > MAX_MESSAGE {
+                return Err(invalid("invalid message length"));
+            }
+            if self.incoming.len() < 4 + len {
+                break;
+            }
+            messages.push(self.incoming[4..4 + len].to_vec());
+            self.incoming.drain(..4 + len);
+        }
+        // messages sent right before closing are still read
+        if closed && messages.is_empty() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(messages)
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// A client as the server sees it
+struct Connection {
+    framed: Framed,
+    joined: bool,
+    player: Option<usize>, // index in PlayerInputs, None for a spectator
+    input: TankInput,      // held until the client sends another one
+    acked: Option<u64>,    // tick of the last snapshot received
+    violations: u32,
+}
+
+impl Connection {
+    fn violate(&mut self) -> bool {
+        self.violations += 1;
+        self.violations < MAX_VIOLATIONS
+    }
+}
+
+/// Clients connected over TCP, and the snapshots sent to them
+pub struct Clients {
+    listener: TcpListener,
+    connections: Vec<Connection>,
+    players: usize,              // clients who can play, the others watch
+    history: VecDeque<Snapshot>, // the last snapshots sent, by tick
+}
+
+impl Clients {
+    pub fn bind(addr: impl ToSocketAddrs, players: usize) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            connections: Vec::new(),
+            players: players.min(MAX_PLAYERS),
+            history: VecDeque::new(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// The number of clients connected, joined or not
+    pub fn connected(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// The number of clients playing
+    pub fn players(&self) -> usize {
+        self.connections
+            .iter()
+            .filter(|connection| connection.player.is_some())
+            .count()
+    }
+
+    /// Buttons held by the clients playing, by player ID
+    pub fn inputs(&self) -> [TankInput; MAX_PLAYERS] {
+        let mut inputs = [TankInput::default(); MAX_PLAYERS];
+        for connection in &self.connections {
+            if let Some(player) = connection.player {
+                inputs[player] = connection.input;
+            }
+        }
+        inputs
+    }
+
+    /// The last snapshot sent
+    pub fn last(&self) -> Option<&Snapshot> {
+        self.history.back()
+    }
+
+    /// Accepts clients connecting, takes their inputs and acknowledgements, and welcomes clients
+    /// joining
+    pub fn poll(&mut self) {
+        self.accept();
+        let mut i = 0;
+        while i < self.connections.len() {
+            let keep = match self.connections[i].framed.receive() {
+                Ok(messages) => messages.iter().all(|message| self.handle(i, message)),
+                Err(_) => false,
+            };
+            if keep {
+                i += 1;
+            } else {
+                self.connections.remove(i);
+            }
+        }
+    }
+
+    /// Sends a snapshot to every client joined, a snapshot not newer than the last one is not
+    /// sent
+    pub fn broadcast(&mut self, snapshot: Snapshot) {
+        if self.last().is_some_and(|last| last.tick >= snapshot.tick) {
+            return;
+        }
+        self.history.push_back(snapshot);
+        if self.history.len() > HISTORY {
+            self.history.pop_front();
+        }
+        let history = &self.history;
+        let snapshot = history.back().unwrap();
+        self.connections.retain_mut(|connection| {
+            if !connection.joined {
+                return true;
+            }
+            let base = connection
+                .acked
+                .and_then(|tick| history.iter().find(|snapshot| snapshot.tick == tick));
+            let delta = Delta::between(base, snapshot).encode();
+            connection.framed.send(DELTA, &delta).is_ok()
+        });
+    }
+
+    fn accept(&mut self) {
+        loop {
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(_) => break,
+            };
+            // a client connecting to a full server is closed right away
+            if self.connections.len() >= MAX_CLIENTS {
+                continue;
+            }
+            if let Ok(framed) = Framed::new(stream) {
+                self.connections.push(Connection {
+                    framed,
+                    joined: false,
+                    player: None,
+                    input: TankInput::default(),
+                    acked: None,
+                    violations: 0,
+                });
+            }
+        }
+    }
+
+    /// Returns false if the client is disconnected
+    fn handle(&mut self, i: usize, message: &[u8]) -> bool {
+        let free = (0..self.players).find(|player| {
+            self.connections
+                .iter()
+                .all(|connection| connection.player != Some(*player))
+        });
+        let last = self.history.back();
+        let connection = &mut self.connections[i];
+        match (message[0], &message[1..]) {
+            (JOIN, magic) if !connection.joined && magic == SERVER_MAGIC => {
+                connection.joined = true;
+                connection.player = free;
+                let welcome = [free.map_or(SPECTATOR, |player| player as u8)];
+                // the world as it is, in case no tick is simulated for a while
+                connection.framed.send(WELCOME, &welcome).is_ok()
+                    && last.is_none_or(|last| {
+                        let full = Delta::between(None, last).encode();
+                        connection.framed.send(DELTA, &full).is_ok()
+                    })
+            }
+            // a client must join first
+            _ if !connection.joined => false,
+            // a spectator's buttons do nothing
+            (INPUT, [bits]) => match validate(*bits) {
+                Some(input) => {
+                    connection.input = input;
+                    true
+                }
+                None => connection.violate(),
+            },
+            (ACK, tick) => match tick.try_into().map(u64::from_le_bytes) {
+                Ok(tick) if last.is_some_and(|last| tick <= last.tick) => {
+                    connection.acked = connection.acked.max(Some(tick));
+                    true
+                }
+                _ => connection.violate(),
+            },
+            _ => connection.violate(),
+        }
+    }
+}
+
+/// Buttons a client holds, None if the bits are not buttons of a player
+fn validate(bits: u8) -> Option<TankInput> {
+    let input = TankInput::from_bits(bits);
+    (input.bits() == bits).then_some(input)
+}
+
+pub struct Server {
+    clients: Clients,
+    app: App,
+    players: usize,
+    started: bool,
+}
+
+impl Server {
+    pub fn bind(addr: impl ToSocketAddrs, config: ServerConfig) -> io::Result<Self> {
+        let players = config.players.clamp(1, MAX_PLAYERS);
+        let clients = Clients::bind(addr, players)?;
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(BattleCityPlugins {
+                headless: true,
+                seed: config.seed,
+                difficulty: config.difficulty,
+                input: InputSource::External,
+                ally: false,
+                save: None,
+                players,
+                teams: config.teams,
+                friendly_fire: config.friendly_fire,
+                versus: config.versus,
+                survival: config.survival,
+            });
+        app.world.get_resource_mut::<GameData>().unwrap().stage = config.stage;
+        Ok(Self {
+            clients,
+            app,
+            players,
+            started: false,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.clients.local_addr()
+    }
+
+    /// The game itself, e.g. to inspect entities or change resources
+    pub fn app(&mut self) -> &mut App {
+        &mut self.app
+    }
+
+    pub fn clients(&self) -> &Clients {
+        &self.clients
+    }
+
+    /// What clients were sent last
+    pub fn snapshot(&self) -> Option<&Snapshot> {
+        self.clients.last()
+    }
+
+    /// Talks to clients, then simulates a tick once enough players have joined, returns false
+    /// once the stage is over
+    pub fn step(&mut self) -> bool {
+        self.clients.poll();
+        self.started |= self.clients.players() >= self.players;
+        if self.started {
+            let inputs = self.clients.inputs();
+            let mut player_inputs = self.app.world.get_resource_mut::<PlayerInputs>().unwrap();
+            player_inputs.push(inputs);
+            self.app.update();
+            self.clients
+                .broadcast(Snapshot::capture(&mut self.app.world));
+        }
+        !self.snapshot().is_some_and(|last| last.status.over)
+    }
+}
+
+/// Streams the game played on this machine to spectators connecting to `Clients`, which must be
+/// inserted as a resource
+pub struct StreamPlugin;
+
+impl Plugin for StreamPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_to_stage(CoreStage::PostUpdate, stream.exclusive_system());
+    }
+}
+
+/// Sends spectators a snapshot after every frame a tick is simulated
+pub fn stream(world: &mut World) {
+    world.resource_scope(|world, mut clients: Mut<Clients>| {
+        clients.poll();
+        let tick = world.get_resource::<Clock>().unwrap().tick;
+        if clients.last().is_none_or(|last| last.tick < tick) {
+            clients.broadcast(Snapshot::capture(world));
+        }
+    });
+}
+
+/// A player or a spectator connected to a server
+pub struct Client {
+    framed: Framed,
+    welcomed: bool,
+    player: Option<usize>,
+    received: VecDeque<Snapshot>, // from the base of the last delta, by tick
+}
+
+impl Client {
+    /// Connects and asks to join, the server welcomes the client during a later `poll`
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let mut framed = Framed::new(TcpStream::connect(addr)?)?;
+        framed.send(JOIN, SERVER_MAGIC)?;
+        Ok(Self {
+            framed,
+            welcomed: false,
+            player: None,
+            received: VecDeque::new(),
+        })
+    }
+
+    pub fn welcomed(&self) -> bool {
+        self.welcomed
+    }
+
+    /// The player the client controls, index in `PlayerInputs`, None for a spectator
+    pub fn player(&self) -> Option<usize> {
+        self.player
+    }
+
+    /// The last snapshot received
+    pub fn snapshot(&self) -> Option<&Snapshot> {
+        self.received.back()
+    }
+
+    pub fn send_input(&mut self, input: TankInput) -> io::Result<()> {
+        self.framed.send(INPUT, &[input.bits()])
+    }
+
+    /// Sends what could not be sent yet, applies every message which arrived, and acknowledges
+    /// the snapshots
+    pub fn poll(&mut self) -> io::Result<()> {
+        self.framed.flush()?;
+        for message in self.framed.receive()? {
+            match (message[0], &message[1..]) {
+                (WELCOME, [player]) => {
+                    self.welcomed = true;
+                    self.player = (*player != SPECTATOR).then_some(*player as usize);
+                }
+                (DELTA, body) => {
+                    let delta = Delta::decode(body).ok_or_else(|| invalid("invalid delta"))?;
+                    let base = match delta.base {
+                        Some(tick) => Some(
+                            self.received
+                                .iter()
+                                .find(|snapshot| snapshot.tick == tick)
+                                .ok_or_else(|| invalid("delta against an unknown snapshot"))?,
+                        ),
+                        None => None,
+                    };
+                    let snapshot = delta.apply(base);
+                    // the server only sends deltas against newer snapshots from now on
+                    if let Some(tick) = delta.base {
+                        self.received.retain(|snapshot| snapshot.tick >= tick);
+                    }
+                    if self.received.len() >= HISTORY {
+                        self.received.pop_front();
+                    }
+                    self.framed.send(ACK, &snapshot.tick.to_le_bytes())?;
+                    self.received.push_back(snapshot);
+                }
+                _ => return Err(invalid("unknown message")),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_loopback() {
+    use crate::utils::Direction;
+
+    let config = ServerConfig {
+        seed: 45,
+        difficulty: Difficulty::Hard,
+        stage: 1,
+        players: 2,
+        teams: Teams::COOP,
+        friendly_fire: FriendlyFire::Off,
+        versus: None,
+        survival: None,
+    };
+    let mut server = Server::bind("127.0.0.1:0", config).unwrap();
+    let addr = server.local_addr().unwrap();
+    let mut clients = [
+        Client::connect(addr).unwrap(),
+        Client::connect(addr).unwrap(),
+    ];
+    while !clients.iter().all(Client::welcomed) {
+        assert!(server.step());
+        for client in &mut clients {
+            client.poll().unwrap();
+        }
+    }
+    let mut players: Vec<_> = clients.iter().map(Client::player).collect();
+    players.sort();
+    assert_eq!(players, [Some(0), Some(1)]);
+
+    // a spectator joining late gets the whole battle field
+    let mut spectator = Client::connect(addr).unwrap();
+    // anything but buttons gets a client disconnected
+    let mut cheater = Client::connect(addr).unwrap();
+    let directions = [
+        Direction::Up,
+        Direction::Right,
+        Direction::Down,
+        Direction::Left,
+    ];
+    let mut ticks = 0;
+    while server.step() {
+        ticks += 1;
+        assert!(ticks < 100_000, "the stage never ends");
+        for (i, client) in clients.iter_mut().enumerate() {
+            client.poll().unwrap();
+            let mut input = TankInput::default();
+            input.press(directions[(ticks / 50 + i) % 4]);
+            input.set(TankInput::FIRE, ticks % 20 < 10);
+            client.send_input(input).unwrap();
+        }
+        spectator.poll().unwrap();
+        if ticks <= MAX_VIOLATIONS as usize {
+            let _ = cheater.poll();
+            let _ = cheater.framed.send(INPUT, &[u8::MAX]);
+        }
+        if ticks == 100 {
+            assert_eq!(spectator.player(), None);
+            assert_eq!(server.clients().connected(), 3);
+        }
+    }
+    assert!(server.snapshot().unwrap().status.over);
+
+    // every client ends up seeing what the server saw
+    for _ in 0..100 {
+        for client in clients.iter_mut().chain([&mut spectator]) {
+            client.poll().unwrap();
+        }
+        if clients
+            .iter()
+            .chain([&spectator])
+            .all(|client| client.snapshot() == server.snapshot())
+        {
+            return;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    panic!("clients did not get the last snapshot");
+}
@@ -0,0 +1,55 @@
+use bevy::prelude::*;
+
+use crate::{
+    config::{Config, Difficulty},
+    game_data::GameData,
+    team::{FriendlyFire, Teams},
+    texture::{load_texture_atlas, Textures},
+};
+
+pub struct SetupPlugin {
+    pub headless: bool,
+    pub difficulty: Difficulty,
+    pub players: usize,
+    pub teams: Teams,
+    pub friendly_fire: FriendlyFire,
+}
+
+impl Plugin for SetupPlugin {
+    fn build(&self, app: &mut App) {
+        // inserted before startup, so it can be changed before the game starts, e.g. the stage
+        app.insert_resource(GameData::new())
+            .insert_resource(Config {
+                difficulty: self.difficulty,
+                players: self.players,
+                teams: self.teams,
+            })
+            .insert_resource(self.friendly_fire);
+        if self.headless {
+            app.add_startup_system(setup_headless);
+        } else {
+            app.add_startup_system(setup);
+        }
+    }
+}
+
+pub fn setup(
+    mut commands: Commands,
+    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    asset_server: Res<AssetServer>,
+) {
+    // Add the game's entities to our world
+    let texture_handle = texture_atlases.add(load_texture_atlas(asset_server));
+    // cameras
+    commands.spawn_bundle(OrthographicCameraBundle::new_2d());
+    commands.insert_resource(Textures {
+        texture: texture_handle,
+    });
+}
+
+/// Same as `setup`, but without camera and sprite sheet
+pub fn setup_headless(mut commands: Commands) {
+    commands.insert_resource(Textures {
+        texture: Handle::default(),
+    });
+}
@@ -1,10 +1,10 @@
-use crate::texture::SpriteIndex;
+use crate::{simulation::TICK, texture::SpriteIndex};
 use bevy::prelude::*;
 
-#[derive(Component)]
+#[derive(Component, Clone)]
 pub struct Shield;
 
-pub fn spawn(commands: &mut Commands, tank: Entity, texture: Handle<TextureAtlas>) {
+pub fn spawn(commands: &mut Commands, tank: Entity, texture: Handle<TextureAtlas>) -> Entity {
     let shield = commands
         .spawn_bundle(SpriteSheetBundle {
             sprite: TextureAtlasSprite::new(SpriteIndex::SHIELD[0]),
@@ -15,14 +15,12 @@ pub fn spawn(commands: &mut Commands, tank: Entity, texture: Handle<TextureAtlas
         .insert(Timer::from_seconds(0.05, true))
         .id();
     commands.entity(tank).push_children(&[shield]);
+    shield
 }
 
-pub fn animation(
-    time: Res<Time>,
-    mut query: Query<(&mut TextureAtlasSprite, &mut Timer), With<Shield>>,
-) {
+pub fn animation(mut query: Query<(&mut TextureAtlasSprite, &mut Timer), With<Shield>>) {
     for (mut sprite, mut timer) in query.iter_mut() {
-        if timer.tick(time.delta()).just_finished() {
+        if timer.tick(TICK).just_finished() {
             if sprite.index % 2 == 0 {
                 sprite.index += 1;
             } else {
@@ -0,0 +1,219 @@
+use std::time::Duration;
+
+use bevy::{ecs::schedule::ShouldRun, prelude::*};
+use rand::{Error, RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/// Length of a simulation tick. Tanks and bullets move once per tick.
+pub const TICK: Duration = Duration::from_millis(10);
+// avoid freezing the game to catch up after a long frame, e.g. when the window is dragged
+const MAX_TICKS_PER_FRAME: u32 = 10;
+
+/// Stage holding every system which changes the game's state.
+///
+/// It runs after `CoreStage::Update`, zero or more times per frame, once per tick.
+/// Systems in it run single threaded in the order they are added with `add_simulation_system`,
+/// so the result only depends on the seed and players' inputs.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, StageLabel)]
+pub struct SimulationStage;
+
+pub struct SimulationPlugin {
+    pub seed: u64,
+    pub realtime: bool, // follow the wall clock, otherwise run exactly one tick per frame
+}
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Clock::new(self.realtime))
+            .insert_resource(GameRng::new(self.seed))
+            .add_stage_after(
+                CoreStage::Update,
+                SimulationStage,
+                SystemStage::single_threaded().with_run_criteria(run_tick),
+            );
+    }
+}
+
+/// Adds systems to `SimulationStage` one after another.
+///
+/// Bevy sorts the systems of a stage by their dependencies only, in an order which differs from
+/// one `App` to another, so systems added with `add_system_to_stage` would not always run in the
+/// same order.
+pub trait AddSimulationSystem {
+    /// Adds a system running after every system added to `SimulationStage` with this before
+    fn add_simulation_system<Params>(
+        &mut self,
+        system: impl ParallelSystemDescriptorCoercion<Params>,
+    ) -> &mut Self;
+}
+
+impl AddSimulationSystem for App {
+    fn add_simulation_system<Params>(
+        &mut self,
+        system: impl ParallelSystemDescriptorCoercion<Params>,
+    ) -> &mut Self {
+        let mut systems = self
+            .world
+            .get_resource_or_insert_with(SimulationSystems::default);
+        let index = systems.0;
+        systems.0 += 1;
+        let system = system.label(SimulationSystem(index));
+        match index {
+            0 => self.add_system_to_stage(SimulationStage, system),
+            _ => {
+                self.add_system_to_stage(SimulationStage, system.after(SimulationSystem(index - 1)))
+            }
+        }
+    }
+}
+
+/// A system of `SimulationStage` by the order it was added in
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
+struct SimulationSystem(usize);
+
+/// Number of systems added with `add_simulation_system`
+#[derive(Default)]
+struct SimulationSystems(usize);
+
+pub struct Clock {
+    pub tick: u64,          // number of ticks simulated, including the running one
+    pub limit: Option<u64>, // the simulation stops after this tick
+    pub paused: bool,
+    pub speed: u32, // ticks simulated during one tick of real time
+    realtime: bool,
+    resimulate: u64, // ticks rewound, simulated again right away
+    accumulated: Duration,
+    looping: bool, // if the run criteria is called again in the same frame
+    step: bool,
+}
+
+impl Clock {
+    pub fn new(realtime: bool) -> Self {
+        Self {
+            tick: 0,
+            limit: None,
+            paused: false,
+            speed: 1,
+            realtime,
+            resimulate: 0,
+            accumulated: Duration::ZERO,
+            looping: false,
+            step: false,
+        }
+    }
+
+    /// Simulates a single tick while paused
+    pub fn step(&mut self) {
+        self.step = true;
+    }
+
+    /// Goes back to an earlier tick, the ticks after it are simulated again in the next frame
+    /// before any tick of real time
+    pub fn rewind(&mut self, tick: u64) {
+        self.resimulate += self.tick - tick;
+        self.tick = tick;
+    }
+}
+
+pub fn run_tick(time: Res<Time>, mut clock: ResMut<Clock>) -> ShouldRun {
+    if clock.limit.map_or(false, |limit| clock.tick >= limit) {
+        clock.looping = false;
+        return ShouldRun::No;
+    }
+    if clock.resimulate > 0 {
+        clock.resimulate -= 1;
+        clock.tick += 1;
+        return ShouldRun::YesAndCheckAgain;
+    }
+    if clock.paused {
+        clock.looping = false;
+        if !clock.step {
+            return ShouldRun::No;
+        }
+        clock.step = false;
+        clock.tick += 1;
+        return ShouldRun::Yes;
+    }
+    if !clock.realtime {
+        clock.tick += 1;
+        return ShouldRun::Yes;
+    }
+
+    if !clock.looping {
+        let max = TICK * MAX_TICKS_PER_FRAME * clock.speed;
+        clock.accumulated = (clock.accumulated + time.delta() * clock.speed).min(max);
+    }
+    if clock.accumulated >= TICK {
+        clock.accumulated -= TICK;
+        clock.tick += 1;
+        clock.looping = true;
+        ShouldRun::YesAndCheckAgain
+    } else {
+        clock.looping = false;
+        ShouldRun::No
+    }
+}
+
+/// The only source of randomness of the game.
+///
+/// ChaCha8 gives the same numbers for a seed on every platform and with every version of rand,
+/// unlike `StdRng`, so replays and peers built differently stay in sync.
+#[derive(Clone)]
+pub struct GameRng {
+    seed: u64,
+    rng: ChaCha8Rng,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+
+    /// Goes on from where another one with the same seed was, e.g. when a saved game resumes
+    pub fn resumed(seed: u64, position: u128) -> Self {
+        let mut rng = Self::new(seed);
+        rng.rng.set_word_pos(position);
+        rng
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// How far into the seed's numbers it is, in 32 bit words
+    pub fn position(&self) -> u128 {
+        self.rng.get_word_pos()
+    }
+}
+
+impl RngCore for GameRng {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+#[test]
+fn test_game_rng() {
+    // the same numbers everywhere, a change here breaks every replay and save
+    let mut rng = GameRng::new(42);
+    assert_eq!(rng.next_u64(), 12578764544318200737);
+    assert_eq!(rng.next_u64(), 17529487244874322312);
+    assert_eq!(rng.seed(), 42);
+    let mut resumed = GameRng::resumed(42, rng.position());
+    assert_eq!(resumed.next_u64(), rng.next_u64());
+}
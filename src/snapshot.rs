@@ -0,0 +1,513 @@
+//! Snapshots of the world as clients draw it, and their binary protocol.
+//!
+//! A snapshot holds the status of the game, every tile of terrain, and every object which is not
+//! terrain: tanks, bullets, power ups, the base, and effects like stars, explosions and shields.
+//! Objects are identified by the entity drawing them on the simulating side, so an entity
+//! respawned is a new object.
+//!
+//! Snapshots are sent as deltas against a snapshot the client acknowledged, or against an empty
+//! snapshot before it acknowledged any. A delta is encoded as, all numbers little endian:
+//!
+//! - tick u64
+//! - base u64, the tick of the snapshot it is against, `NO_BASE` for an empty snapshot
+//! - players u8, then lifes u8 x 4 and scores u32 x 4, P1's first
+//! - AI tanks left u8, stage u8, then flags u8: 1 if the base is destroyed, 2 if the stage is
+//!   over
+//! - mode u8, 0 for a stage, 1 for versus followed by rounds won u8 x 2 and kills in the round
+//!   u8 x 2, P1's team first, or 2 for survival followed by the wave u32 and the best score in
+//!   the high-score table u32
+//! - the number of tiles changed u16, then each of them as its index u16, `y * GRID_SIZE + x`,
+//!   and the tile u8, see `encode_tile`
+//! - the number of objects new or changed u16, then each of them as its id u64, its kind u8, see
+//!   `ObjectKind`, its translation f32 x 3, z orders sprites, its index in the texture atlas
+//!   u16, and the player it belongs to u8, `NO_PLAYER` for none
+//! - the number of objects gone u16, then their ids u64
+
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+
+use crate::{
+    brick::Cells,
+    collision::Collider,
+    config::Config,
+    game_data::{self, GameData},
+    rollback::Simulated,
+    simulation::Clock,
+    survival::{HighScoreTable, Waves},
+    terrain::{Tile, TileMap},
+    utils::{Player, GRID_SIZE, MAX_PLAYERS},
+    versus::Match,
+};
+
+/// Base tick of a delta against an empty snapshot
+pub const NO_BASE: u64 = u64::MAX;
+/// Player of an object which belongs to none, e.g. an AI tank
+pub const NO_PLAYER: u8 = u8::MAX;
+
+/// What is not on battle field itself
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Status {
+    pub players: u8, // tanks played
+    pub lifes: [u8; MAX_PLAYERS],
+    pub scores: [u32; MAX_PLAYERS],
+    pub ai_tanks: u8, // AI tanks left to come onto battle field
+    pub stage: u8,
+    pub base_destroyed: bool,
+    pub over: bool, // the stage is over
+    pub mode: Mode,
+}
+
+/// What is played, and its score besides players' ones
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    #[default]
+    Stage,
+    Versus {
+        wins: [u8; 2],  // rounds won by P1's team and P2's team
+        kills: [u8; 2], // tanks of the other team destroyed in this round
+    },
+    Survival {
+        wave: u32,
+        best: u32, // waves survived by the best game in the high-score table
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectKind {
+    Tank,
+    Bullet,
+    PowerUp,
+    Base,
+    Effect, // a star, an explosion or a shield
+}
+
+impl ObjectKind {
+    const ALL: [ObjectKind; 5] = [
+        ObjectKind::Tank,
+        ObjectKind::Bullet,
+        ObjectKind::PowerUp,
+        ObjectKind::Base,
+        ObjectKind::Effect,
+    ];
+}
+
+/// A sprite on battle field which is not terrain
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Object {
+    pub kind: ObjectKind,
+    pub translation: Vec3,
+    pub sprite: u16,        // index in the texture atlas
+    pub player: Option<u8>, // players' tanks and their bullets are drawn in their colours
+}
+
+/// Everything clients draw at a tick
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub tick: u64,
+    pub status: Status,
+    pub tiles: Vec<Tile>, // row by row from the top left one
+    pub objects: BTreeMap<u64, Object>,
+}
+
+impl Default for Snapshot {
+    fn default() -> Self {
+        Self {
+            tick: 0,
+            status: Status::default(),
+            tiles: vec![Tile::Empty; GRID_SIZE * GRID_SIZE],
+            objects: BTreeMap::new(),
+        }
+    }
+}
+
+impl Snapshot {
+    pub fn capture(world: &mut World) -> Self {
+        let over = game_data::is_over(world);
+        let mut objects = world.query_filtered::<(
+            Entity,
+            &Transform,
+            &TextureAtlasSprite,
+            &Visibility,
+            Option<&Collider>,
+            Option<&Parent>,
+            Option<&Player>,
+        ), Simulated>();
+        let mut parents = world.query::<&Transform>();
+        let world = &*world;
+        let mut snapshot = Snapshot {
+            tick: world.get_resource::<Clock>().unwrap().tick,
+            tiles: world
+                .get_resource::<TileMap>()
+                .unwrap()
+                .iter()
+                .map(|(_, _, tile)| tile)
+                .collect(),
+            ..Default::default()
+        };
+        for (entity, transform, sprite, visibility, collider, parent, player) in objects.iter(world)
+        {
+            let kind = match collider {
+                Some(Collider::Tank) => ObjectKind::Tank,
+                Some(Collider::Bullet) => ObjectKind::Bullet,
+                Some(Collider::PowerUp) => ObjectKind::PowerUp,
+                Some(Collider::Base) => ObjectKind::Base,
+                None => ObjectKind::Effect,
+                Some(_) => continue, // terrain is sent as tiles
+            };
+            if !visibility.is_visible {
+                continue;
+            }
+            // transforms are not propagated when headless, e.g. a shield follows its tank
+            let translation = match parent.and_then(|parent| parents.get(world, parent.0).ok()) {
+                Some(parent) => parent.mul_vec3(transform.translation),
+                None => transform.translation,
+            };
+            snapshot.objects.insert(
+                entity.to_bits(),
+                Object {
+                    kind,
+                    translation,
+                    sprite: sprite.index as u16,
+                    player: player.map(|player| player.0),
+                },
+            );
+        }
+        let game_data = world.get_resource::<GameData>().unwrap();
+        let mode = match (world.get_resource::<Match>(), world.get_resource::<Waves>()) {
+            (Some(versus), _) => Mode::Versus {
+                wins: versus.wins,
+                kills: versus.kills,
+            },
+            (None, Some(waves)) => Mode::Survival {
+                wave: waves.wave,
+                best: world
+                    .get_resource::<HighScoreTable>()
+                    .unwrap()
+                    .scores
+                    .0
+                    .first()
+                    .map_or(0, |entry| entry.waves),
+            },
+            (None, None) => Mode::Stage,
+        };
+        snapshot.status = Status {
+            players: world.get_resource::<Config>().unwrap().players as u8,
+            lifes: game_data.lifes,
+            scores: game_data.scores,
+            ai_tanks: game_data.ai_tanks,
+            stage: game_data.stage,
+            base_destroyed: game_data.base_destroyed,
+            over,
+            mode,
+        };
+        snapshot
+    }
+}
+
+/// What changed from one snapshot to another
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delta {
+    pub tick: u64,
+    pub base: Option<u64>, // None against an empty snapshot
+    pub status: Status,
+    pub tiles: Vec<(u16, Tile)>,
+    pub changed: Vec<(u64, Object)>, // new objects too
+    pub removed: Vec<u64>,
+}
+
+impl Delta {
+    /// From a snapshot, or from an empty one, to another
+    pub fn between(base: Option<&Snapshot>, snapshot: &Snapshot) -> Self {
+        let empty = Snapshot::default();
+        let old = base.unwrap_or(&empty);
+        let tiles = old
+            .tiles
+            .iter()
+            .zip(&snapshot.tiles)
+            .enumerate()
+            .filter(|(_, (old, new))| old != new)
+            .map(|(i, (_, new))| (i as u16, *new))
+            .collect();
+        let changed = snapshot
+            .objects
+            .iter()
+            .filter(|(id, object)| old.objects.get(id) != Some(object))
+            .map(|(id, object)| (*id, *object))
+            .collect();
+        let removed = old
+            .objects
+            .keys()
+            .filter(|id| !snapshot.objects.contains_key(id))
+            .copied()
+            .collect();
+        Self {
+            tick: snapshot.tick,
+            base: base.map(|base| base.tick),
+            status: snapshot.status,
+            tiles,
+            changed,
+            removed,
+        }
+    }
+
+    /// The snapshot this delta leads to from its base, which must be the snapshot of `self.base`
+    pub fn apply(&self, base: Option<&Snapshot>) -> Snapshot {
+        let mut snapshot = base.cloned().unwrap_or_default();
+        snapshot.tick = self.tick;
+        snapshot.status = self.status;
+        for (i, tile) in &self.tiles {
+            snapshot.tiles[*i as usize] = *tile;
+        }
+        for id in &self.removed {
+            snapshot.objects.remove(id);
+        }
+        snapshot.objects.extend(self.changed.iter().copied());
+        snapshot
+    }
+
+    /// See the module documentation for the format
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(
+            47 + self.tiles.len() * 3 + self.changed.len() * 24 + self.removed.len() * 8,
+        );
+        bytes.extend_from_slice(&self.tick.to_le_bytes());
+        bytes.extend_from_slice(&self.base.unwrap_or(NO_BASE).to_le_bytes());
+        bytes.push(self.status.players);
+        bytes.extend_from_slice(&self.status.lifes);
+        for score in self.status.scores {
+            bytes.extend_from_slice(&score.to_le_bytes());
+        }
+        bytes.push(self.status.ai_tanks);
+        bytes.push(self.status.stage);
+        bytes.push(self.status.base_destroyed as u8 | (self.status.over as u8) << 1);
+        match self.status.mode {
+            Mode::Stage => bytes.push(0),
+            Mode::Versus { wins, kills } => {
+                bytes.push(1);
+                bytes.extend_from_slice(&wins);
+                bytes.extend_from_slice(&kills);
+            }
+            Mode::Survival { wave, best } => {
+                bytes.push(2);
+                bytes.extend_from_slice(&wave.to_le_bytes());
+                bytes.extend_from_slice(&best.to_le_bytes());
+            }
+        }
+        bytes.extend_from_slice(&(self.tiles.len() as u16).to_le_bytes());
+        for (i, tile) in &self.tiles {
+            bytes.extend_from_slice(&i.to_le_bytes());
+            bytes.push(encode_tile(*tile));
+        }
+        bytes.extend_from_slice(&(self.changed.len() as u16).to_le_bytes());
+        for (id, object) in &self.changed {
+            bytes.extend_from_slice(&id.to_le_bytes());
+            bytes.push(object.kind as u8);
+            for v in object.translation.to_array() {
+                bytes.extend_from_slice(&v.to_le_bytes());
+            }
+            bytes.extend_from_slice(&object.sprite.to_le_bytes());
+            bytes.push(object.player.unwrap_or(NO_PLAYER));
+        }
+        bytes.extend_from_slice(&(self.removed.len() as u16).to_le_bytes());
+        for id in &self.removed {
+            bytes.extend_from_slice(&id.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// None if the bytes are not a delta
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut reader = Reader { bytes };
+        let tick = reader.u64()?;
+        let base = Some(reader.u64()?).filter(|base| *base != NO_BASE);
+        let players = reader.u8()?;
+        let lifes = reader.take()?;
+        let mut scores = [0; MAX_PLAYERS];
+        for score in &mut scores {
+            *score = reader.u32()?;
+        }
+        let [ai_tanks, stage, flags] = reader.take()?;
+        let mode = match reader.u8()? {
+            0 => Mode::Stage,
+            1 => Mode::Versus {
+                wins: reader.take()?,
+                kills: reader.take()?,
+            },
+            2 => Mode::Survival {
+                wave: reader.u32()?,
+                best: reader.u32()?,
+            },
+            _ => return None,
+        };
+        let status = Status {
+            players,
+            lifes,
+            scores,
+            ai_tanks,
+            stage,
+            base_destroyed: flags & 1 != 0,
+            over: flags & 2 != 0,
+            mode,
+        };
+        let mut tiles = Vec::new();
+        for _ in 0..reader.u16()? {
+            let i = reader.u16()?;
+            if i as usize >= GRID_SIZE * GRID_SIZE {
+                return None;
+            }
+            tiles.push((i, decode_tile(reader.u8()?)?));
+        }
+        let mut changed = Vec::new();
+        for _ in 0..reader.u16()? {
+            let id = reader.u64()?;
+            let kind = *ObjectKind::ALL.get(reader.u8()? as usize)?;
+            let translation = Vec3::new(reader.f32()?, reader.f32()?, reader.f32()?);
+            let sprite = reader.u16()?;
+            let player = Some(reader.u8()?).filter(|player| *player != NO_PLAYER);
+            changed.push((
+                id,
+                Object {
+                    kind,
+                    translation,
+                    sprite,
+                    player,
+                },
+            ));
+        }
+        let mut removed = Vec::new();
+        for _ in 0..reader.u16()? {
+            removed.push(reader.u64()?);
+        }
+        reader.bytes.is_empty().then(|| Self {
+            tick,
+            base,
+            status,
+            tiles,
+            changed,
+            removed,
+        })
+    }
+}
+
+/// 0 empty, 1 brick, 2 iron, 3 river, 4 grass and 5 snow in the high 4 bits, the cells of a
+/// brick in the low 4 bits
+pub fn encode_tile(tile: Tile) -> u8 {
+    match tile {
+        Tile::Empty => 0,
+        Tile::Brick(cells) => 1 << 4 | cells.bits(),
+        Tile::Iron => 2 << 4,
+        Tile::River => 3 << 4,
+        Tile::Grass => 4 << 4,
+        Tile::Snow => 5 << 4,
+    }
+}
+
+pub fn decode_tile(byte: u8) -> Option<Tile> {
+    Some(match byte >> 4 {
+        0 => Tile::Empty,
+        1 => Tile::Brick(Cells::from_bits(byte & 0xf)),
+        2 => Tile::Iron,
+        3 => Tile::River,
+        4 => Tile::Grass,
+        5 => Tile::Snow,
+        _ => return None,
+    })
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        if self.bytes.len() < N {
+            return None;
+        }
+        let (taken, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+        taken.try_into().ok()
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take().map(|[byte]| byte)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take().map(u64::from_le_bytes)
+    }
+
+    fn f32(&mut self) -> Option<f32> {
+        self.take().map(f32::from_le_bytes)
+    }
+}
+
+#[test]
+fn test_delta() {
+    let mut old = Snapshot {
+        tick: 7,
+        ..Default::default()
+    };
+    old.tiles[3] = Tile::Brick(Cells::FULL);
+    old.tiles[40] = Tile::Iron;
+    let object = Object {
+        kind: ObjectKind::Tank,
+        translation: Vec3::new(1., 2., 3.),
+        sprite: 4,
+        player: Some(2),
+    };
+    old.objects.insert(1, object);
+    old.objects.insert(2, object);
+
+    let mut new = old.clone();
+    new.tick = 8;
+    new.status.scores[1] = 100;
+    new.status.over = true;
+    new.status.mode = Mode::Versus {
+        wins: [1, 0],
+        kills: [2, 3],
+    };
+    new.tiles[3] = Tile::Brick(Cells::from_bits(0b0011));
+    new.objects.remove(&1);
+    new.objects.get_mut(&2).unwrap().translation.x += 1.;
+    new.objects.insert(
+        3,
+        Object {
+            kind: ObjectKind::Effect,
+            player: None,
+            ..object
+        },
+    );
+
+    // only what changed is sent
+    let delta = Delta::between(Some(&old), &new);
+    assert_eq!(delta.base, Some(7));
+    assert_eq!(delta.tiles, vec![(3, new.tiles[3])]);
+    assert_eq!(delta.removed, vec![1]);
+    assert_eq!(delta.changed.len(), 2);
+    let bytes = delta.encode();
+    assert_eq!(bytes.len(), 51 + 3 + 2 * 24 + 8);
+    let decoded = Delta::decode(&bytes).unwrap();
+    assert_eq!(decoded, delta);
+    assert_eq!(decoded.apply(Some(&old)), new);
+    assert_eq!(Delta::decode(&bytes[..bytes.len() - 1]), None);
+
+    // against an empty snapshot, everything is sent
+    let full = Delta::decode(&Delta::between(None, &new).encode()).unwrap();
+    assert_eq!(full.base, None);
+    assert_eq!(full.tiles.len(), 2);
+    assert_eq!(full.apply(None), new);
+
+    // and the score of survival
+    new.status.mode = Mode::Survival { wave: 7, best: 12 };
+    let delta = Delta::between(Some(&old), &new);
+    assert_eq!(Delta::decode(&delta.encode()).unwrap(), delta);
+}
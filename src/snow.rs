@@ -1,26 +1,23 @@
 use crate::{
-    collision::Collider,
-    consts::{HALF_BLOCK_WIDTH, MIN_BLOCK_WIDTH, SCALE},
+    collision::{Collider, Hitbox},
+    consts::{HALF_BLOCK_WIDTH, SCALE},
     state,
     texture::SpriteIndex,
 };
 use bevy::{math::const_vec2, prelude::*};
 
 const SIZE: Vec2 = const_vec2!([HALF_BLOCK_WIDTH, HALF_BLOCK_WIDTH]);
-#[derive(Component)]
+#[derive(Component, Clone)]
 pub struct Snow;
 
-pub fn spawn(commands: &mut Commands, position: Vec3, texture: Handle<TextureAtlas>) {
+/// Spawns snow of a quarter block
+pub fn spawn(commands: &mut Commands, position: Vec3, texture: Handle<TextureAtlas>) -> Entity {
     commands
         .spawn_bundle(SpriteSheetBundle {
             sprite: TextureAtlasSprite::new(SpriteIndex::SNOW[0]),
-            texture_atlas: texture.clone(),
+            texture_atlas: texture,
             transform: Transform {
-                translation: Vec3::new(
-                    position.x - MIN_BLOCK_WIDTH,
-                    position.y + MIN_BLOCK_WIDTH,
-                    position.z,
-                ),
+                translation: position,
                 scale: Vec3::splat(SCALE),
                 ..Default::default()
             },
@@ -28,59 +25,7 @@ pub fn spawn(commands: &mut Commands, position: Vec3, texture: Handle<TextureAtl
         })
         .insert(Snow)
         .insert(Collider::Snow)
-        .insert(state::State::Snow);
-    commands
-        .spawn_bundle(SpriteSheetBundle {
-            sprite: TextureAtlasSprite::new(SpriteIndex::SNOW[0]),
-            texture_atlas: texture.clone(),
-            transform: Transform {
-                translation: Vec3::new(
-                    position.x + MIN_BLOCK_WIDTH,
-                    position.y + MIN_BLOCK_WIDTH,
-                    position.z,
-                ),
-                scale: Vec3::splat(SCALE),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Snow)
-        .insert(Collider::Snow)
-        .insert(state::State::Snow);
-    commands
-        .spawn_bundle(SpriteSheetBundle {
-            sprite: TextureAtlasSprite::new(SpriteIndex::SNOW[0]),
-            texture_atlas: texture.clone(),
-            transform: Transform {
-                translation: Vec3::new(
-                    position.x + MIN_BLOCK_WIDTH,
-                    position.y - MIN_BLOCK_WIDTH,
-                    position.z,
-                ),
-                scale: Vec3::splat(SCALE),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Snow)
-        .insert(Collider::Snow)
-        .insert(state::State::Snow);
-    commands
-        .spawn_bundle(SpriteSheetBundle {
-            sprite: TextureAtlasSprite::new(SpriteIndex::SNOW[0]),
-            texture_atlas: texture.clone(),
-            transform: Transform {
-                translation: Vec3::new(
-                    position.x - MIN_BLOCK_WIDTH,
-                    position.y - MIN_BLOCK_WIDTH,
-                    position.z,
-                ),
-                scale: Vec3::splat(SCALE),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Snow)
-        .insert(Collider::Snow)
-        .insert(state::State::Snow);
+        .insert(Hitbox(SIZE))
+        .insert(state::State::Snow)
+        .id()
 }
@@ -0,0 +1,290 @@
+//! A uniform spatial hash over the battle field, so collision checks only look at colliders
+//! nearby instead of every collider on battle field.
+//!
+//! The grid is synced with colliders which were spawned, moved or despawned at the beginning of
+//! every tick. Systems moving things update the grid right away, so systems after them in the
+//! same tick see where things are now.
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{
+    collision::{Collider, Hitbox},
+    consts::{BATTLE_FIELD_WIDTH, BLOCK_WIDTH, HALF_BLOCK_WIDTH},
+    simulation::AddSimulationSystem,
+    utils::Direction,
+};
+
+const CELL_WIDTH: f32 = BLOCK_WIDTH;
+/// Cells in a row or a column: battle field and one cell around it, where the boundaries are
+const CELLS: i32 = (BATTLE_FIELD_WIDTH / CELL_WIDTH) as i32 + 2;
+const LEFT: f32 = -HALF_BLOCK_WIDTH - BATTLE_FIELD_WIDTH / 2. - CELL_WIDTH;
+const BOTTOM: f32 = -BATTLE_FIELD_WIDTH / 2. - CELL_WIDTH;
+
+/// A collider as the grid knows it
+#[derive(Debug, Clone, Copy)]
+pub struct Shape {
+    pub entity: Entity,
+    pub collider: Collider,
+    pub translation: Vec3,
+    pub size: Vec2,
+}
+
+struct Entry {
+    shape: Shape,
+    cells: (i32, i32, i32, i32), // min x, min y, max x, max y
+}
+
+/// Keeps the spatial grid in sync with colliders on battle field
+pub struct SpatialPlugin;
+
+impl Plugin for SpatialPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpatialGrid>()
+            .add_simulation_system(sync);
+    }
+}
+
+pub struct SpatialGrid {
+    cells: Vec<Vec<Entity>>,
+    entries: HashMap<Entity, Entry>,
+}
+
+impl Default for SpatialGrid {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SpatialGrid {
+    pub fn new() -> Self {
+        Self {
+            cells: vec![Vec::new(); (CELLS * CELLS) as usize],
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, entity: Entity) -> Option<Shape> {
+        self.entries.get(&entity).map(|entry| entry.shape)
+    }
+
+    /// Adds a collider, or replaces it if it is already in the grid
+    pub fn insert(&mut self, entity: Entity, collider: Collider, translation: Vec3, size: Vec2) {
+        self.remove(entity);
+        let cells = cell_range(translation, size);
+        self.for_each_cell(cells, |cell| cell.push(entity));
+        let shape = Shape {
+            entity,
+            collider,
+            translation,
+            size,
+        };
+        self.entries.insert(entity, Entry { shape, cells });
+    }
+
+    /// Moves a collider to a new translation
+    pub fn update(&mut self, entity: Entity, translation: Vec3) {
+        let entry = match self.entries.get_mut(&entity) {
+            Some(entry) => entry,
+            None => return,
+        };
+        entry.shape.translation = translation;
+        let cells = cell_range(translation, entry.shape.size);
+        if cells != entry.cells {
+            let shape = entry.shape;
+            self.insert(entity, shape.collider, shape.translation, shape.size);
+        }
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        if let Some(entry) = self.entries.remove(&entity) {
+            self.for_each_cell(entry.cells, |cell| cell.retain(|e| *e != entity));
+        }
+    }
+
+    /// Colliders overlapping or touching a rectangle, from the bottom left to the top right.
+    ///
+    /// Things at the same place come in the order of their colliders, never of their entities:
+    /// something spawned after a rollback may get another entity than it did before, and so may
+    /// it on the other peer.
+    pub fn query(&self, translation: Vec3, size: Vec2) -> Vec<Shape> {
+        let min = translation.truncate() - size / 2.;
+        let max = translation.truncate() + size / 2.;
+        let (x0, y0, x1, y1) = cell_range(translation, size);
+        let mut entities = Vec::new();
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                entities.extend_from_slice(&self.cells[(y * CELLS + x) as usize]);
+            }
+        }
+        entities.sort_unstable();
+        entities.dedup();
+        let mut shapes: Vec<Shape> = entities
+            .into_iter()
+            .map(|entity| self.entries[&entity].shape)
+            .filter(|shape| {
+                let s_min = shape.translation.truncate() - shape.size / 2.;
+                let s_max = shape.translation.truncate() + shape.size / 2.;
+                s_min.x <= max.x && s_max.x >= min.x && s_min.y <= max.y && s_max.y >= min.y
+            })
+            .collect();
+        shapes.sort_by(|a, b| {
+            let (a_at, b_at) = (a.translation, b.translation);
+            (a_at.y, a_at.x, a_at.z)
+                .partial_cmp(&(b_at.y, b_at.x, b_at.z))
+                .unwrap()
+                .then(a.collider.cmp(&b.collider))
+        });
+        shapes
+    }
+
+    /// Colliders a rectangle overlaps or runs into when it moves some distance in a direction
+    pub fn ahead(
+        &self,
+        translation: Vec3,
+        size: Vec2,
+        direction: Direction,
+        distance: f32,
+    ) -> Vec<Shape> {
+        let offset = match direction {
+            Direction::Up => Vec3::Y,
+            Direction::Right => Vec3::X,
+            Direction::Down => -Vec3::Y,
+            Direction::Left => -Vec3::X,
+        } * distance;
+        let swept = match direction {
+            Direction::Up | Direction::Down => size + Vec2::Y * distance,
+            Direction::Left | Direction::Right => size + Vec2::X * distance,
+        };
+        self.query(translation + offset / 2., swept)
+    }
+
+    fn for_each_cell(&mut self, cells: (i32, i32, i32, i32), mut f: impl FnMut(&mut Vec<Entity>)) {
+        let (x0, y0, x1, y1) = cells;
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                f(&mut self.cells[(y * CELLS + x) as usize]);
+            }
+        }
+    }
+}
+
+/// Cells covered by a rectangle, anything outside of the grid is in the cells at its edges
+fn cell_range(translation: Vec3, size: Vec2) -> (i32, i32, i32, i32) {
+    let cell =
+        |x: f32, origin: f32| (((x - origin) / CELL_WIDTH).floor() as i32).clamp(0, CELLS - 1);
+    let min = translation.truncate() - size / 2.;
+    let max = translation.truncate() + size / 2.;
+    (
+        cell(min.x, LEFT),
+        cell(min.y, BOTTOM),
+        cell(max.x, LEFT),
+        cell(max.y, BOTTOM),
+    )
+}
+
+/// Adds spawned and moved colliders to the grid and removes the despawned ones
+pub fn sync(
+    mut grid: ResMut<SpatialGrid>,
+    changed: Query<
+        (Entity, &Collider, &Transform, &Hitbox),
+        Or<(Added<Collider>, Changed<Transform>)>,
+    >,
+    colliders: Query<(), With<Collider>>,
+) {
+    let despawned: Vec<Entity> = grid
+        .entries
+        .keys()
+        .copied()
+        .filter(|entity| colliders.get(*entity).is_err())
+        .collect();
+    for entity in despawned {
+        grid.remove(entity);
+    }
+    for (entity, collider, transform, hitbox) in changed.iter() {
+        if grid.entries.contains_key(&entity) {
+            grid.update(entity, transform.translation);
+        } else {
+            grid.insert(entity, *collider, transform.translation, hitbox.0);
+        }
+    }
+}
+
+#[test]
+fn test_spatial_grid() {
+    let mut grid = SpatialGrid::new();
+    let size = Vec2::splat(HALF_BLOCK_WIDTH);
+    let mut shapes = Vec::new();
+    for i in 0..200u32 {
+        let translation = Vec3::new(
+            (i % 20) as f32 * 23. - BATTLE_FIELD_WIDTH / 2.,
+            (i / 20) as f32 * 37. - BATTLE_FIELD_WIDTH / 2.,
+            0.,
+        );
+        grid.insert(Entity::from_raw(i), Collider::Brick, translation, size);
+        shapes.push((Entity::from_raw(i), translation));
+    }
+    // the same as checking every shape
+    let area = Vec2::new(100., 60.);
+    for center in [
+        Vec3::ZERO,
+        Vec3::new(-150., 120., 0.),
+        Vec3::new(300., 0., 0.),
+    ] {
+        let found: Vec<Entity> = grid
+            .query(center, area)
+            .iter()
+            .map(|shape| shape.entity)
+            .collect();
+        let expected: Vec<Entity> = shapes
+            .iter()
+            .filter(|(_, t)| {
+                (t.x - center.x).abs() <= (area.x + size.x) / 2.
+                    && (t.y - center.y).abs() <= (area.y + size.y) / 2.
+            })
+            .map(|(entity, _)| *entity)
+            .collect();
+        assert_eq!(found, expected);
+    }
+
+    // moving and removing
+    let entity = Entity::from_raw(0);
+    let far = Vec3::new(100., 100., 0.);
+    grid.update(entity, far);
+    assert!(grid
+        .query(shapes[0].1, size)
+        .iter()
+        .all(|s| s.entity != entity));
+    assert!(grid.query(far, size).iter().any(|s| s.entity == entity));
+    grid.remove(entity);
+    assert!(grid.get(entity).is_none());
+    assert_eq!(grid.len(), 199);
+
+    // a rectangle runs into the one right in front of it
+    let mut grid = SpatialGrid::new();
+    grid.insert(entity, Collider::Iron, Vec3::new(0., 40., 0.), size);
+    assert_eq!(grid.ahead(Vec3::ZERO, size, Direction::Up, 24.).len(), 1);
+    assert!(grid.ahead(Vec3::ZERO, size, Direction::Up, 20.).is_empty());
+    assert!(grid
+        .ahead(Vec3::ZERO, size, Direction::Down, 24.)
+        .is_empty());
+    // things at the same place come in the order of their colliders, whatever their entities
+    let mut grid = SpatialGrid::new();
+    grid.insert(Entity::from_raw(1), Collider::Tank, Vec3::ZERO, size);
+    grid.insert(Entity::from_raw(2), Collider::Brick, Vec3::ZERO, size);
+    let colliders: Vec<Collider> = grid
+        .query(Vec3::ZERO, size)
+        .iter()
+        .map(|shape| shape.collider)
+        .collect();
+    assert_eq!(colliders, [Collider::Brick, Collider::Tank]);
+}
@@ -0,0 +1,150 @@
+//! Watching a game streamed by a server or by another game, read-only.
+//!
+//! Nothing is simulated, snapshots received are drawn as they are: terrain through `TileMap`,
+//! like when playing, and the other objects as plain sprites.
+
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+
+use crate::{
+    consts::SCALE,
+    server::Client,
+    setup,
+    snapshot::{Delta, Mode, Snapshot},
+    stage,
+    terrain::{self, TileEntities, TileMap},
+    texture::Textures,
+    utils::{GRID_SIZE, MAX_PLAYERS, PLAYER_COLORS},
+};
+
+/// Draws what a `Spectator`, which must be inserted as a resource, receives
+pub struct SpectatorPlugin;
+
+impl Plugin for SpectatorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TileMap>()
+            .init_resource::<TileEntities>()
+            .add_startup_system(setup::setup)
+            .add_startup_system(spawn_boundaries)
+            .add_system(spectate.label("spectate"))
+            .add_system(terrain::sync.after("spectate"));
+    }
+}
+
+/// A connection watching a game, and what is drawn of it
+pub struct Spectator {
+    client: Client,
+    connected: bool,
+    drawn: Option<Snapshot>,
+    entities: BTreeMap<u64, Entity>, // drawing objects of the snapshot drawn
+}
+
+impl Spectator {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            connected: true,
+            drawn: None,
+            entities: BTreeMap::new(),
+        }
+    }
+}
+
+fn spawn_boundaries(mut commands: Commands) {
+    stage::spawn_boundaries(&mut commands);
+}
+
+/// Draws the last snapshot received, the last one stays once the game is gone
+pub fn spectate(
+    mut commands: Commands,
+    textures: Res<Textures>,
+    mut spectator: ResMut<Spectator>,
+    mut tiles: ResMut<TileMap>,
+    mut sprites: Query<(&mut Transform, &mut TextureAtlasSprite)>,
+    mut windows: ResMut<Windows>,
+) {
+    if spectator.connected {
+        if let Err(err) = spectator.client.poll() {
+            warn!("disconnected: {}", err);
+            spectator.connected = false;
+        }
+    }
+    let snapshot = match spectator.client.snapshot() {
+        Some(snapshot)
+            if spectator.drawn.as_ref().map(|drawn| drawn.tick) != Some(snapshot.tick) =>
+        {
+            snapshot.clone()
+        }
+        _ => return,
+    };
+
+    let delta = Delta::between(spectator.drawn.as_ref(), &snapshot);
+    for (i, tile) in delta.tiles {
+        tiles.set(i as usize % GRID_SIZE, i as usize / GRID_SIZE, tile);
+    }
+    for id in delta.removed {
+        if let Some(entity) = spectator.entities.remove(&id) {
+            commands.entity(entity).despawn();
+        }
+    }
+    for (id, object) in delta.changed {
+        let color = match object.player {
+            Some(player) => PLAYER_COLORS[player as usize],
+            None => Color::WHITE,
+        };
+        let entity = spectator.entities.get(&id).copied();
+        match entity.and_then(|entity| sprites.get_mut(entity).ok()) {
+            Some((mut transform, mut sprite)) => {
+                transform.translation = object.translation;
+                sprite.index = object.sprite as usize;
+                sprite.color = color;
+            }
+            None => {
+                let entity = commands
+                    .spawn_bundle(SpriteSheetBundle {
+                        sprite: TextureAtlasSprite {
+                            color,
+                            ..TextureAtlasSprite::new(object.sprite as usize)
+                        },
+                        texture_atlas: textures.texture.clone(),
+                        transform: Transform {
+                            translation: object.translation,
+                            scale: Vec3::splat(SCALE),
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    })
+                    .id();
+                spectator.entities.insert(id, entity);
+            }
+        }
+    }
+
+    if let Some(window) = windows.get_primary_mut() {
+        let status = snapshot.status;
+        let mut title = match status.mode {
+            Mode::Stage => format!("Battle City - stage {}", status.stage),
+            Mode::Versus { wins, kills } => format!(
+                "Battle City - P1 {} : {} P2 - kills {} : {}",
+                wins[0], wins[1], kills[0], kills[1]
+            ),
+            Mode::Survival { wave, best } => {
+                format!("Battle City - survival - wave {} - best {}", wave, best)
+            }
+        };
+        for player in 0..(status.players as usize).min(MAX_PLAYERS) {
+            title += &format!(
+                " - P{} {} ({} left)",
+                player + 1,
+                status.scores[player],
+                status.lifes[player]
+            );
+        }
+        if status.over {
+            title += " - over";
+        }
+        window.set_title(title);
+    }
+    spectator.drawn = Some(snapshot);
+}
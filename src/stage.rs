@@ -0,0 +1,244 @@
+use bevy::prelude::*;
+
+use crate::{
+    base::{
+        self,
+        wall::{self, BaseWallType},
+    },
+    brick::Cells,
+    collision::{Collider, Hitbox},
+    config::Config,
+    consts::{BATTLE_FIELD_WIDTH, BLOCK_WIDTH, HALF_BLOCK_WIDTH, SCALE, WINDOW_HEIGHT},
+    game_data::GameData,
+    menu::AppState,
+    power_up,
+    save::Resume,
+    state,
+    survival::Waves,
+    tank::{self, ai, player},
+    terrain::{Tile, TileMap},
+    texture::Textures,
+    utils::{self, block2translation as b2t},
+    versus::{self, Match},
+};
+
+/// Spawns tanks and terrain of the battle field
+pub struct StagePlugin;
+
+impl Plugin for StagePlugin {
+    fn build(&self, app: &mut App) {
+        // spawned in a fixed order, so entities are the same in every run with the same seed
+        app.add_system_set(
+            SystemSet::on_enter(AppState::Playing)
+                .with_system(spawn_tank.label("spawn_tank"))
+                .with_system(spawn_terrian.label("spawn_terrian").after("spawn_tank")),
+        );
+    }
+}
+
+pub fn spawn_tank(
+    mut commands: Commands,
+    textures: Res<Textures>,
+    mut game_data: ResMut<GameData>,
+    resume: Option<Res<Resume>>,
+    versus: Option<Res<Match>>,
+    survival: Option<Res<Waves>>,
+    config: Res<Config>,
+) {
+    if resume.is_some() {
+        return; // tanks come from the save
+    }
+    let texture = &textures.texture;
+    for id in 0..config.players as u8 {
+        let owner = config.teams.owner(id);
+        let position = match versus {
+            Some(_) => versus::spawn_position(owner),
+            None => player::SPAWN_POSITIONS[id as usize],
+        };
+        if id == 0 {
+            player::spawn(&mut commands, texture.clone(), position, owner);
+        } else {
+            tank::spawn(&mut commands, texture.clone(), position, owner, 0);
+        }
+    }
+    if versus.is_some() {
+        // players only, teams across battle field from each other
+        game_data.ai_tanks = 0;
+        return;
+    }
+    if survival.is_some() {
+        // AI tanks come in waves, by survival::waves
+        game_data.ai_tanks = 0;
+        return;
+    }
+    tank::spawn(
+        &mut commands,
+        texture.clone(),
+        ai::SPAWN_POSITION1,
+        utils::Owner::AI,
+        1,
+    );
+    tank::spawn(
+        &mut commands,
+        texture.clone(),
+        ai::SPAWN_POSITION2,
+        utils::Owner::AI,
+        2,
+    );
+    tank::spawn(
+        &mut commands,
+        texture.clone(),
+        ai::SPAWN_POSITION3,
+        utils::Owner::AI,
+        3,
+    );
+    game_data.ai_tanks -= 3; // the rest come in by ai::spawner
+}
+
+/// Spawns boundaries, the base and power ups, and lays out terrain in the tile map.
+///
+/// Only boundaries and the base when a saved game is resumed. In versus, P2 has a base of its
+/// own at the top and both bases are walled with bricks.
+pub fn spawn_terrian(
+    mut commands: Commands,
+    textures: Res<Textures>,
+    mut tiles: ResMut<TileMap>,
+    resume: Option<Res<Resume>>,
+    versus: Option<Res<Match>>,
+) {
+    let texture = &textures.texture;
+
+    spawn_boundaries(&mut commands);
+    base::spawn(
+        &mut commands,
+        b2t(Vec2::new(0., -6.), 0.),
+        texture.clone(),
+        false,
+    );
+    if versus.is_some() {
+        base::spawn(
+            &mut commands,
+            versus::P2_BASE_POSITION,
+            texture.clone(),
+            false,
+        );
+        wall::spawn(&mut tiles, BaseWallType::Brick, false);
+        wall::spawn(&mut tiles, BaseWallType::Brick, true);
+    }
+    if resume.is_some() {
+        return; // terrain and power ups come from the save
+    }
+
+    tiles.set_block(Vec2::new(0., 0.), Tile::Brick(Cells::FULL));
+    tiles.set_block(Vec2::new(1., 0.), Tile::Iron);
+    tiles.set_block(Vec2::new(-1., 0.), Tile::Grass);
+    tiles.set_block(Vec2::new(0., -1.), Tile::Snow);
+    tiles.set_block(Vec2::new(1., -1.), Tile::River);
+
+    power_up::spawn(
+        &mut commands,
+        b2t(Vec2::new(-3., 1.), 0.),
+        power_up::PowerType::Helmet,
+        texture.clone(),
+    );
+    power_up::spawn(
+        &mut commands,
+        b2t(Vec2::new(-2., 1.), 0.),
+        power_up::PowerType::Clock,
+        texture.clone(),
+    );
+    power_up::spawn(
+        &mut commands,
+        b2t(Vec2::new(-1., 1.), 0.),
+        power_up::PowerType::Shovel,
+        texture.clone(),
+    );
+    power_up::spawn(
+        &mut commands,
+        b2t(Vec2::new(0., 1.), 0.),
+        power_up::PowerType::Star,
+        texture.clone(),
+    );
+    power_up::spawn(
+        &mut commands,
+        b2t(Vec2::new(1., 1.), 0.),
+        power_up::PowerType::Grenade,
+        texture.clone(),
+    );
+    power_up::spawn(
+        &mut commands,
+        b2t(Vec2::new(2., 1.), 0.),
+        power_up::PowerType::Tank,
+        texture.clone(),
+    );
+    power_up::spawn(
+        &mut commands,
+        b2t(Vec2::new(3., 1.), 0.),
+        power_up::PowerType::Gun,
+        texture.clone(),
+    );
+}
+
+/// Grey walls around battle field
+pub fn spawn_boundaries(commands: &mut Commands) {
+    // let wall_thickness = 10.;
+    let left_size = Vec2::new(BLOCK_WIDTH, WINDOW_HEIGHT);
+    let right_size = Vec2::new(2. * BLOCK_WIDTH, WINDOW_HEIGHT);
+    let top_size = Vec2::new(BATTLE_FIELD_WIDTH, HALF_BLOCK_WIDTH);
+    // left
+    commands
+        .spawn_bundle(SpriteBundle {
+            transform: Transform::from_translation(Vec3::new(-120. * SCALE, 0., 0.)),
+            sprite: Sprite {
+                custom_size: Some(left_size),
+                color: Color::GRAY,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Collider::Boundary)
+        .insert(Hitbox(left_size))
+        .insert(state::State::Boundary);
+    // right
+    commands
+        .spawn_bundle(SpriteBundle {
+            transform: Transform::from_translation(Vec3::new(112. * SCALE, 0., 0.)),
+            sprite: Sprite {
+                custom_size: Some(right_size),
+                color: Color::GRAY,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Collider::Boundary)
+        .insert(Hitbox(right_size))
+        .insert(state::State::Boundary);
+    // top
+    commands
+        .spawn_bundle(SpriteBundle {
+            transform: Transform::from_translation(Vec3::new(-HALF_BLOCK_WIDTH, 108. * SCALE, 0.)),
+            sprite: Sprite {
+                custom_size: Some(top_size),
+                color: Color::GRAY,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Collider::Boundary)
+        .insert(Hitbox(top_size))
+        .insert(state::State::Boundary);
+    // bottom
+    commands
+        .spawn_bundle(SpriteBundle {
+            transform: Transform::from_translation(Vec3::new(-HALF_BLOCK_WIDTH, -108. * SCALE, 0.)),
+            sprite: Sprite {
+                custom_size: Some(top_size),
+                color: Color::GRAY,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Collider::Boundary)
+        .insert(Hitbox(top_size))
+        .insert(state::State::Boundary);
+}
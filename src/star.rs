@@ -1,34 +1,70 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, sprite::collide_aabb};
 
 use crate::{
-    ai,
     consts::SCALE,
-    p1, p2,
+    game_data::GameData,
+    simulation::TICK,
+    tank::{ai, controller::Controllers, player, Tank, TANK_SIZE},
     texture::{SpriteIndex, Textures},
     utils::Owner,
 };
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum State {
     Shrink,
     Enlarge,
 }
 
-#[derive(Component)]
+#[derive(Component, Clone)]
 pub struct Star {
     owner: Owner,   // tank's owner
     level: u8,      // tank's level
+    bonus: bool,    // if the tank drops a power up when destroyed
     done: bool,     // if star has finish twinkling
     state: State,   // if star is shrinking or enlarging
 }
 
+impl Star {
+    /// A star part way through twinkling, e.g. of a saved game
+    pub fn new(owner: Owner, level: u8, bonus: bool, done: bool, state: State) -> Self {
+        Self {
+            owner,
+            level,
+            bonus,
+            done,
+            state,
+        }
+    }
+
+    pub fn owner(&self) -> Owner {
+        self.owner
+    }
+
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    pub fn bonus(&self) -> bool {
+        self.bonus
+    }
+
+    pub fn done(&self) -> bool {
+        self.done
+    }
+
+    pub fn state(&self) -> State {
+        self.state.clone()
+    }
+}
+
 pub fn spawn(
     commands: &mut Commands,
     texture: Handle<TextureAtlas>,
     position: Vec3,
     owner: Owner,
     level: u8,
-) {
+    bonus: bool,
+) -> Entity {
     commands
         .spawn_bundle(SpriteSheetBundle {
             sprite: TextureAtlasSprite::new(SpriteIndex::STAR[0]),
@@ -44,15 +80,20 @@ pub fn spawn(
             owner,
             done: false,
             level,
+            bonus,
             state: State::Enlarge,
         })
-        .insert(Timer::from_seconds(0.1, true));
+        .insert(Timer::from_seconds(0.1, true))
+        .id()
 }
 
+/// Stars twinkle until the tank comes out. A star keeps twinkling while another tank stands on it,
+/// so tanks never spawn on top of each other.
 pub fn twinkling(
-    time: Res<Time>,
     mut commands: Commands,
     textures: Res<Textures>,
+    game_data: Res<GameData>,
+    controllers: Res<Controllers>,
     mut stars: Query<(
         Entity,
         &mut Timer,
@@ -60,9 +101,10 @@ pub fn twinkling(
         &Transform,
         &mut Star,
     )>,
+    tanks: Query<&Transform, With<Tank>>,
 ) {
     for (entity, mut timer, mut sprite, transform, mut star) in stars.iter_mut() {
-        if timer.tick(time.delta()).just_finished() {
+        if timer.tick(TICK).just_finished() {
             match sprite.index {
                 272 => {
                     if star.state == State::Shrink {
@@ -70,19 +112,25 @@ pub fn twinkling(
                     }
                     sprite.index += 1;
                 }
-                275 => match star.done {
+                275 => match star.done && !occupied(transform.translation, &tanks) {
                     true => {
                         commands.entity(entity).despawn();
-                        match star.owner {
-                            Owner::P1 => p1::spawn(&mut commands, textures.texture.clone()),
-                            Owner::P2 => p2::spawn(&mut commands, textures.texture.clone()),
-                            Owner::AI => ai::spawn(
+                        match star.owner.player {
+                            Some(_) => player::spawn(
+                                &mut commands,
+                                textures.texture.clone(),
+                                transform.translation,
+                                star.owner,
+                            ),
+                            None => ai::spawn(
                                 &mut commands,
                                 textures.texture.clone(),
                                 transform.translation,
                                 star.level,
+                                star.bonus,
+                                (controllers.select)(game_data.stage, star.level),
                             ),
-                        }
+                        };
                     }
                     false => {
                         star.done = true;
@@ -98,3 +146,10 @@ pub fn twinkling(
         }
     }
 }
+
+/// If a tank overlaps a spawn point
+fn occupied(position: Vec3, tanks: &Query<&Transform, With<Tank>>) -> bool {
+    tanks.iter().any(|transform| {
+        collide_aabb::collide(position, TANK_SIZE, transform.translation, TANK_SIZE).is_some()
+    })
+}
@@ -2,7 +2,7 @@ use bevy::prelude::Component;
 
 use crate::{brick, bullet, power_up, tank};
 
-#[derive(Component, Debug)]
+#[derive(Component, Debug, Clone)]
 pub enum State {
     Boundary, // boundary of battle field
     Brick(brick::State),
@@ -0,0 +1,447 @@
+//! Survival: a stage without end. AI tanks come in waves, every wave bigger and with more armoured
+//! and fast tanks than the one before, and the next one comes once the last tank of a wave is
+//! destroyed. Meanwhile, terrain destroyed slowly grows back as it was at the first tick.
+//!
+//! The game is over as any other one, when the base is destroyed or players have no tanks left.
+//! Its score is the number of waves survived, kept in a high-score table of its own.
+
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::{
+    collision::Collider,
+    config::{Config, Difficulty},
+    consts::HALF_BLOCK_WIDTH,
+    game_data::{self, GameData},
+    simulation::{AddSimulationSystem, GameRng, SimulationStage, TICK},
+    spatial::SpatialGrid,
+    star::Star,
+    tank::Tank,
+    terrain::{Tile, TileMap},
+    utils::{grid2translation, Owner, AI, GRID_SIZE},
+};
+
+pub const HIGH_SCORES_PATH: &str = "battle_city.hi";
+const HIGH_SCORES_MAGIC: &[u8; 4] = b"BCHS";
+const HIGH_SCORES_VERSION: u8 = 1;
+/// Entries kept in the high-score table
+const TABLE_SIZE: usize = 10;
+const FAST_RATIO: f64 = 0.1; // chance of a tank of the first wave being a fast one
+const MAX_RATIO: f64 = 0.4; // of armoured tanks, and of fast tanks
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rules {
+    pub first_wave: u8,     // AI tanks in the first wave
+    pub growth: u8,         // AI tanks more in every wave
+    pub ratio_step: f64,    // armoured and fast tanks' ratios grow by, every wave
+    pub regrowth: Duration, // between two quarter blocks of terrain growing back
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            first_wave: 4,
+            growth: 2,
+            ratio_step: 0.04,
+            regrowth: Duration::from_secs(3),
+        }
+    }
+}
+
+pub struct SurvivalPlugin {
+    pub rules: Rules,
+    pub high_scores: Option<PathBuf>, // where the high-score table is kept, None to not keep it
+}
+
+impl Plugin for SurvivalPlugin {
+    fn build(&self, app: &mut App) {
+        let scores = match &self.high_scores {
+            Some(path) => HighScores::load(path).unwrap_or_default(),
+            None => HighScores::default(),
+        };
+        app.insert_resource(Waves::new(self.rules))
+            .insert_resource(HighScoreTable {
+                path: self.high_scores.clone(),
+                scores,
+                recorded: false,
+                rank: None,
+            })
+            .add_simulation_system(waves)
+            .add_simulation_system(regrow)
+            // after every other system of the tick, and the commands they issued
+            .add_system_to_stage(SimulationStage, record.exclusive_system().at_end())
+            .add_system(title);
+    }
+}
+
+/// The wave on battle field, and terrain as it grows back to
+#[derive(Clone)]
+pub struct Waves {
+    pub rules: Rules,
+    pub wave: u32,             // from 1, 0 before the first one comes
+    layout: Option<Vec<Tile>>, // terrain at the first tick, row by row from the top left one
+    regrowth: Duration,        // since a quarter block grew back
+}
+
+impl Waves {
+    pub fn new(rules: Rules) -> Self {
+        Self {
+            rules,
+            wave: 0,
+            layout: None,
+            regrowth: Duration::ZERO,
+        }
+    }
+
+    /// The score, waves whose tanks were all destroyed
+    pub fn survived(&self) -> u32 {
+        self.wave.saturating_sub(1)
+    }
+
+    /// AI tanks in a wave
+    pub fn size(&self, wave: u32) -> u8 {
+        let size = self.rules.first_wave as u32 + self.rules.growth as u32 * (wave - 1);
+        size.min(u8::MAX as u32) as u8
+    }
+
+    /// Level of an AI tank of the wave, 3 is an armoured one and 1 a fast one. Armoured tanks
+    /// start from the difficulty's ratio.
+    pub fn level(&self, armour_ratio: f64, rng: &mut impl Rng) -> u8 {
+        let step = self.rules.ratio_step * self.survived() as f64;
+        let armour = (armour_ratio + step).min(MAX_RATIO);
+        let fast = (FAST_RATIO + step).min(MAX_RATIO);
+        let roll: f64 = rng.gen();
+        if roll < armour {
+            3
+        } else if roll < armour + fast {
+            1
+        } else if rng.gen_bool(0.5) {
+            2
+        } else {
+            0
+        }
+    }
+}
+
+/// Brings the next wave once every tank of the last one is destroyed, `ai::spawner` brings its
+/// tanks onto battle field
+pub fn waves(
+    mut waves: ResMut<Waves>,
+    mut game_data: ResMut<GameData>,
+    tanks: Query<Entity, (With<AI>, With<Tank>)>,
+    stars: Query<&Star>,
+) {
+    let on_field =
+        tanks.iter().next().is_some() || stars.iter().any(|star| star.owner() == Owner::AI);
+    if on_field || game_data.ai_tanks > 0 {
+        return;
+    }
+    waves.wave += 1;
+    game_data.ai_tanks = waves.size(waves.wave);
+}
+
+/// If a tile is destroyed, or partly, from what it was at the first tick
+fn damaged(original: Tile, tile: Tile) -> bool {
+    match (original, tile) {
+        (Tile::Brick(_), Tile::Empty | Tile::Brick(_)) => original != tile,
+        (Tile::Iron, Tile::Empty) => true,
+        _ => false,
+    }
+}
+
+/// Grows a quarter block of terrain destroyed back every `Rules::regrowth`, never under a tank
+pub fn regrow(
+    mut waves: ResMut<Waves>,
+    mut tiles: ResMut<TileMap>,
+    mut rng: ResMut<GameRng>,
+    grid: Res<SpatialGrid>,
+) {
+    let waves = &mut *waves;
+    let layout = match &waves.layout {
+        Some(layout) => layout,
+        None => {
+            waves.layout = Some(tiles.iter().map(|(_, _, tile)| tile).collect());
+            return;
+        }
+    };
+    waves.regrowth += TICK;
+    if waves.regrowth < waves.rules.regrowth {
+        return;
+    }
+    waves.regrowth = Duration::ZERO;
+
+    let size = Vec2::splat(HALF_BLOCK_WIDTH);
+    let candidates: Vec<(usize, usize)> = tiles
+        .iter()
+        .filter(|(x, y, tile)| damaged(layout[y * GRID_SIZE + x], *tile))
+        .filter(|(x, y, _)| {
+            let translation = grid2translation(*x as i32, *y as i32, 0.);
+            !grid
+                .query(translation, size)
+                .iter()
+                .any(|shape| matches!(shape.collider, Collider::Tank))
+        })
+        .map(|(x, y, _)| (x, y))
+        .collect();
+    if candidates.is_empty() {
+        return;
+    }
+    let (x, y) = candidates[rng.gen_range(0..candidates.len())];
+    tiles.set(x, y, layout[y * GRID_SIZE + x]);
+}
+
+/// A game in the high-score table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    pub waves: u32, // survived
+    pub difficulty: Difficulty,
+    pub players: u8,
+}
+
+/// The best games, the best first
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HighScores(pub Vec<Entry>);
+
+impl HighScores {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        Self::from_bytes(&bytes)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        File::create(path)?.write_all(&self.to_bytes())
+    }
+
+    /// Puts a game into the table, after the games as good as it, and returns its rank from 0.
+    /// None if it is not good enough to be kept.
+    pub fn insert(&mut self, entry: Entry) -> Option<usize> {
+        let rank = self
+            .0
+            .iter()
+            .position(|other| other.waves < entry.waves)
+            .unwrap_or(self.0.len());
+        if rank >= TABLE_SIZE {
+            return None;
+        }
+        self.0.insert(rank, entry);
+        self.0.truncate(TABLE_SIZE);
+        Some(rank)
+    }
+
+    /// magic, version, number of entries, then each of them as (waves u32 little endian,
+    /// difficulty, players)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(HIGH_SCORES_MAGIC);
+        bytes.push(HIGH_SCORES_VERSION);
+        bytes.push(self.0.len() as u8);
+        for entry in &self.0 {
+            bytes.extend_from_slice(&entry.waves.to_le_bytes());
+            bytes.push(entry.difficulty as u8);
+            bytes.push(entry.players);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        if bytes.len() < 6 || &bytes[..4] != HIGH_SCORES_MAGIC {
+            return Err(invalid("not a high-score table"));
+        }
+        if bytes[4] != HIGH_SCORES_VERSION {
+            return Err(invalid("unsupported high-score table version"));
+        }
+        let entries = &bytes[6..];
+        if entries.len() != bytes[5] as usize * 6 {
+            return Err(invalid("corrupted high-score table"));
+        }
+        let mut scores = Vec::new();
+        for entry in entries.chunks(6) {
+            scores.push(Entry {
+                waves: u32::from_le_bytes(entry[..4].try_into().unwrap()),
+                difficulty: *Difficulty::ALL
+                    .get(entry[4] as usize)
+                    .ok_or_else(|| invalid("unknown difficulty"))?,
+                players: entry[5],
+            });
+        }
+        Ok(Self(scores))
+    }
+}
+
+/// The high-score table, and the game's rank in it once the game is over
+pub struct HighScoreTable {
+    pub path: Option<PathBuf>,
+    pub scores: HighScores,
+    pub recorded: bool, // the game is over and was put into the table
+    pub rank: Option<usize>,
+}
+
+/// Puts the game into the high-score table once it is over
+pub fn record(world: &mut World) {
+    if world.get_resource::<HighScoreTable>().unwrap().recorded || !game_data::is_over(world) {
+        return;
+    }
+    let config = world.get_resource::<Config>().unwrap();
+    let entry = Entry {
+        waves: world.get_resource::<Waves>().unwrap().survived(),
+        difficulty: config.difficulty,
+        players: config.players as u8,
+    };
+    let mut table = world.get_resource_mut::<HighScoreTable>().unwrap();
+    table.recorded = true;
+    table.rank = table.scores.insert(entry);
+    if let (Some(path), Some(_)) = (&table.path, table.rank) {
+        if let Err(err) = table.scores.save(path) {
+            error!("failed to save high scores: {}", err);
+        }
+    }
+}
+
+/// Shows the wave and the best score in the window's title
+pub fn title(waves: Res<Waves>, table: Res<HighScoreTable>, windows: Option<ResMut<Windows>>) {
+    // no window when headless
+    let mut windows = match windows {
+        Some(windows) => windows,
+        None => return,
+    };
+    let window = match windows.get_primary_mut() {
+        Some(window) => window,
+        None => return,
+    };
+    let best = table.scores.0.first().map_or(0, |entry| entry.waves);
+    let mut title = format!(
+        "Battle City - survival - wave {} - best {}",
+        waves.wave, best
+    );
+    if table.recorded {
+        title += &match table.rank {
+            Some(rank) => format!(
+                " - {} waves survived, #{} of the best",
+                waves.survived(),
+                rank + 1
+            ),
+            None => format!(" - {} waves survived", waves.survived()),
+        };
+    }
+    if window.title() != title {
+        window.set_title(title);
+    }
+}
+
+#[test]
+fn test_high_scores() {
+    let entry = |waves| Entry {
+        waves,
+        difficulty: Difficulty::Hard,
+        players: 2,
+    };
+    let mut scores = HighScores::default();
+    assert_eq!(scores.insert(entry(3)), Some(0));
+    assert_eq!(scores.insert(entry(5)), Some(0));
+    assert_eq!(scores.insert(entry(3)), Some(2)); // after the games as good as it
+    for _ in 0..TABLE_SIZE {
+        scores.insert(entry(4));
+    }
+    assert_eq!(scores.0.len(), TABLE_SIZE);
+    assert_eq!(scores.insert(entry(1)), None);
+    assert_eq!(scores.0[0], entry(5));
+
+    let bytes = scores.to_bytes();
+    assert_eq!(bytes.len(), 6 + 6 * TABLE_SIZE);
+    assert_eq!(HighScores::from_bytes(&bytes).unwrap(), scores);
+    assert!(HighScores::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+}
+
+#[test]
+fn test_waves() {
+    use crate::{
+        brick::Cells,
+        input::InputSource,
+        team::{FriendlyFire, Teams},
+        BattleCityPlugins,
+    };
+
+    let rules = Rules {
+        regrowth: TICK * 10,
+        ..Default::default()
+    };
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugins(BattleCityPlugins {
+            headless: true,
+            seed: 11,
+            difficulty: Difficulty::default(),
+            input: InputSource::External,
+            ally: false,
+            save: None,
+            players: 1,
+            versus: None,
+            teams: Teams::COOP,
+            friendly_fire: FriendlyFire::Off,
+            survival: Some(rules),
+        });
+    for _ in 0..2 {
+        app.update();
+    }
+    let waves = app.world.get_resource::<Waves>().unwrap();
+    assert_eq!(waves.wave, 1);
+    assert_eq!(waves.size(1), rules.first_wave);
+    assert_eq!(waves.size(3), rules.first_wave + 2 * rules.growth);
+
+    // the last tank of a wave is destroyed, and the next one comes instead of the stage ending
+    let mut ai_tanks = app.world.query_filtered::<Entity, With<AI>>();
+    for entity in ai_tanks.iter(&app.world).collect::<Vec<_>>() {
+        app.world.despawn(entity);
+    }
+    app.world.get_resource_mut::<GameData>().unwrap().ai_tanks = 0;
+    app.update();
+    assert_eq!(app.world.get_resource::<Waves>().unwrap().wave, 2);
+    assert_eq!(
+        app.world.get_resource::<GameData>().unwrap().ai_tanks,
+        rules.first_wave + rules.growth
+    );
+    assert!(!game_data::is_over(&mut app.world));
+
+    // a brick shot through grows back
+    let (x, y, original) = app
+        .world
+        .get_resource::<TileMap>()
+        .unwrap()
+        .iter()
+        .find(|(_, _, tile)| *tile == Tile::Brick(Cells::FULL))
+        .unwrap();
+    app.world
+        .get_resource_mut::<TileMap>()
+        .unwrap()
+        .set(x, y, Tile::Empty);
+    for _ in 0..10 {
+        app.update();
+    }
+    assert_eq!(
+        app.world.get_resource::<TileMap>().unwrap().get(x, y),
+        original
+    );
+
+    // later waves have more armoured and fast tanks
+    let mut waves = Waves::new(rules);
+    let mut rng = GameRng::new(5);
+    let count = |waves: &Waves, rng: &mut GameRng, level| {
+        (0..1000)
+            .filter(|_| waves.level(0.1, &mut *rng) == level)
+            .count()
+    };
+    waves.wave = 1;
+    let (armoured, fast) = (count(&waves, &mut rng, 3), count(&waves, &mut rng, 1));
+    waves.wave = 6;
+    assert!(count(&waves, &mut rng, 3) > armoured);
+    assert!(count(&waves, &mut rng, 1) > fast);
+}
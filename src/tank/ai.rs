@@ -1,14 +1,29 @@
+use std::time::Duration;
+
 use bevy::{math::const_vec3, prelude::*};
 use rand::Rng;
 
 use crate::{
     bullet,
-    collision::{collide, Collider},
+    collision::{Collider, Hitbox},
+    config::Config,
     consts::{BATTLE_FIELD_WIDTH, BLOCK_WIDTH, SCALE},
+    game_data::GameData,
+    pathfinding::PathGrid,
+    simulation::{GameRng, TICK},
+    spatial::SpatialGrid,
+    star::{self, Star},
     state,
-    tank::{AnimationTimer, Data, MovementTimer, Tank, TANK_SIZE, TANK_SPEED},
-    texture::{SpriteIndex, Textures},
-    utils::{get_sprite, Direction, Owner, AI},
+    survival::Waves,
+    tank::{
+        cal_position,
+        controller::{
+            blocker, Action, AiSettings, Controller, Surroundings, TankController, WorldView,
+        },
+        AnimationTimer, Data, Tank, TANK_SIZE, TANK_SPEED,
+    },
+    texture::Textures,
+    utils::{Direction, Owner, AI},
 };
 
 pub const SPAWN_POSITION1: Vec3 = const_vec3!([
@@ -27,13 +42,27 @@ pub const SPAWN_POSITION3: Vec3 = const_vec3!([
     0.
 ]);
 
-pub fn spawn(commands: &mut Commands, texture: Handle<TextureAtlas>, position: Vec3, level: u8) {
-    let index = match level {
+// the order AI tanks come in, as on NES
+const SPAWN_POSITIONS: [Vec3; 3] = [SPAWN_POSITION2, SPAWN_POSITION3, SPAWN_POSITION1];
+/// AI tanks on battle field at the same time
+pub const MAX_ON_FIELD: usize = 4;
+const BONUS_SPRITE_OFFSET: usize = 128; // red tanks
+
+pub fn spawn(
+    commands: &mut Commands,
+    texture: Handle<TextureAtlas>,
+    position: Vec3,
+    level: u8,
+    bonus: bool,
+    controller: Box<dyn TankController>,
+) -> Entity {
+    let base_sprite = match level {
         0 => 72,
         1 => 88,
         2 => 104,
         _ => 120,
     };
+    let index = base_sprite + sprite_offset(Direction::Down); // enemies enter facing down
     commands
         .spawn_bundle(SpriteSheetBundle {
             sprite: TextureAtlasSprite::new(index),
@@ -47,150 +76,112 @@ pub fn spawn(commands: &mut Commands, texture: Handle<TextureAtlas>, position: V
         })
         .insert(Tank)
         .insert(Collider::Tank)
-        .insert(MovementTimer(Timer::from_seconds(0.01, true)))
+        .insert(Hitbox(TANK_SIZE))
         .insert(AnimationTimer(Timer::from_seconds(0.1, true)))
         .insert(state::State::Tank(Data {
+            direction: Direction::Down,
             owner: Owner::AI,
-            base_sprite: index,
+            base_sprite,
             level,
+            bonus,
             ..Default::default()
         }))
-        .insert(AI);
+        .insert(AI)
+        .insert(Controller(controller))
+        .id()
 }
 
-#[derive(PartialEq, Clone, Copy, Debug)]
-pub enum Action {
-    MoveForward,
-    TurnLeft,
-    TurnRight,
-    TurnAround,
-}
-
-pub struct Decision {
-    action: Action,
-    fire: bool,
-}
-
-impl Decision {
-    pub fn random() -> Self {
-        let mut rng = rand::thread_rng();
-        let action = match rng.gen_range(0..4) {
-            0 => Action::MoveForward,
-            1 => Action::TurnLeft,
-            2 => Action::TurnRight,
-            3 => Action::TurnAround,
-            _ => unreachable!(),
-        };
-        let fire = rng.gen_range(0..2) == 0;
-        Self { action, fire }
+fn sprite_offset(direction: Direction) -> usize {
+    match direction {
+        Direction::Up => 0,
+        Direction::Left => 2,
+        Direction::Down => 4,
+        Direction::Right => 6,
     }
 }
 
+/// Moves and fires AI tanks as their controllers decide
 pub fn movement(
     mut commands: Commands,
-    time: Res<Time>,
     textures: Res<Textures>,
-    texture_atlases: Res<Assets<TextureAtlas>>,
+    settings: Res<AiSettings>,
+    grid: Res<PathGrid>,
+    mut spatial: ResMut<SpatialGrid>,
+    mut rng: ResMut<GameRng>,
     mut set: QuerySet<(
         QueryState<
             (
                 Entity,
                 &mut Transform,
-                &mut MovementTimer,
                 &mut state::State,
                 &mut TextureAtlasSprite,
+                &mut Controller,
             ),
             (With<AI>, With<Tank>),
         >,
-        QueryState<(
-            Entity,
-            &Collider,
-            &Transform,
-            Option<&Sprite>,
-            Option<&TextureAtlasSprite>,
-        )>,
+        QueryState<(Entity, &Collider, &Transform, Option<&AI>)>,
     )>,
 ) {
     let texture = &textures.texture;
-    let obstacles = set
-        .q1()
-        .iter()
-        .map(|(entity, collider, transform, sprite, texture_sprite)| {
-            let size = match *collider {
-                Collider::Boundary => sprite.unwrap().custom_size.unwrap(),
-                _ => {
-                    let index = texture_sprite.unwrap().index;
-                    let texture_atlas = texture_atlases.get(texture).unwrap();
-                    let sprite = texture_atlas.textures.get(index).unwrap();
-                    sprite.size() * SCALE
-                }
-            };
-            (
-                entity.clone(),
-                collider.clone(),
-                transform.translation.clone(),
-                size,
-            )
-        })
-        .collect::<Vec<_>>();
-    for (t_entity, mut t_transform, mut timer, mut state, mut sprite) in set.q0().iter_mut() {
-        if !timer.0.tick(time.delta()).just_finished() {
-            continue;
-        }
+    let surroundings = Surroundings::new(set.q1().iter());
+    let players = surroundings.players(None);
 
+    for (t_entity, mut t_transform, mut state, mut sprite, mut controller) in set.q0().iter_mut() {
         let tank = state.as_mut_tank();
-        let decision = Decision::random();
-        let mut min_distance = BATTLE_FIELD_WIDTH; // a large float number
-        for (o_entity, collider, o_translation, o_size) in obstacles.iter() {
-            if &t_entity == o_entity {
-                continue;
-            }
+        let (min_distance, blocked_by) = blocker(
+            &spatial,
+            t_entity,
+            t_transform.translation,
+            tank.direction,
+            tank.amphibious,
+        );
+        let blocked = blocked_by.is_some();
+        let view = WorldView {
+            translation: t_transform.translation,
+            direction: tank.direction,
+            level: tank.level,
+            amphibious: tank.amphibious,
+            blocked_by,
+            base: surroundings.base,
+            enemies: &players,
+            allies: &surroundings.ai_tanks(Some(t_entity)),
+            power_ups: &surroundings.power_ups,
+            grid: &grid,
+            settings: &settings,
+        };
+        let decision = controller.0.decide(&view, &mut rng);
 
-            match decision.action {
-                Action::MoveForward => {
-                    match collide(
-                        t_transform.translation,
-                        TANK_SIZE,
-                        *o_translation,
-                        *o_size,
-                        &tank.direction,
-                    ) {
-                        None => continue,
-                        Some(distance) => {
-                            if distance < min_distance {
-                                min_distance = distance;
-                            }
-                        }
+        let direction = decision.action.apply(tank.direction);
+        if direction != tank.direction {
+            // align to the grid on turns as players' tanks do
+            if !direction.is_opposite(tank.direction) {
+                match direction {
+                    Direction::Up | Direction::Down => {
+                        t_transform.translation.x = cal_position(t_transform.translation, direction)
                     }
-
-                    let move_distance = min_distance.min(TANK_SPEED);
-                    match tank.direction {
-                        Direction::Up => t_transform.translation.y += move_distance,
-                        Direction::Down => t_transform.translation.y -= move_distance,
-                        Direction::Left => t_transform.translation.x -= move_distance,
-                        Direction::Right => t_transform.translation.x += move_distance,
+                    Direction::Left | Direction::Right => {
+                        t_transform.translation.y = cal_position(t_transform.translation, direction)
                     }
                 }
-                Action::TurnLeft => {
-                    tank.direction.turn_left();
-                    sprite.index = get_sprite(tank.owner, tank.level, tank.direction);
-                }
-                Action::TurnRight => {
-                    tank.direction.turn_right();
-                    sprite.index = get_sprite(tank.owner, tank.level, tank.direction);
-                }
-                Action::TurnAround => {
-                    tank.direction.turn_around();
-                    sprite.index = get_sprite(tank.owner, tank.level, tank.direction);
-                }
+            }
+            tank.direction = direction;
+            sprite.index = tank.base_sprite + sprite_offset(direction);
+        } else if !blocked && decision.action == Action::MoveForward {
+            let move_distance = min_distance.min(TANK_SPEED);
+            match tank.direction {
+                Direction::Up => t_transform.translation.y += move_distance,
+                Direction::Down => t_transform.translation.y -= move_distance,
+                Direction::Left => t_transform.translation.x -= move_distance,
+                Direction::Right => t_transform.translation.x += move_distance,
             }
         }
+        spatial.update(t_entity, t_transform.translation);
 
         if decision.fire {
             bullet::spawn(
                 &mut commands,
                 texture.clone(),
-                t_transform.translation,
+                bullet::cal_position(&t_transform.translation, &tank.direction),
                 &tank.direction,
                 Owner::AI,
                 tank.level,
@@ -200,12 +191,76 @@ pub fn movement(
 }
 
 pub fn animation(
-    time: Res<Time>,
-    mut query: Query<(&mut AnimationTimer, &mut TextureAtlasSprite), With<AI>>,
+    mut query: Query<(&mut AnimationTimer, &mut TextureAtlasSprite, &state::State), With<AI>>,
 ) {
-    for (mut timer, mut sprite) in query.iter_mut() {
-        if timer.0.tick(time.delta()).finished() {
+    for (mut timer, mut sprite, state) in query.iter_mut() {
+        if timer.0.tick(TICK).finished() {
             sprite.index ^= 1;
+            // bonus tanks flash red
+            if state.as_tank().bonus {
+                if sprite.index >= state.as_tank().base_sprite + BONUS_SPRITE_OFFSET {
+                    sprite.index -= BONUS_SPRITE_OFFSET;
+                } else {
+                    sprite.index += BONUS_SPRITE_OFFSET;
+                }
+            }
         }
     }
 }
+
+/// Tunes AI tanks by the difficulty chosen for the game
+pub fn apply_difficulty(config: Res<Config>, mut settings: ResMut<AiSettings>) {
+    *settings = config.difficulty.ai_settings();
+}
+
+/// Brings the rest of AI tanks onto battle field one by one
+#[derive(Default, Clone)]
+pub struct EnemySpawner {
+    pub elapsed: Duration,
+    pub next: usize, // index of SPAWN_POSITIONS
+}
+
+pub fn spawner(
+    mut commands: Commands,
+    textures: Res<Textures>,
+    settings: Res<AiSettings>,
+    mut rng: ResMut<GameRng>,
+    mut game_data: ResMut<GameData>,
+    mut spawner: ResMut<EnemySpawner>,
+    waves: Option<Res<Waves>>,
+    tanks: Query<Entity, (With<AI>, With<Tank>)>,
+    stars: Query<&Star>,
+) {
+    spawner.elapsed += TICK;
+    if spawner.elapsed < settings.spawn_interval || game_data.ai_tanks == 0 {
+        return;
+    }
+    let on_field = tanks.iter().count()
+        + stars
+            .iter()
+            .filter(|star| star.owner() == Owner::AI)
+            .count();
+    if on_field >= MAX_ON_FIELD {
+        return;
+    }
+
+    spawner.elapsed = Duration::ZERO;
+    let level = if let Some(waves) = waves {
+        waves.level(settings.armour_ratio, &mut *rng)
+    } else if rng.gen_bool(settings.armour_ratio) {
+        3
+    } else {
+        rng.gen_range(0..3)
+    };
+    let bonus = rng.gen_bool(settings.bonus_ratio);
+    star::spawn(
+        &mut commands,
+        textures.texture.clone(),
+        SPAWN_POSITIONS[spawner.next],
+        Owner::AI,
+        level,
+        bonus,
+    );
+    spawner.next = (spawner.next + 1) % SPAWN_POSITIONS.len();
+    game_data.ai_tanks -= 1;
+}
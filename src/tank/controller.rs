@@ -0,0 +1,691 @@
+//! Decision making of computer controlled tanks.
+//!
+//! A controller only sees a read-only `WorldView` and answers with a `Decision`, the tank
+//! systems turn, move and fire for it. Which controller an AI tank gets is picked by the
+//! `Controllers` resource when the tank spawns, e.g. to use a scripted controller on stage 5:
+//!
+//! ```no_run
+//! # use battle_city::tank::controller::{ClassicController, Controllers, RandomController};
+//! let controllers = Controllers {
+//!     select: Box::new(|stage, _level| match stage {
+//!         5 => Box::new(RandomController),
+//!         _ => Box::new(ClassicController::default()),
+//!     }),
+//! };
+//! ```
+
+use std::{io, time::Duration};
+
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::{
+    collision::{collide, collide_tanks, Collider},
+    config::Difficulty,
+    consts::{BATTLE_FIELD_WIDTH, BLOCK_WIDTH},
+    pathfinding::{self, Node, PathGrid},
+    save::{Reader, Writer},
+    simulation::{GameRng, TICK},
+    spatial::SpatialGrid,
+    tank::{TANK_SIZE, TANK_SPEED},
+    utils::{Direction, AI},
+};
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Action {
+    MoveForward,
+    TurnLeft,
+    TurnRight,
+    TurnAround,
+    Stay,
+}
+
+impl Action {
+    /// The action turning a tank facing `current` to `target`, or moving forward if it already
+    /// faces it
+    pub fn toward(current: Direction, target: Direction) -> Self {
+        let mut left = current;
+        left.turn_left();
+        let mut right = current;
+        right.turn_right();
+        if target == current {
+            Action::MoveForward
+        } else if target == left {
+            Action::TurnLeft
+        } else if target == right {
+            Action::TurnRight
+        } else {
+            Action::TurnAround
+        }
+    }
+
+    /// The direction a tank facing `current` faces after the action
+    pub fn apply(self, current: Direction) -> Direction {
+        let mut direction = current;
+        match self {
+            Action::MoveForward | Action::Stay => (),
+            Action::TurnLeft => direction.turn_left(),
+            Action::TurnRight => direction.turn_right(),
+            Action::TurnAround => direction.turn_around(),
+        }
+        direction
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Decision {
+    pub action: Action,
+    pub fire: bool,
+}
+
+impl Decision {
+    pub fn random(rng: &mut impl Rng) -> Self {
+        let action = match rng.gen_range(0..4) {
+            0 => Action::MoveForward,
+            1 => Action::TurnLeft,
+            2 => Action::TurnRight,
+            3 => Action::TurnAround,
+            _ => unreachable!(),
+        };
+        let fire = rng.gen_range(0..2) == 0;
+        Self { action, fire }
+    }
+}
+
+/// How AI tanks behave, shared by every AI tank on battle field. Presets of every difficulty
+/// are in `Difficulty::ai_settings`.
+pub struct AiSettings {
+    pub base_bias: f64, // chance of heading for the base when choosing a new direction
+    pub player_bias: f64, // chance of heading for the closest player instead
+    pub reaction_time: Duration, // before turning when blocked, and between looking for players
+    pub aim_accuracy: f64, // chance of turning to a player in line of fire
+    pub fire_cooldown: Duration,
+    pub drive_time: (Duration, Duration), // min and max time of driving straight
+    pub spawn_interval: Duration,
+    pub armour_ratio: f64, // chance of a new tank being an armoured one
+    pub bonus_ratio: f64,  // chance of a new tank dropping a power up when destroyed
+}
+
+impl Default for AiSettings {
+    fn default() -> Self {
+        Difficulty::Normal.ai_settings()
+    }
+}
+
+/// What a controller knows about the world during a tick
+pub struct WorldView<'a> {
+    pub translation: Vec3,
+    pub direction: Direction,
+    pub level: u8,
+    pub amphibious: bool,
+    pub blocked_by: Option<Collider>, // what the tank is touching in front of it
+    pub base: Option<Vec3>,
+    pub enemies: &'a [Vec3], // tanks it fights against
+    pub allies: &'a [Vec3],  // other tanks on its side
+    pub power_ups: &'a [Vec3],
+    pub grid: &'a PathGrid,
+    pub settings: &'a AiSettings,
+}
+
+impl WorldView<'_> {
+    pub fn node(&self) -> Node {
+        pathfinding::node(self.translation)
+    }
+
+    pub fn closest_enemy(&self) -> Option<Vec3> {
+        self.enemies.iter().copied().min_by(|a, b| {
+            let a = a.distance_squared(self.translation);
+            let b = b.distance_squared(self.translation);
+            a.partial_cmp(&b).unwrap()
+        })
+    }
+
+    /// Bullets of this tank break iron
+    pub fn breaks_iron(&self) -> bool {
+        self.level > 2
+    }
+}
+
+/// Everything on battle field controllers look at, gathered once per tick
+pub struct Surroundings {
+    pub base: Option<Vec3>,
+    players: Vec<(Entity, Vec3)>,
+    ai_tanks: Vec<(Entity, Vec3)>,
+    pub power_ups: Vec<Vec3>,
+}
+
+impl Surroundings {
+    pub fn new<'a>(
+        colliders: impl Iterator<Item = (Entity, &'a Collider, &'a Transform, Option<&'a AI>)>,
+    ) -> Self {
+        let mut surroundings = Self {
+            base: None,
+            players: Vec::new(),
+            ai_tanks: Vec::new(),
+            power_ups: Vec::new(),
+        };
+        for (entity, collider, transform, ai) in colliders {
+            let translation = transform.translation;
+            match collider {
+                Collider::Base => surroundings.base = Some(translation),
+                Collider::PowerUp => surroundings.power_ups.push(translation),
+                Collider::Tank if ai.is_some() => surroundings.ai_tanks.push((entity, translation)),
+                Collider::Tank => surroundings.players.push((entity, translation)),
+                _ => (),
+            }
+        }
+        surroundings
+    }
+
+    /// Players' tanks, except the given one
+    pub fn players(&self, except: Option<Entity>) -> Vec<Vec3> {
+        positions(&self.players, except)
+    }
+
+    /// AI tanks, except the given one
+    pub fn ai_tanks(&self, except: Option<Entity>) -> Vec<Vec3> {
+        positions(&self.ai_tanks, except)
+    }
+}
+
+/// Distance to the closest obstacle in front of a tank, and the obstacle if the tank touches it
+pub fn blocker(
+    grid: &SpatialGrid,
+    tank: Entity,
+    translation: Vec3,
+    direction: Direction,
+    amphibious: bool,
+) -> (f32, Option<Collider>) {
+    let mut min_distance = BATTLE_FIELD_WIDTH; // a large float number
+    let mut blocker = None;
+    for shape in grid.ahead(translation, TANK_SIZE, direction, TANK_SPEED) {
+        if tank == shape.entity {
+            continue;
+        }
+        let distance = match shape.collider {
+            Collider::Grass | Collider::Snow | Collider::Bullet | Collider::PowerUp => continue,
+            Collider::River if amphibious => continue,
+            Collider::Tank => collide_tanks(translation, shape.translation, TANK_SIZE, &direction),
+            _ => collide(
+                translation,
+                TANK_SIZE,
+                shape.translation,
+                shape.size,
+                &direction,
+            ),
+        };
+        if let Some(distance) = distance {
+            if distance < min_distance {
+                min_distance = distance;
+                blocker = Some(shape.collider);
+            }
+        }
+    }
+    if min_distance > 0. {
+        blocker = None;
+    }
+    (min_distance, blocker)
+}
+
+fn positions(tanks: &[(Entity, Vec3)], except: Option<Entity>) -> Vec<Vec3> {
+    tanks
+        .iter()
+        .filter(|(entity, _)| Some(*entity) != except)
+        .map(|(_, translation)| *translation)
+        .collect()
+}
+
+pub trait TankController: Send + Sync {
+    /// Called once per tick
+    fn decide(&mut self, view: &WorldView, rng: &mut GameRng) -> Decision;
+
+    /// A copy with the same state, so a game can be rewound, e.g. `Box::new(self.clone())`
+    fn clone_box(&self) -> Box<dyn TankController>;
+
+    /// Writes the state into a saved game, nothing for a controller without state
+    fn save(&self, _w: &mut Writer) {}
+
+    /// Reads the state `save` wrote, so the tank goes on the same when the game resumes
+    fn load(&mut self, _r: &mut Reader) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Controller of an AI tank
+#[derive(Component)]
+pub struct Controller(pub Box<dyn TankController>);
+
+impl Clone for Controller {
+    fn clone(&self) -> Self {
+        Self(self.0.clone_box())
+    }
+}
+
+/// Picks the controller of an AI tank by stage and tank level when it spawns
+pub struct Controllers {
+    pub select: Box<dyn Fn(u8, u8) -> Box<dyn TankController> + Send + Sync>,
+}
+
+impl Default for Controllers {
+    fn default() -> Self {
+        Self {
+            select: Box::new(|_, _| Box::new(ClassicController::default())),
+        }
+    }
+}
+
+/// Does anything at any tick
+#[derive(Clone)]
+pub struct RandomController;
+
+impl TankController for RandomController {
+    fn decide(&mut self, _view: &WorldView, rng: &mut GameRng) -> Decision {
+        Decision::random(rng)
+    }
+
+    fn clone_box(&self) -> Box<dyn TankController> {
+        Box::new(self.clone())
+    }
+}
+
+/// NES style AI: drive straight until blocked or for a while, then turn, often following a path
+/// to the base. Fires on a cooldown.
+#[derive(Clone)]
+pub struct ClassicController {
+    drive: Timer,  // choose a new direction when finished
+    reload: Timer, // can fire when finished
+    aim: Timer,    // look for players in line of fire when finished
+    blocked_time: Duration,
+    route: Option<Route>,
+}
+
+impl Default for ClassicController {
+    fn default() -> Self {
+        // choose a direction and fire as soon as the tank appears
+        Self {
+            drive: Timer::new(Duration::ZERO, false),
+            reload: Timer::new(Duration::ZERO, false),
+            aim: Timer::new(Duration::ZERO, false),
+            blocked_time: Duration::ZERO,
+            route: None,
+        }
+    }
+}
+
+impl TankController for ClassicController {
+    fn decide(&mut self, view: &WorldView, rng: &mut GameRng) -> Decision {
+        self.drive.tick(TICK);
+        self.reload.tick(TICK);
+        self.aim.tick(TICK);
+        let settings = view.settings;
+        let blocked = view.blocked_by.is_some();
+        if blocked {
+            self.blocked_time += TICK;
+        } else {
+            self.blocked_time = Duration::ZERO;
+        }
+
+        // keep the route for a while shooting through what blocks it, otherwise find another way
+        if blocked {
+            let destructible = match view.blocked_by {
+                Some(Collider::Brick) | Some(Collider::Base) => true,
+                Some(Collider::Iron) => view.breaks_iron(),
+                _ => false,
+            };
+            if !destructible || self.drive.finished() {
+                self.route = None;
+            }
+        }
+        if let Some(route) = self.route.as_mut() {
+            if route.version != view.grid.version() {
+                match find_route(view, route.target) {
+                    Some(new_route) => *route = new_route,
+                    None => self.route = None,
+                }
+            }
+        }
+        let mut next = self
+            .route
+            .as_mut()
+            .and_then(|route| route.next(view.translation));
+        if next.is_none() {
+            self.route = None; // arrived
+        }
+
+        if self.aim.finished() {
+            self.aim = Timer::new(settings.reaction_time, false);
+            let in_line = view
+                .enemies
+                .iter()
+                .find_map(|enemy| aim(view.translation, *enemy));
+            if let Some(direction) = in_line {
+                if rng.gen_bool(settings.aim_accuracy) {
+                    self.route = None;
+                    next = Some(direction);
+                    let (min, max) = settings.drive_time;
+                    self.drive = Timer::new(rng.gen_range(min..=max), false);
+                }
+            }
+        }
+
+        let react = blocked && self.blocked_time >= settings.reaction_time;
+        if next.is_none() && (react || self.drive.finished()) {
+            let avoid = if blocked { Some(view.direction) } else { None };
+            let roll = rng.gen::<f64>();
+            let target = if roll < settings.player_bias {
+                view.closest_enemy()
+            } else if roll < settings.player_bias + settings.base_bias {
+                view.base
+            } else {
+                None
+            };
+            if let Some(target) = target {
+                if let Some(mut route) = find_route(view, pathfinding::node(target)) {
+                    next = route.next(view.translation);
+                    self.route = Some(route);
+                }
+            }
+            next = next
+                .or_else(|| target.and_then(|target| toward(view.translation, target, avoid)))
+                .or_else(|| Some(random_direction(rng, avoid)));
+            let (min, max) = settings.drive_time;
+            self.drive = Timer::new(rng.gen_range(min..=max), false);
+        }
+
+        let fire = self.reload.finished();
+        if fire {
+            // a little randomness, so AI tanks do not fire all together
+            let cooldown = settings.fire_cooldown.mul_f64(rng.gen_range(0.75..1.25));
+            self.reload = Timer::new(cooldown, false);
+        }
+        Decision {
+            action: Action::toward(view.direction, next.unwrap_or(view.direction)),
+            fire,
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn TankController> {
+        Box::new(self.clone())
+    }
+
+    fn save(&self, w: &mut Writer) {
+        w.timer(&self.drive);
+        w.timer(&self.reload);
+        w.timer(&self.aim);
+        w.duration(self.blocked_time);
+        Route::save(&self.route, w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> io::Result<()> {
+        self.drive = r.timer()?;
+        self.reload = r.timer()?;
+        self.aim = r.timer()?;
+        self.blocked_time = r.duration()?;
+        self.route = Route::load(r)?;
+        Ok(())
+    }
+}
+
+const ALLY_FIRE_COOLDOWN: Duration = Duration::from_millis(300);
+const ALLY_PLAN_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Friendly AI driving a player's tank: picks up power ups, hunts the enemy closest to the base
+/// and guards the base otherwise. It never fires toward an ally or the base.
+#[derive(Clone)]
+pub struct AllyController {
+    plan: Timer,   // choose a new goal when finished
+    reload: Timer, // can fire when finished
+    route: Option<Route>,
+}
+
+impl Default for AllyController {
+    fn default() -> Self {
+        Self {
+            plan: Timer::new(Duration::ZERO, false),
+            reload: Timer::new(Duration::ZERO, false),
+            route: None,
+        }
+    }
+}
+
+impl AllyController {
+    fn can_fire(&mut self, view: &WorldView, direction: Direction) -> bool {
+        let safe = view
+            .allies
+            .iter()
+            .chain(view.base.iter())
+            .all(|friend| aim(view.translation, *friend) != Some(direction));
+        if safe && direction == view.direction && self.reload.finished() {
+            self.reload = Timer::new(ALLY_FIRE_COOLDOWN, false);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn goal(view: &WorldView) -> Option<Vec3> {
+        let closest = |positions: &[Vec3], to: Vec3| {
+            positions.iter().copied().min_by(|a, b| {
+                let a = a.distance_squared(to);
+                let b = b.distance_squared(to);
+                a.partial_cmp(&b).unwrap()
+            })
+        };
+        let guard = view.base.map(|base| base + Vec3::Y * 2.5 * BLOCK_WIDTH);
+        closest(view.power_ups, view.translation)
+            .or_else(|| closest(view.enemies, view.base.unwrap_or(view.translation)))
+            .or(guard)
+    }
+}
+
+impl TankController for AllyController {
+    fn decide(&mut self, view: &WorldView, _rng: &mut GameRng) -> Decision {
+        self.plan.tick(TICK);
+        self.reload.tick(TICK);
+
+        // an enemy in line of fire comes first
+        let in_line = view
+            .enemies
+            .iter()
+            .find_map(|enemy| aim(view.translation, *enemy));
+        if let Some(direction) = in_line {
+            return Decision {
+                action: Action::toward(view.direction, direction),
+                fire: self.can_fire(view, direction),
+            };
+        }
+
+        let outdated = self
+            .route
+            .as_ref()
+            .map_or(false, |route| route.version != view.grid.version());
+        if self.plan.finished() || outdated {
+            self.plan = Timer::new(ALLY_PLAN_INTERVAL, false);
+            self.route =
+                Self::goal(view).and_then(|goal| find_route(view, pathfinding::node(goal)));
+        }
+        let next = self
+            .route
+            .as_mut()
+            .and_then(|route| route.next(view.translation));
+        match next {
+            Some(direction) => {
+                // shoot through bricks on the way
+                let fire = matches!(view.blocked_by, Some(Collider::Brick))
+                    && self.can_fire(view, direction);
+                Decision {
+                    action: Action::toward(view.direction, direction),
+                    fire,
+                }
+            }
+            None => {
+                self.route = None;
+                Decision {
+                    action: Action::Stay,
+                    fire: false,
+                }
+            }
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn TankController> {
+        Box::new(self.clone())
+    }
+
+    fn save(&self, w: &mut Writer) {
+        w.timer(&self.plan);
+        w.timer(&self.reload);
+        Route::save(&self.route, w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> io::Result<()> {
+        self.plan = r.timer()?;
+        self.reload = r.timer()?;
+        self.route = Route::load(r)?;
+        Ok(())
+    }
+}
+
+/// A path the tank is following
+#[derive(Clone)]
+struct Route {
+    target: Node,
+    nodes: Vec<Node>, // reversed, the next node is the last one
+    version: u64,     // version of the path grid it was found on
+}
+
+impl Route {
+    /// Direction to the next node, None if the route is finished
+    fn next(&mut self, translation: Vec3) -> Option<Direction> {
+        while let Some(&node) = self.nodes.last() {
+            let center = pathfinding::node_translation(node, translation.z);
+            let delta = (center - translation).truncate().abs();
+            // a tank moves TANK_SPEED per tick, so it always passes this close to the center
+            if delta.x < TANK_SPEED && delta.y < TANK_SPEED {
+                self.nodes.pop();
+                continue;
+            }
+            return Some(pathfinding::direction(translation, center));
+        }
+        None
+    }
+
+    /// If there is a route, then its target, version and nodes
+    fn save(route: &Option<Route>, w: &mut Writer) {
+        w.bool(route.is_some());
+        if let Some(route) = route {
+            w.node(route.target);
+            w.u64(route.version);
+            w.u32(route.nodes.len() as u32);
+            for node in &route.nodes {
+                w.node(*node);
+            }
+        }
+    }
+
+    fn load(r: &mut Reader) -> io::Result<Option<Route>> {
+        if !r.bool()? {
+            return Ok(None);
+        }
+        let target = r.node()?;
+        let version = r.u64()?;
+        let mut nodes = Vec::new();
+        for _ in 0..r.u32()? {
+            nodes.push(r.node()?);
+        }
+        Ok(Some(Route {
+            target,
+            nodes,
+            version,
+        }))
+    }
+}
+
+fn find_route(view: &WorldView, target: Node) -> Option<Route> {
+    let mut nodes =
+        view.grid
+            .find_path(view.node(), target, view.amphibious, view.breaks_iron())?;
+    nodes.reverse();
+    Some(Route {
+        target,
+        nodes,
+        version: view.grid.version(),
+    })
+}
+
+/// Direction to fire at a tank in the same row or column
+fn aim(from: Vec3, to: Vec3) -> Option<Direction> {
+    let delta = to - from;
+    let tolerance = TANK_SIZE.x / 2.;
+    if delta.x.abs() < tolerance {
+        Some(if delta.y > 0. {
+            Direction::Up
+        } else {
+            Direction::Down
+        })
+    } else if delta.y.abs() < tolerance {
+        Some(if delta.x > 0. {
+            Direction::Right
+        } else {
+            Direction::Left
+        })
+    } else {
+        None
+    }
+}
+
+/// Picks a direction toward the target along the longer axis, falls back to the other axis if
+/// the longer one is `avoid`
+fn toward(from: Vec3, to: Vec3, avoid: Option<Direction>) -> Option<Direction> {
+    let delta = to - from;
+    let horizontal = if delta.x > 0. {
+        Direction::Right
+    } else {
+        Direction::Left
+    };
+    let vertical = if delta.y > 0. {
+        Direction::Up
+    } else {
+        Direction::Down
+    };
+    let (first, second) = if delta.x.abs() > delta.y.abs() {
+        (horizontal, vertical)
+    } else {
+        (vertical, horizontal)
+    };
+    [first, second]
+        .into_iter()
+        .find(|direction| Some(*direction) != avoid)
+}
+
+fn random_direction(rng: &mut impl Rng, avoid: Option<Direction>) -> Direction {
+    let directions = [
+        Direction::Up,
+        Direction::Right,
+        Direction::Down,
+        Direction::Left,
+    ]
+    .into_iter()
+    .filter(|direction| Some(*direction) != avoid)
+    .collect::<Vec<_>>();
+    directions[rng.gen_range(0..directions.len())]
+}
+
+#[test]
+fn test_action_toward() {
+    for current in [
+        Direction::Up,
+        Direction::Right,
+        Direction::Down,
+        Direction::Left,
+    ] {
+        for target in [
+            Direction::Up,
+            Direction::Right,
+            Direction::Down,
+            Direction::Left,
+        ] {
+            assert_eq!(Action::toward(current, target).apply(current), target);
+        }
+    }
+}
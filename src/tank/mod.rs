@@ -7,18 +7,52 @@ use bevy::{
 
 use crate::{
     consts::{BLOCK_WIDTH, HALF_BLOCK_WIDTH, MIN_BLOCK_WIDTH, HALF_MIN_BLOCK_WIDTH},
+    event,
+    menu::AppState,
+    pathfinding::{self, PathGrid},
+    shield,
+    simulation::AddSimulationSystem,
     utils::{Direction, Owner},
     star,
 };
 
+use controller::{AiSettings, Controllers};
+
 pub mod ai;
-pub mod p1;
+pub mod controller;
 pub mod p2;
+pub mod player;
+
+/// Spawning, moving, animating and firing of players' and AI's tanks
+pub struct TankPlugin;
+
+impl Plugin for TankPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AiSettings>()
+            .init_resource::<Controllers>()
+            .init_resource::<ai::EnemySpawner>()
+            .init_resource::<PathGrid>()
+            .add_event::<event::TankDestroyed>()
+            .add_system_set(
+                SystemSet::on_enter(AppState::Playing).with_system(ai::apply_difficulty),
+            )
+            .add_simulation_system(event::handle_tank_destroyed)
+            .add_simulation_system(ai::spawner)
+            .add_simulation_system(star::twinkling)
+            .add_simulation_system(player::movement)
+            .add_simulation_system(player::animation)
+            .add_simulation_system(player::firing)
+            .add_simulation_system(pathfinding::update)
+            .add_simulation_system(ai::movement)
+            .add_simulation_system(ai::animation)
+            .add_simulation_system(shield::animation);
+    }
+}
 
-#[derive(Component)]
+#[derive(Component, Clone)]
 pub struct Tank;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Data {
     pub direction: Direction,
     pub owner: Owner,
@@ -26,6 +60,7 @@ pub struct Data {
     pub shield: bool,
     pub amphibious: bool,
     pub base_sprite: usize,
+    pub bonus: bool, // drops a power up when destroyed
 }
 
 impl Data {
@@ -48,6 +83,7 @@ impl Default for Data {
             shield: false,
             amphibious: false,
             base_sprite: 0,
+            bonus: false,
         }
     }
 }
@@ -55,10 +91,8 @@ impl Default for Data {
 pub const TANK_SPEED: f32 = HALF_BLOCK_WIDTH / 8.;
 pub const TANK_SIZE: Vec2 = const_vec2!([BLOCK_WIDTH, BLOCK_WIDTH]);
 pub const MAX_LEVEL: u8 = 4;
-#[derive(Component)]
-pub struct AnimationTimer(Timer);
-#[derive(Component)]
-pub struct MovementTimer(Timer);
+#[derive(Component, Clone)]
+pub struct AnimationTimer(pub Timer);
 
 // calculate tank's x or y when a tank turns left or right
 pub fn cal_position(tank_pos: Vec3, new_direction: Direction) -> f32 {
@@ -89,5 +123,5 @@ pub fn cal_position(tank_pos: Vec3, new_direction: Direction) -> f32 {
 
 // Actually, this spawn spawns a star. After start finishes twikling, a tank will be spawned.
 pub fn spawn(commands: &mut Commands, texture: Handle<TextureAtlas>, position: Vec3, owner: Owner, level: u8) {
-    star::spawn(commands, texture, position, owner, level);
+    star::spawn(commands, texture, position, owner, level, false);
 }
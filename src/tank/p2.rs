@@ -1,172 +1,68 @@
-use bevy::{math::const_vec3, prelude::*};
+use bevy::prelude::*;
 
 use crate::{
-    collision::{collide, Collider},
-    consts::{BATTLE_FIELD_WIDTH, BLOCK_WIDTH, SCALE},
+    collision::Collider,
+    input::{PlayerInputs, TankInput},
+    pathfinding::PathGrid,
+    simulation::GameRng,
+    spatial::SpatialGrid,
     state,
-    tank::{Data, Tank, TANK_SIZE, TANK_SPEED},
-    utils::{Direction, Owner, P2},
+    tank::{
+        controller::{
+            blocker, Action, AiSettings, AllyController, Surroundings, TankController, WorldView,
+        },
+        Tank,
+    },
+    utils::{Player, AI},
 };
 
-pub const DIRECTION_KEYS: [KeyCode; 4] =
-    [KeyCode::Up, KeyCode::Right, KeyCode::Down, KeyCode::Left];
-pub const SPAWN_POSITION: Vec3 = const_vec3!([
-    1.5 * BLOCK_WIDTH,
-    (BLOCK_WIDTH - BATTLE_FIELD_WIDTH) / 2.,
-    0.
-]);
+const PLAYER: u8 = 1; // P2's ID
 
-pub fn spawn(commands: &mut Commands, texture: Handle<TextureAtlas>) {
-    commands
-        .spawn_bundle(SpriteSheetBundle {
-            sprite: TextureAtlasSprite::new(128),
-            texture_atlas: texture,
-            transform: Transform {
-                translation: SPAWN_POSITION,
-                scale: Vec3::splat(SCALE),
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(Tank)
-        .insert(P2)
-        .insert(Collider::Tank)
-        .insert(Timer::from_seconds(0.1, true))
-        .insert(state::State::Tank(Data {
-            owner: Owner::P2,
-            base_sprite: 128,
-            ..Default::default()
-        }));
-}
+/// The friendly AI driving P2 when there is no second player
+#[derive(Default, Clone)]
+pub struct Ally(pub AllyController);
 
-pub fn animation(
-    time: Res<Time>,
-    keyboard_input: Res<Input<KeyCode>>,
-    mut query: Query<(&mut Timer, &mut TextureAtlasSprite, &state::State), With<P2>>,
+/// Presses P2's buttons as the ally decides, so P2 moves and fires exactly as a human would
+pub fn ally(
+    settings: Res<AiSettings>,
+    grid: Res<PathGrid>,
+    spatial: Res<SpatialGrid>,
+    mut ally: ResMut<Ally>,
+    mut rng: ResMut<GameRng>,
+    mut inputs: ResMut<PlayerInputs>,
+    players: Query<(Entity, &Transform, &state::State, &Player), With<Tank>>,
+    colliders: Query<(Entity, &Collider, &Transform, Option<&AI>)>,
 ) {
-    let result = query.iter_mut().next();
-    if result.is_none() {
-        return;
-    }
-    let (mut timer, mut sprite, state) = result.unwrap();
-    let tank = state.as_tank();
-
-    let moving = match tank.direction {
-        Direction::Up => keyboard_input.pressed(DIRECTION_KEYS[0]),
-        Direction::Right => keyboard_input.pressed(DIRECTION_KEYS[1]),
-        Direction::Down => keyboard_input.pressed(DIRECTION_KEYS[2]),
-        Direction::Left => keyboard_input.pressed(DIRECTION_KEYS[3]),
-    };
-
-    if !moving {
-        return;
-    }
-
-    if timer.tick(time.delta()).just_finished() {
-        if sprite.index % 2 == 0 {
-            sprite.index += 1;
-        } else {
-            sprite.index -= 1;
-        }
-    }
-}
-
-/// Movement system
-pub fn movement(
-    keyboard_input: Res<Input<KeyCode>>,
-    mut tank: Query<(&mut Transform, &mut TextureAtlasSprite, &mut state::State), With<P2>>,
-    obstacles: Query<(&Collider, &Transform, Option<&Sprite>), Without<P2>>,
-) {
-    let result = tank.iter_mut().next();
-    if result.is_none() {
-        return;
-    }
-    let (mut t_transform, mut t_sprite, mut state) = result.unwrap();
-    let tank = state.as_mut_tank();
-    if keyboard_input.just_pressed(DIRECTION_KEYS[0]) && tank.direction != Direction::Up {
-        t_sprite.index = 128;
-        tank.direction = Direction::Up;
-        return;
-    }
-    if keyboard_input.just_pressed(DIRECTION_KEYS[1]) && tank.direction != Direction::Right {
-        t_sprite.index = 134;
-        tank.direction = Direction::Right;
-        return;
-    }
-    if keyboard_input.just_pressed(DIRECTION_KEYS[2]) && tank.direction != Direction::Down {
-        t_sprite.index = 132;
-        tank.direction = Direction::Down;
-        return;
-    }
-    if keyboard_input.just_pressed(DIRECTION_KEYS[3]) && tank.direction != Direction::Left {
-        t_sprite.index = 130;
-        tank.direction = Direction::Left;
-        return;
-    }
-
-    match tank.direction {
-        Direction::Up => {
-            if !keyboard_input.pressed(DIRECTION_KEYS[0]) {
-                return;
-            }
-        }
-        Direction::Right => {
-            if !keyboard_input.pressed(DIRECTION_KEYS[1]) {
-                return;
-            }
-        }
-        Direction::Down => {
-            if !keyboard_input.pressed(DIRECTION_KEYS[2]) {
-                return;
-            }
-        }
-        Direction::Left => {
-            if !keyboard_input.pressed(DIRECTION_KEYS[3]) {
-                return;
-            }
-        }
-    }
-
-    let mut size;
-    let mut min_distance = BATTLE_FIELD_WIDTH; // a large float number
-    for (collider, transform, sprite) in obstacles.iter() {
-        match collider {
-            Collider::Grass | Collider::Snow | Collider::Bullet => continue,
-            Collider::Tank => {
-                size = TANK_SIZE;
-            }
-            _ => match sprite {
-                Some(s) => size = s.custom_size.unwrap(),
-                None => {
-                    println!("Collider {:?} does not have size", collider);
-                    continue;
-                }
-            },
-        }
-        match collide(
-            t_transform.translation,
-            TANK_SIZE,
+    let mut input = TankInput::default();
+    let p2 = players.iter().find(|(_, _, _, player)| player.0 == PLAYER);
+    if let Some((entity, transform, state, _)) = p2 {
+        let tank = state.as_tank();
+        let surroundings = Surroundings::new(colliders.iter());
+        let (_, blocked_by) = blocker(
+            &spatial,
+            entity,
             transform.translation,
-            size,
-            &tank.direction,
-        ) {
-            None => continue,
-            Some(distance) => {
-                if distance <= 0. {
-                    // tank is at the edge of an obstacle, shall not move forward
-                    return;
-                }
-                if distance < min_distance {
-                    min_distance = distance;
-                }
-            }
+            tank.direction,
+            tank.amphibious,
+        );
+        let view = WorldView {
+            translation: transform.translation,
+            direction: tank.direction,
+            level: tank.level,
+            amphibious: tank.amphibious,
+            blocked_by,
+            base: surroundings.base,
+            enemies: &surroundings.ai_tanks(None),
+            allies: &surroundings.players(Some(entity)),
+            power_ups: &surroundings.power_ups,
+            grid: &grid,
+            settings: &settings,
+        };
+        let decision = ally.0.decide(&view, &mut rng);
+        if decision.action != Action::Stay {
+            input.press(decision.action.apply(tank.direction));
         }
+        input.set(TankInput::FIRE, decision.fire);
     }
-    let move_distance = min_distance.min(TANK_SPEED);
-    match tank.direction {
-        Direction::Up => t_transform.translation.y += move_distance,
-        Direction::Right => t_transform.translation.x += move_distance,
-        Direction::Down => t_transform.translation.y -= move_distance,
-        Direction::Left => t_transform.translation.x -= move_distance,
-    }
+    inputs.set_current(PLAYER as usize, input);
 }
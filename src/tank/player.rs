@@ -0,0 +1,399 @@
+use bevy::{math::const_vec3, prelude::*, sprite::collide_aabb};
+
+use crate::{
+    base::wall::ChangeBaseWall,
+    bullet,
+    collision::{collide, collide_tanks, Collider, Hitbox},
+    consts::{BATTLE_FIELD_WIDTH, BLOCK_WIDTH, SCALE},
+    event,
+    game_data::GameData,
+    input::PlayerInputs,
+    power_up::PowerType,
+    shield,
+    simulation::TICK,
+    spatial::SpatialGrid,
+    state,
+    tank::{cal_position, AnimationTimer, Data, Tank, MAX_LEVEL, TANK_SIZE, TANK_SPEED},
+    texture::Textures,
+    utils::{get_sprite, Direction, Owner, Player, MAX_PLAYERS, PLAYER_COLORS},
+};
+
+/// Players' keys in the order of up, right, down, left, gamepad N drives player N as well
+pub const DIRECTION_KEYS: [[KeyCode; 4]; MAX_PLAYERS] = [
+    [KeyCode::W, KeyCode::D, KeyCode::S, KeyCode::A],
+    [KeyCode::Up, KeyCode::Right, KeyCode::Down, KeyCode::Left],
+    [
+        KeyCode::Numpad8,
+        KeyCode::Numpad6,
+        KeyCode::Numpad5,
+        KeyCode::Numpad4,
+    ],
+    [
+        KeyCode::Home,
+        KeyCode::PageDown,
+        KeyCode::End,
+        KeyCode::Delete,
+    ],
+];
+pub const FIRE_KEYS: [KeyCode; MAX_PLAYERS] = [
+    KeyCode::J,
+    KeyCode::RControl,
+    KeyCode::Numpad0,
+    KeyCode::Insert,
+];
+/// Next to the base, P1 and P2 the closest
+pub const SPAWN_POSITIONS: [Vec3; MAX_PLAYERS] = [
+    const_vec3!([
+        -2.5 * BLOCK_WIDTH,
+        (BLOCK_WIDTH - BATTLE_FIELD_WIDTH) / 2.,
+        0.
+    ]),
+    const_vec3!([
+        1.5 * BLOCK_WIDTH,
+        (BLOCK_WIDTH - BATTLE_FIELD_WIDTH) / 2.,
+        0.
+    ]),
+    const_vec3!([
+        -4.5 * BLOCK_WIDTH,
+        (BLOCK_WIDTH - BATTLE_FIELD_WIDTH) / 2.,
+        0.
+    ]),
+    const_vec3!([
+        3.5 * BLOCK_WIDTH,
+        (BLOCK_WIDTH - BATTLE_FIELD_WIDTH) / 2.,
+        0.
+    ]),
+];
+
+/// the real function that spawns a player's tank after star is despawned
+pub fn spawn(
+    commands: &mut Commands,
+    texture: Handle<TextureAtlas>,
+    position: Vec3,
+    owner: Owner,
+) -> Entity {
+    let player = owner.player.expect("a player's tank");
+    let base_sprite = get_sprite(owner, 0, Direction::Up);
+    commands
+        .spawn_bundle(SpriteSheetBundle {
+            sprite: TextureAtlasSprite {
+                color: PLAYER_COLORS[player as usize],
+                ..TextureAtlasSprite::new(base_sprite)
+            },
+            texture_atlas: texture,
+            transform: Transform {
+                translation: position,
+                scale: Vec3::splat(SCALE),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Tank)
+        .insert(Player(player))
+        .insert(Collider::Tank)
+        .insert(Hitbox(TANK_SIZE))
+        .insert(AnimationTimer(Timer::from_seconds(0.1, true)))
+        .insert(state::State::Tank(Data {
+            owner,
+            base_sprite,
+            ..Default::default()
+        }))
+        .id()
+}
+
+/// Animation systems
+pub fn animation(
+    inputs: Res<PlayerInputs>,
+    mut query: Query<
+        (
+            &mut AnimationTimer,
+            &mut TextureAtlasSprite,
+            &state::State,
+            &Player,
+        ),
+        With<Tank>,
+    >,
+) {
+    for (mut timer, mut sprite, state, player) in query.iter_mut() {
+        let tank = state.as_tank();
+        if !inputs.pressed(player.0 as usize, tank.direction) {
+            continue;
+        }
+
+        if timer.0.tick(TICK).finished() {
+            if sprite.index % 2 == 0 {
+                sprite.index += 1;
+            } else {
+                sprite.index -= 1;
+            }
+        }
+    }
+}
+
+/// Movement system
+pub fn movement(
+    mut commands: Commands,
+    inputs: Res<PlayerInputs>,
+    textures: Res<Textures>,
+    mut game_data: ResMut<GameData>,
+    mut tanks: Query<
+        (
+            Entity,
+            &mut Transform,
+            &mut TextureAtlasSprite,
+            &mut state::State,
+            &Player,
+        ),
+        (With<Player>, With<Tank>),
+    >,
+    mut grid: ResMut<SpatialGrid>,
+    power_ups: Query<&state::State, Without<Player>>,
+    mut dae_events: EventWriter<event::DestroyAllEnemies>,
+    mut cbw_events: EventWriter<ChangeBaseWall>,
+) {
+    let texture = &textures.texture;
+    'tanks: for (t_entity, mut t_transform, mut t_sprite, mut state, player) in tanks.iter_mut() {
+        let player = player.0 as usize;
+        let tank = state.as_mut_tank();
+
+        // The center of battle field is (-HALF_BLOCK_WIDTH, 0)
+        if inputs.just_pressed(player, Direction::Up) && tank.direction != Direction::Up {
+            t_sprite.index = tank.base_sprite;
+            if !tank.direction.is_opposite(Direction::Up) {
+                t_transform.translation.x = cal_position(t_transform.translation, Direction::Up);
+            }
+            tank.direction = Direction::Up;
+            grid.update(t_entity, t_transform.translation);
+            continue;
+        }
+        if inputs.just_pressed(player, Direction::Right) && tank.direction != Direction::Right {
+            t_sprite.index = tank.base_sprite + 6;
+            if !tank.direction.is_opposite(Direction::Right) {
+                t_transform.translation.y = cal_position(t_transform.translation, Direction::Right);
+            }
+            tank.direction = Direction::Right;
+            grid.update(t_entity, t_transform.translation);
+            continue;
+        }
+        if inputs.just_pressed(player, Direction::Down) && tank.direction != Direction::Down {
+            t_sprite.index = tank.base_sprite + 4;
+            if !tank.direction.is_opposite(Direction::Down) {
+                t_transform.translation.x = cal_position(t_transform.translation, Direction::Down);
+            }
+            tank.direction = Direction::Down;
+            grid.update(t_entity, t_transform.translation);
+            continue;
+        }
+        if inputs.just_pressed(player, Direction::Left) && tank.direction != Direction::Left {
+            t_sprite.index = tank.base_sprite + 2;
+            if !tank.direction.is_opposite(Direction::Left) {
+                t_transform.translation.y = cal_position(t_transform.translation, Direction::Left);
+            }
+            tank.direction = Direction::Left;
+            grid.update(t_entity, t_transform.translation);
+            continue;
+        }
+
+        if !inputs.pressed(player, tank.direction) {
+            continue;
+        }
+
+        let mut min_distance = BATTLE_FIELD_WIDTH; // a large float number
+        for shape in grid.ahead(
+            t_transform.translation,
+            TANK_SIZE,
+            tank.direction,
+            TANK_SPEED,
+        ) {
+            let (c_entity, collider) = (shape.entity, &shape.collider);
+            if c_entity == t_entity {
+                continue;
+            }
+            match collider {
+                Collider::Grass | Collider::Snow | Collider::Bullet => continue, // hit bullet is handled in bullet.rs
+                Collider::PowerUp => {
+                    match collide_aabb::collide(
+                        t_transform.translation,
+                        TANK_SIZE,
+                        shape.translation,
+                        TANK_SIZE, // power ups and tanks share same size. TODO: change a better name
+                    ) {
+                        None => (),
+                        Some(_) => {
+                            // out of the grid at once, another tank reaching it during this tick
+                            // does not pick it up again
+                            commands.entity(c_entity).despawn();
+                            grid.remove(c_entity);
+                            match power_ups.get(c_entity).unwrap().as_power_up() {
+                                PowerType::Helmet => {
+                                    tank.shield = true;
+                                    shield::spawn(&mut commands, t_entity, texture.clone());
+                                }
+                                PowerType::Star => {
+                                    match tank.level {
+                                        3 => tank.level += 1, // level 4 can remove grass
+                                        0 | 1 | 2 => {
+                                            tank.level += 1;
+                                            tank.base_sprite += 16;
+                                            t_sprite.index += 16;
+                                        }
+                                        _ => (),
+                                    };
+                                }
+                                PowerType::Gun => {
+                                    tank.base_sprite = get_sprite(tank.owner, 3, Direction::Up);
+                                    tank.level = MAX_LEVEL.min(tank.level + 3);
+                                    t_sprite.index = get_sprite(tank.owner, 3, tank.direction);
+                                }
+                                PowerType::Tank => {
+                                    if game_data.lifes[player] < 100 {
+                                        game_data.lifes[player] += 1;
+                                    }
+                                }
+                                PowerType::Clock => (), // TODO: freeze all ai tanks on battle field
+                                PowerType::Shovel => {
+                                    cbw_events.send(ChangeBaseWall { by: tank.owner });
+                                }
+                                PowerType::Grenade => {
+                                    dae_events.send(event::DestroyAllEnemies { by: tank.owner });
+                                }
+                            }
+                        }
+                    };
+                    continue;
+                }
+                _ => (),
+            }
+            let distance = match collider {
+                Collider::Tank => collide_tanks(
+                    t_transform.translation,
+                    shape.translation,
+                    TANK_SIZE,
+                    &tank.direction,
+                ),
+                _ => collide(
+                    t_transform.translation,
+                    TANK_SIZE,
+                    shape.translation,
+                    shape.size,
+                    &tank.direction,
+                ),
+            };
+            match distance {
+                None => continue,
+                Some(distance) => {
+                    if distance <= 0. {
+                        // tank is at the edge of an obstacle, shall not move forward
+                        continue 'tanks;
+                    }
+                    if distance < min_distance {
+                        min_distance = distance;
+                    }
+                }
+            }
+        }
+        let move_distance = min_distance.min(TANK_SPEED);
+        match tank.direction {
+            Direction::Up => t_transform.translation.y += move_distance,
+            Direction::Right => t_transform.translation.x += move_distance,
+            Direction::Down => t_transform.translation.y -= move_distance,
+            Direction::Left => t_transform.translation.x -= move_distance,
+        }
+        grid.update(t_entity, t_transform.translation);
+    }
+}
+
+pub fn firing(
+    mut commands: Commands,
+    inputs: Res<PlayerInputs>,
+    textures: Res<Textures>,
+    tanks: Query<(&Transform, &state::State, &Player), With<Tank>>, // With<Tank> to get rid of players' bullets
+) {
+    for (transform, state, player) in tanks.iter() {
+        let tank = state.as_tank();
+        if inputs.just_fired(player.0 as usize) {
+            let bullet_pos = bullet::cal_position(&transform.translation, &tank.direction);
+            bullet::spawn(
+                &mut commands,
+                textures.texture.clone(),
+                bullet_pos,
+                &tank.direction,
+                tank.owner,
+                tank.level,
+            )
+        }
+    }
+}
+
+#[test]
+fn test_power_up_picked_up_once() {
+    use crate::{
+        config::Difficulty,
+        input::{InputSource, TankInput},
+        team::{FriendlyFire, Teams},
+        BattleCityPlugins,
+    };
+
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugins(BattleCityPlugins {
+            headless: true,
+            seed: 1,
+            difficulty: Difficulty::default(),
+            input: InputSource::External,
+            ally: false,
+            save: None,
+            players: 2,
+            teams: Teams::COOP,
+            friendly_fire: FriendlyFire::Off,
+            survival: None,
+            versus: None,
+        });
+    let mut tanks = app.world.query_filtered::<(Entity, &Player), With<Tank>>();
+    while tanks.iter(&app.world).count() < 2 {
+        let mut inputs = app.world.get_resource_mut::<PlayerInputs>().unwrap();
+        inputs.push(Default::default());
+        app.update();
+    }
+
+    // P1 from below and P2 from above, too far apart to block each other, reach an extra life
+    // during the same tick
+    let position = Vec3::new(0., -5. * BLOCK_WIDTH, 0.);
+    app.world
+        .spawn()
+        .insert(Transform::from_translation(position))
+        .insert(Collider::PowerUp)
+        .insert(Hitbox(Vec2::new(BLOCK_WIDTH, BLOCK_WIDTH)))
+        .insert(state::State::PowerUp(PowerType::Tank));
+    let players: Vec<(Entity, usize)> = tanks
+        .iter(&app.world)
+        .map(|(entity, player)| (entity, player.0 as usize))
+        .collect();
+    let mut frame = [TankInput::default(); MAX_PLAYERS];
+    for (entity, player) in players {
+        let (offset, direction) = match player {
+            0 => (2. - TANK_SIZE.y, Direction::Up),
+            _ => (TANK_SIZE.y - 2., Direction::Down),
+        };
+        let mut entity = app.world.entity_mut(entity);
+        entity.get_mut::<Transform>().unwrap().translation = position + Vec3::Y * offset;
+        entity
+            .get_mut::<state::State>()
+            .unwrap()
+            .as_mut_tank()
+            .direction = direction;
+        frame[player].press(direction);
+    }
+    let lifes = app.world.get_resource::<GameData>().unwrap().lifes;
+    app.world
+        .get_resource_mut::<PlayerInputs>()
+        .unwrap()
+        .push(frame);
+    app.update();
+
+    let now = app.world.get_resource::<GameData>().unwrap().lifes;
+    let gained: u8 = (0..MAX_PLAYERS)
+        .map(|player| now[player] - lifes[player])
+        .sum();
+    assert_eq!(gained, 1);
+}
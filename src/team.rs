@@ -0,0 +1,106 @@
+//! Teams of players, and whether bullets hurt teammates.
+//!
+//! Every tank, bullet and star carries its `Owner`: a team and, for players, a player ID. Tanks of
+//! different teams are enemies, AI's tanks are a team of their own. So co-op, versus, 2v2 or
+//! free-for-all are tables of teams rather than rules of their own.
+
+use std::str::FromStr;
+
+use crate::utils::{Owner, MAX_PLAYERS};
+
+/// Each player's team, by player ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Teams(pub [u8; MAX_PLAYERS]);
+
+impl Teams {
+    /// Every player together against AI
+    pub const COOP: Teams = Teams([0, 0, 0, 0]);
+    /// P1 and P3 against P2 and P4, the two teams of a versus match
+    pub const VERSUS: Teams = Teams([0, 1, 0, 1]);
+    /// Every player on their own
+    pub const FREE_FOR_ALL: Teams = Teams([0, 1, 2, 3]);
+
+    /// Owner of a player's tanks and bullets
+    pub fn owner(self, player: u8) -> Owner {
+        Owner {
+            team: self.0[player as usize],
+            player: Some(player),
+        }
+    }
+
+    /// If every player is in P1's team or P2's team, the two teams a versus match has bases for
+    pub fn two_sided(self) -> bool {
+        self.0.iter().all(|team| *team < 2)
+    }
+}
+
+impl Default for Teams {
+    fn default() -> Self {
+        Teams::COOP
+    }
+}
+
+impl FromStr for Teams {
+    type Err = String;
+
+    /// A preset, or every player's team as a digit from P1's, e.g. "0011" for P1 and P2 against
+    /// P3 and P4
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "coop" => return Ok(Teams::COOP),
+            "versus" => return Ok(Teams::VERSUS),
+            "ffa" => return Ok(Teams::FREE_FOR_ALL),
+            _ => (),
+        }
+        let digits: Vec<u32> = s.chars().filter_map(|c| c.to_digit(10)).collect();
+        if digits.len() != MAX_PLAYERS || s.len() != MAX_PLAYERS {
+            return Err(format!("unknown teams: {}", s));
+        }
+        let mut teams = [0; MAX_PLAYERS];
+        for (team, digit) in teams.iter_mut().zip(digits) {
+            *team = digit as u8;
+        }
+        Ok(Teams(teams))
+    }
+}
+
+/// If bullets hurt the shooter's teammates, never the shooter itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FriendlyFire {
+    Off, // bullets stop at teammates, like at a wall
+    On,
+}
+
+impl FriendlyFire {
+    /// If a bullet of `by` destroys a tank of `tank`, without a shield. AI's tanks share one
+    /// owner, so they never hurt each other.
+    pub fn hurts(self, by: Owner, tank: Owner) -> bool {
+        by != tank && (by.is_enemy(tank) || self == FriendlyFire::On)
+    }
+}
+
+impl Default for FriendlyFire {
+    fn default() -> Self {
+        FriendlyFire::Off
+    }
+}
+
+#[test]
+fn test_teams() {
+    let versus = Teams::VERSUS;
+    assert!(versus.owner(0).is_enemy(versus.owner(1)));
+    assert!(!versus.owner(0).is_enemy(versus.owner(2))); // P3 is in P1's team
+    assert!(!Teams::COOP.owner(0).is_enemy(Teams::COOP.owner(1)));
+    assert!(Teams::COOP.owner(0).is_enemy(Owner::AI));
+    assert_eq!(Teams::COOP.owner(0), Owner::P1);
+
+    assert!(!FriendlyFire::Off.hurts(Teams::COOP.owner(0), Teams::COOP.owner(1)));
+    assert!(FriendlyFire::On.hurts(Teams::COOP.owner(0), Teams::COOP.owner(1)));
+    assert!(!FriendlyFire::On.hurts(Owner::AI, Owner::AI));
+    assert!(FriendlyFire::Off.hurts(Owner::AI, Teams::FREE_FOR_ALL.owner(3)));
+
+    assert_eq!("ffa".parse(), Ok(Teams::FREE_FOR_ALL));
+    assert_eq!("0011".parse(), Ok(Teams([0, 0, 1, 1])));
+    assert!("001".parse::<Teams>().is_err());
+    assert!(Teams::VERSUS.two_sided() && !Teams::FREE_FOR_ALL.two_sided());
+}
@@ -0,0 +1,220 @@
+//! Terrain of the battle field as a grid of quarter blocks.
+//!
+//! `TileMap` is where terrain lives: stages build it, bullets and the shovel change it, and path
+//! finding reads it. Terrain entities only draw the tiles and collide with tanks and bullets,
+//! they are respawned from the map whenever a tile changes.
+
+use std::collections::BTreeSet;
+
+use bevy::prelude::*;
+
+use crate::{
+    brick::{self, Cells},
+    grass, iron, river,
+    simulation::AddSimulationSystem,
+    snow,
+    texture::Textures,
+    utils::{block2translation, grid2translation, translation2grid, Direction, GRID_SIZE},
+};
+
+/// Behaviour of terrain which changes by itself, e.g. river's waves, and entities of terrain
+pub struct TerrainPlugin;
+
+impl Plugin for TerrainPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TileMap>()
+            .init_resource::<TileEntities>()
+            .add_simulation_system(river::wave)
+            .add_simulation_system(sync);
+    }
+}
+
+/// Terrain of a quarter block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tile {
+    Empty,
+    Brick(Cells), // the cells of brick left
+    Iron,
+    River,
+    Grass,
+    Snow,
+}
+
+#[derive(Clone)]
+pub struct TileMap {
+    tiles: Vec<Tile>,
+    changed: BTreeSet<(usize, usize)>, // tiles whose entities are not respawned yet
+    version: u64,                      // increased when a tile changes
+}
+
+impl Default for TileMap {
+    fn default() -> Self {
+        Self {
+            tiles: vec![Tile::Empty; GRID_SIZE * GRID_SIZE],
+            changed: BTreeSet::new(),
+            version: 0,
+        }
+    }
+}
+
+impl TileMap {
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Tile {
+        self.tiles[y * GRID_SIZE + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, tile: Tile) {
+        if self.tiles[y * GRID_SIZE + x] != tile {
+            self.tiles[y * GRID_SIZE + x] = tile;
+            self.changed.insert((x, y));
+            self.version += 1;
+        }
+    }
+
+    /// Sets the four quarters of a block, see `block2translation` for block positions
+    pub fn set_block(&mut self, block: Vec2, tile: Tile) {
+        let (x, y) = translation2grid(block2translation(block, 0.));
+        // the center of a block is the top left corner of its bottom right quarter
+        for (x, y) in [(x - 1, y - 1), (x, y - 1), (x - 1, y), (x, y)] {
+            if let Some((x, y)) = Self::in_bounds(x, y) {
+                self.set(x, y, tile);
+            }
+        }
+    }
+
+    /// The tile a translation is in, None if it is outside of battle field
+    pub fn locate(translation: Vec3) -> Option<(usize, usize)> {
+        let (x, y) = translation2grid(translation);
+        Self::in_bounds(x, y)
+    }
+
+    fn in_bounds(x: i32, y: i32) -> Option<(usize, usize)> {
+        let size = GRID_SIZE as i32;
+        if (0..size).contains(&x) && (0..size).contains(&y) {
+            Some((x as usize, y as usize))
+        } else {
+            None
+        }
+    }
+
+    /// A bullet flying in a direction hits a tile
+    pub fn hit(&mut self, x: usize, y: usize, direction: Direction, break_iron: bool) {
+        match self.get(x, y) {
+            Tile::Brick(cells) => {
+                let left = cells.hit(direction);
+                let tile = if left.is_empty() {
+                    Tile::Empty
+                } else {
+                    Tile::Brick(left)
+                };
+                self.set(x, y, tile);
+            }
+            Tile::Iron if break_iron => self.set(x, y, Tile::Empty),
+            _ => (),
+        }
+    }
+
+    /// Every tile with its position, row by row from the top left one
+    pub fn iter(&self) -> impl Iterator<Item = (usize, usize, Tile)> + '_ {
+        self.tiles
+            .iter()
+            .enumerate()
+            .map(|(i, tile)| (i % GRID_SIZE, i / GRID_SIZE, *tile))
+    }
+}
+
+/// Entities drawing the tiles
+#[derive(Clone)]
+pub struct TileEntities(Vec<Option<Entity>>);
+
+impl Default for TileEntities {
+    fn default() -> Self {
+        Self(vec![None; GRID_SIZE * GRID_SIZE])
+    }
+}
+
+/// Respawns entities of the tiles changed since the last sync
+pub fn sync(
+    mut commands: Commands,
+    textures: Res<Textures>,
+    mut tiles: ResMut<TileMap>,
+    mut entities: ResMut<TileEntities>,
+) {
+    respawn_changed(&mut commands, &textures.texture, &mut tiles, &mut entities);
+}
+
+/// Respawns entities of the tiles changed since the last sync, e.g. right away when a saved game
+/// is resumed
+pub fn respawn_changed(
+    commands: &mut Commands,
+    texture: &Handle<TextureAtlas>,
+    tiles: &mut TileMap,
+    entities: &mut TileEntities,
+) {
+    let changed = std::mem::take(&mut tiles.changed);
+    for (x, y) in changed {
+        if let Some(entity) = entities.0[y * GRID_SIZE + x].take() {
+            commands.entity(entity).despawn();
+        }
+        let translation = grid2translation(x as i32, y as i32, 0.);
+        entities.0[y * GRID_SIZE + x] = match tiles.get(x, y) {
+            Tile::Empty => None,
+            Tile::Brick(cells) => Some(brick::spawn(commands, texture.clone(), translation, cells)),
+            Tile::Iron => Some(iron::spawn(commands, translation, texture.clone())),
+            Tile::River => Some(river::spawn(commands, translation, texture.clone())),
+            // grass is drawn above tanks
+            Tile::Grass => Some(grass::spawn(
+                commands,
+                translation + Vec3::Z,
+                texture.clone(),
+            )),
+            Tile::Snow => Some(snow::spawn(commands, translation, texture.clone())),
+        };
+    }
+}
+
+#[test]
+fn test_tile_map() {
+    let mut tiles = TileMap::default();
+    tiles.set_block(Vec2::ZERO, Tile::Brick(Cells::FULL));
+    let changed: Vec<(usize, usize)> = tiles.changed.iter().copied().collect();
+    assert_eq!(changed, vec![(12, 12), (12, 13), (13, 12), (13, 13)]);
+    assert_eq!(
+        TileMap::locate(block2translation(Vec2::ZERO, 0.) + Vec3::new(-1., 1., 0.)),
+        Some((12, 12))
+    );
+    assert_eq!(TileMap::locate(Vec3::new(0., 1000., 0.)), None);
+
+    // bricks are broken cells by cells
+    let version = tiles.version();
+    tiles.hit(12, 13, Direction::Up, false);
+    assert_eq!(
+        tiles.get(12, 13),
+        Tile::Brick(Cells::FULL.hit(Direction::Up))
+    );
+    tiles.hit(12, 13, Direction::Up, false);
+    assert_eq!(tiles.get(12, 13), Tile::Empty);
+    assert_eq!(tiles.version(), version + 2);
+
+    // iron only by bullets strong enough
+    tiles.set(0, 0, Tile::Iron);
+    tiles.hit(0, 0, Direction::Left, false);
+    assert_eq!(tiles.get(0, 0), Tile::Iron);
+    tiles.hit(0, 0, Direction::Left, true);
+    assert_eq!(tiles.get(0, 0), Tile::Empty);
+
+    // setting the same tile again is not a change
+    let version = tiles.version();
+    tiles.set(13, 13, Tile::Brick(Cells::FULL));
+    assert_eq!(tiles.version(), version);
+    assert_eq!(
+        tiles
+            .iter()
+            .filter(|(_, _, tile)| *tile != Tile::Empty)
+            .count(),
+        3
+    );
+}
@@ -21,6 +21,7 @@ impl SpriteIndex {
     pub const POWER_UP: [usize; 7] = [280, 281, 282, 283, 284, 285, 286];
     pub const EXPLOSION: [usize; 5] = [287, 288, 289, 290, 291];
     pub const SHIELD: [usize; 2] = [292, 293];
+    pub const DIGITS: [usize; 10] = [294, 295, 296, 297, 298, 299, 300, 301, 302, 303];
 }
 
 pub struct Textures {
@@ -29,6 +30,16 @@ pub struct Textures {
 
 pub fn load_texture_atlas(asset_server: Res<AssetServer>) -> TextureAtlas {
     let texture_handle = asset_server.load("General Sprites.png");
+    TextureAtlas {
+        size: Vec2::new(400., 256.),
+        textures: sprite_rects(),
+        texture: texture_handle,
+        texture_handles: None,
+    }
+}
+
+/// Position of every sprite in "General Sprites.png"
+fn sprite_rects() -> Vec<Rect> {
     let mut sprites = Vec::new();
     // load tank with different color in order: yellow, white, green, red, 16x16px
     // sprite index 0 ~ 255
@@ -182,10 +193,15 @@ pub fn load_texture_atlas(asset_server: Res<AssetServer>) -> TextureAtlas {
         })
     }
 
-    TextureAtlas {
-        size: Vec2::new(400., 256.),
-        textures: sprites,
-        texture: texture_handle,
-        texture_handles: None,
+    // load digits 0 ~ 9, 8x8px
+    // sprite index 294 ~ 303
+    for i in 0..10 {
+        let min = Vec2::new(328. + 8. * (i % 5) as f32, 184. + 8. * (i / 5) as f32);
+        sprites.push(Rect {
+            min,
+            max: min + Vec2::splat(8.),
+        })
     }
+
+    sprites
 }
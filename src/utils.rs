@@ -1,9 +1,12 @@
-use crate::consts::BLOCK_WIDTH;
+use crate::consts::{BATTLE_FIELD_WIDTH, BLOCK_WIDTH, HALF_BLOCK_WIDTH};
 use bevy::{
     math::{Vec2, Vec3},
-    prelude::Component,
+    prelude::{Color, Component},
 };
 
+/// Players a game has at most, local or networked
+pub const MAX_PLAYERS: usize = 4;
+
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum Direction {
     Left,
@@ -52,27 +55,37 @@ impl Direction {
     }
 }
 
-#[derive(Debug, PartialEq, Copy, Clone, Component)]
-pub enum Owner {
-    P1,
-    P2,
-    AI,
+/// Team of AI's tanks, players' teams count from 0
+pub const AI_TEAM: u8 = u8::MAX;
+
+/// Who a tank, a bullet or a star belongs to. Tanks of different teams are enemies, see
+/// `team::Teams` for how players are split into teams.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Component)]
+pub struct Owner {
+    pub team: u8,
+    pub player: Option<u8>, // player ID, 0 is P1, None for AI
 }
 
 impl Owner {
+    pub const AI: Owner = Owner {
+        team: AI_TEAM,
+        player: None,
+    };
+    /// P1 in every preset of `team::Teams`
+    pub const P1: Owner = Owner {
+        team: 0,
+        player: Some(0),
+    };
+
     pub fn is_enemy(self, other: Owner) -> bool {
-        match self {
-            Owner::P1 | Owner::P2 => other == Owner::AI,
-            Owner::AI => other != Owner::AI,
-        }
+        self.team != other.team
     }
 }
 
-#[derive(Component)]
-pub struct P1;
-#[derive(Component)]
-pub struct P2;
-#[derive(Component)]
+/// Marks tanks and bullets of a player
+#[derive(Component, Clone)]
+pub struct Player(pub u8);
+#[derive(Component, Clone)]
 pub struct AI;
 
 /// Dividing battle field into 13x13 blocks
@@ -82,18 +95,57 @@ pub fn block2translation(block: Vec2, z: f32) -> Vec3 {
     Vec3::new((block.x - 0.5) * BLOCK_WIDTH, block.y * BLOCK_WIDTH, z)
 }
 
+/// Dividing battle field into 26x26 quarter blocks
+/// Quarter block (0, 0) is the top left one, x grows to the right and y grows downwards
+pub const GRID_SIZE: usize = 26;
+const GRID_LEFT: f32 = -HALF_BLOCK_WIDTH - BATTLE_FIELD_WIDTH / 2.;
+const GRID_TOP: f32 = BATTLE_FIELD_WIDTH / 2.;
+
+/// Calculate which quarter block a translation is in, it can be outside of battle field
+pub fn translation2grid(translation: Vec3) -> (i32, i32) {
+    (
+        ((translation.x - GRID_LEFT) / HALF_BLOCK_WIDTH).floor() as i32,
+        ((GRID_TOP - translation.y) / HALF_BLOCK_WIDTH).floor() as i32,
+    )
+}
+
+/// Calculate the translation of a quarter block's center by its grid position
+pub fn grid2translation(x: i32, y: i32, z: f32) -> Vec3 {
+    Vec3::new(
+        GRID_LEFT + (x as f32 + 0.5) * HALF_BLOCK_WIDTH,
+        GRID_TOP - (y as f32 + 0.5) * HALF_BLOCK_WIDTH,
+        z,
+    )
+}
+
+/// Quarter blocks covered by a rectangle, the ones outside of battle field are left out
+pub fn grid_cells(translation: Vec3, size: Vec2) -> impl Iterator<Item = (usize, usize)> {
+    // shrink a little, so a rectangle exactly fits in quarter blocks does not touch the next one
+    let half = size / 2. - Vec2::splat(0.01);
+    let (x0, y0) = translation2grid(translation + Vec3::new(-half.x, half.y, 0.));
+    let (x1, y1) = translation2grid(translation + Vec3::new(half.x, -half.y, 0.));
+    let max = GRID_SIZE as i32 - 1;
+    (y0.max(0)..=y1.min(max))
+        .flat_map(move |y| (x0.max(0)..=x1.min(max)).map(move |x| (x as usize, y as usize)))
+}
+
+/// Players' tanks of level 0 facing up: yellow, green, and silver ones for P3 and P4, which are
+/// recoloured by `PLAYER_COLORS` so they do not look like AI's
+pub const PLAYER_SPRITES: [usize; MAX_PLAYERS] = [0, 128, 8, 8];
+pub const PLAYER_COLORS: [Color; MAX_PLAYERS] = [
+    Color::WHITE,
+    Color::WHITE,
+    Color::rgb(0.45, 0.65, 1.),
+    Color::rgb(1., 0.45, 0.45),
+];
+
+/// A tank's sprite in "General Sprites.png", every level is a row of 16 sprites
 pub fn get_sprite(owner: Owner, level: u8, direction: Direction) -> usize {
-    let mut base_index = match owner {
-        Owner::P1 => 0,
-        Owner::P2 => 128,
-        Owner::AI => 96,
-    };
-    base_index += match level {
-        0 => 0,
-        1 => 8,
-        2 => 16,
-        _ => 24,
+    let mut base_index = match owner.player {
+        Some(player) => PLAYER_SPRITES[player as usize],
+        None => 72,
     };
+    base_index += 16 * level.min(3) as usize;
     base_index
         + match direction {
             Direction::Up => 0,
@@ -110,3 +162,15 @@ fn test_block2translation() {
     let expected = Vec3::new(-0.5 * BLOCK_WIDTH, 0., 1.);
     assert_eq!(result, expected);
 }
+
+#[test]
+fn test_grid2translation() {
+    let result = grid2translation(0, 0, 0.);
+    let expected = Vec3::new(-13.5 * HALF_BLOCK_WIDTH, 12.5 * HALF_BLOCK_WIDTH, 0.);
+    assert_eq!(result, expected);
+    assert_eq!(translation2grid(result), (0, 0));
+    assert_eq!(
+        translation2grid(block2translation(Vec2::ZERO, 0.)),
+        (13, 13)
+    );
+}
@@ -0,0 +1,249 @@
+//! Versus: P1's team against P2's team, by default P1 and P3 against P2 and P4, see
+//! `team::Teams`, each team defending a base of its own.
+//!
+//! There are no AI tanks, and players' tanks come back as often as they are destroyed. A round
+//! is won by destroying the other team's base, or by destroying the other team's tanks
+//! `Rules::kills` times. The first team winning most of `Rules::rounds` rounds wins the match.
+//!
+//! Every round starts over from battle field as it was at the first tick of the first round,
+//! restored from a `rollback::Snapshot`.
+
+use std::sync::Arc;
+
+use bevy::{
+    app::{Events, ManualEventReader},
+    math::const_vec3,
+    prelude::*,
+};
+
+use crate::{
+    consts::BLOCK_WIDTH, event::TankDestroyed, rollback::Snapshot, simulation::SimulationStage,
+    tank::player, utils::Owner,
+};
+
+/// P2's base is at the top, across battle field from P1's one
+pub const P2_BASE_POSITION: Vec3 = const_vec3!([-0.5 * BLOCK_WIDTH, 6. * BLOCK_WIDTH, 0.]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rules {
+    pub rounds: u8, // best of
+    pub kills: u8,  // tanks to destroy to win a round
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            rounds: 3,
+            kills: 5,
+        }
+    }
+}
+
+pub struct VersusPlugin {
+    pub rules: Rules,
+}
+
+impl Plugin for VersusPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Match::new(self.rules))
+            // after every other system of the tick, and the commands they issued
+            .add_system_to_stage(SimulationStage, referee.exclusive_system().at_end())
+            .add_system(title);
+    }
+}
+
+/// Score of a versus match, by team
+pub struct Match {
+    pub rules: Rules,
+    pub wins: [u8; 2],            // rounds won by P1's team and P2's team
+    pub kills: [u8; 2],           // tanks of the other team destroyed in this round
+    pub bases_lost: [bool; 2],    // in this round
+    start: Option<Arc<Snapshot>>, // battle field when the match started
+    destroyed: ManualEventReader<TankDestroyed>,
+}
+
+/// A copy for a rollback snapshot, see `skip_destroyed`
+impl Clone for Match {
+    fn clone(&self) -> Self {
+        Self {
+            rules: self.rules,
+            wins: self.wins,
+            kills: self.kills,
+            bases_lost: self.bases_lost,
+            start: self.start.clone(),
+            destroyed: ManualEventReader::default(),
+        }
+    }
+}
+
+impl Match {
+    pub fn new(rules: Rules) -> Self {
+        Self {
+            rules,
+            wins: [0; 2],
+            kills: [0; 2],
+            bases_lost: [false; 2],
+            start: None,
+            destroyed: ManualEventReader::default(),
+        }
+    }
+
+    /// The team which won the match, None while it goes on
+    pub fn winner(&self) -> Option<usize> {
+        let needed = self.rules.rounds / 2 + 1;
+        (0..2).find(|team| self.wins[*team] >= needed)
+    }
+
+    /// Does not count tanks destroyed so far, e.g. those of the tick before a rollback snapshot,
+    /// which were counted before it was taken and are sent again when it is restored
+    pub fn skip_destroyed(&mut self, events: &Events<TankDestroyed>) {
+        self.destroyed.iter(events).for_each(drop);
+    }
+
+    /// The base at a position is destroyed
+    pub fn base_destroyed(&mut self, position: Vec3) {
+        let team = if position.y > 0. { 1 } else { 0 };
+        self.bases_lost[team] = true;
+    }
+
+    /// Counts a tank destroyed by the other team
+    fn tank_destroyed(&mut self, event: &TankDestroyed) {
+        if event.by.is_enemy(event.tank) {
+            self.kills[event.by.team as usize] += 1;
+        }
+    }
+
+    /// If P1's team and P2's team won the round, both at once is a draw
+    fn round_won(&self) -> [bool; 2] {
+        [
+            self.bases_lost[1] || self.kills[0] >= self.rules.kills,
+            self.bases_lost[0] || self.kills[1] >= self.rules.kills,
+        ]
+    }
+}
+
+/// Where a player's tank comes onto battle field, P2's team at the top
+pub fn spawn_position(owner: Owner) -> Vec3 {
+    let position = player::SPAWN_POSITIONS[owner.player.unwrap() as usize];
+    match owner.team {
+        0 => position,
+        _ => Vec3::new(position.x, -position.y, position.z),
+    }
+}
+
+/// Counts kills, and ends a round once it is won by starting the next one over
+pub fn referee(world: &mut World) {
+    world.resource_scope(|world, mut versus: Mut<Match>| {
+        let versus = &mut *versus;
+        if versus.winner().is_some() {
+            return;
+        }
+        if versus.start.is_none() {
+            let snapshot = Snapshot::take(world, 0, &mut ManualEventReader::default());
+            versus.start = Some(Arc::new(snapshot));
+        }
+        let events = world.get_resource::<Events<TankDestroyed>>().unwrap();
+        let destroyed: Vec<TankDestroyed> = versus.destroyed.iter(events).cloned().collect();
+        for event in &destroyed {
+            versus.tank_destroyed(event);
+        }
+
+        match versus.round_won() {
+            [false, false] => return,
+            [true, false] => versus.wins[0] += 1,
+            [false, true] => versus.wins[1] += 1,
+            [true, true] => (), // a draw is played again
+        }
+        if versus.winner().is_some() {
+            return; // the last round stays on battle field
+        }
+        versus.kills = [0; 2];
+        versus.bases_lost = [false; 2];
+        versus.destroyed = ManualEventReader::default();
+        versus.start.as_ref().unwrap().restore(world);
+    });
+}
+
+/// Shows the score of the match in the window's title
+pub fn title(versus: Res<Match>, windows: Option<ResMut<Windows>>) {
+    // no window when headless
+    let mut windows = match windows {
+        Some(windows) => windows,
+        None => return,
+    };
+    let window = match windows.get_primary_mut() {
+        Some(window) => window,
+        None => return,
+    };
+    let mut title = format!(
+        "Battle City - P1 {} : {} P2 - kills {} : {}",
+        versus.wins[0], versus.wins[1], versus.kills[0], versus.kills[1]
+    );
+    if let Some(winner) = versus.winner() {
+        title += &format!(" - P{}'s team wins", winner + 1);
+    }
+    if window.title() != title {
+        window.set_title(title);
+    }
+}
+
+#[test]
+fn test_match() {
+    use crate::{
+        base::Base,
+        config::Difficulty,
+        game_data,
+        input::InputSource,
+        team::{FriendlyFire, Teams},
+        BattleCityPlugins,
+    };
+
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugins(BattleCityPlugins {
+            headless: true,
+            seed: 3,
+            difficulty: Difficulty::default(),
+            input: InputSource::External,
+            ally: false,
+            save: None,
+            players: 2,
+            teams: Teams::VERSUS,
+            friendly_fire: FriendlyFire::Off,
+            survival: None,
+            versus: Some(Rules {
+                rounds: 3,
+                kills: 1,
+            }),
+        });
+    for _ in 0..10 {
+        app.update();
+    }
+    let mut bases = app.world.query_filtered::<Entity, With<Base>>();
+    assert_eq!(bases.iter(&app.world).count(), 2);
+
+    // P1 destroys P2's base, and the next round starts over
+    let mut versus = app.world.get_resource_mut::<Match>().unwrap();
+    versus.base_destroyed(P2_BASE_POSITION);
+    app.update();
+    let versus = app.world.get_resource::<Match>().unwrap();
+    assert_eq!(versus.wins, [1, 0]);
+    assert_eq!(versus.bases_lost, [false; 2]);
+    assert!(!game_data::is_over(&mut app.world));
+
+    // P1 destroys P2's tank, which is enough to win a round and the match
+    app.world
+        .get_resource_mut::<Events<TankDestroyed>>()
+        .unwrap()
+        .send(TankDestroyed {
+            tank: Teams::VERSUS.owner(1),
+            level: 0,
+            bonus: false,
+            by: Teams::VERSUS.owner(0),
+        });
+    app.update();
+    let versus = app.world.get_resource::<Match>().unwrap();
+    assert_eq!(versus.wins, [2, 0]);
+    assert_eq!(versus.winner(), Some(0));
+    assert!(game_data::is_over(&mut app.world));
+}